@@ -0,0 +1,332 @@
+use crate::facts::Facts;
+use anyhow::{Result, bail};
+use toml::map::Map;
+use toml::Value;
+
+/// A parsed `when` expression, e.g. `os == "linux" && command("sway")`.
+///
+/// Grammar, loosest binding first:
+///
+/// ```text
+/// expr    = and ( "||" and )*
+/// and     = unary ( "&&" unary )*
+/// unary   = "!" unary | "(" expr ")" | "command(" string ")" | fact [ ("==" | "!=") literal ]
+/// fact    = "os" | "arch" | "distro" | "hostname" | "env.NAME" | "vars.dotted.path"
+/// literal = "string" | 'string' | word
+/// ```
+///
+/// A fact on its own is true when it is set (and, for vars, not `false`, `0` or empty).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Or(Box<Condition>, Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Not(Box<Condition>),
+    /// An executable with this name is on `PATH`
+    Command(String),
+    IsSet(Fact),
+    Equals(Fact, String),
+    NotEquals(Fact, String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fact {
+    Os,
+    Arch,
+    Distro,
+    Hostname,
+    Env(String),
+    Var(Vec<String>),
+}
+
+impl Condition {
+    pub fn parse(expr: &str) -> Result<Self> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let condition = parser.or()?;
+        if let Some(token) = parser.peek() {
+            bail!("unexpected {} in condition '{expr}'", token.describe());
+        }
+        Ok(condition)
+    }
+
+    /// Evaluate against the machine's facts and the merged role/host vars.
+    pub fn eval(&self, facts: &Facts, vars: &Map<String, Value>) -> bool {
+        match self {
+            Condition::Or(a, b) => a.eval(facts, vars) || b.eval(facts, vars),
+            Condition::And(a, b) => a.eval(facts, vars) && b.eval(facts, vars),
+            Condition::Not(inner) => !inner.eval(facts, vars),
+            Condition::Command(name) => facts.has_command(name),
+            Condition::IsSet(fact) => fact.value(facts, vars).is_some_and(|v| is_truthy(&v)),
+            Condition::Equals(fact, expected) => fact.value(facts, vars).as_deref() == Some(expected.as_str()),
+            Condition::NotEquals(fact, expected) => fact.value(facts, vars).as_deref() != Some(expected.as_str()),
+        }
+    }
+}
+
+impl Fact {
+    fn value(&self, facts: &Facts, vars: &Map<String, Value>) -> Option<String> {
+        match self {
+            Fact::Os => Some(facts.os.clone()),
+            Fact::Arch => Some(facts.arch.clone()),
+            Fact::Distro => facts.distro.clone(),
+            Fact::Hostname => Some(facts.hostname.clone()),
+            Fact::Env(name) => facts.env.get(name).cloned(),
+            Fact::Var(path) => {
+                let (first, rest) = path.split_first()?;
+                let mut value = vars.get(first)?;
+                for key in rest {
+                    value = value.as_table()?.get(key)?;
+                }
+                match value {
+                    Value::String(s) => Some(s.clone()),
+                    Value::Table(_) | Value::Array(_) => Some("true".to_string()),
+                    other => Some(other.to_string()),
+                }
+            }
+        }
+    }
+}
+
+fn is_truthy(value: &str) -> bool {
+    !matches!(value, "" | "false" | "0")
+}
+
+/// Evaluate an optional `when` expression; a missing condition is always true.
+pub fn evaluate(when: Option<&str>, facts: &Facts, vars: &Map<String, Value>) -> Result<bool> {
+    match when {
+        Some(expr) => Ok(Condition::parse(expr)?.eval(facts, vars)),
+        None => Ok(true),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Eq,
+    Ne,
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(w) => format!("'{w}'"),
+            Token::Str(s) => format!("\"{s}\""),
+            Token::Eq => "'=='".to_string(),
+            Token::Ne => "'!='".to_string(),
+            Token::And => "'&&'".to_string(),
+            Token::Or => "'||'".to_string(),
+            Token::Not => "'!'".to_string(),
+            Token::LParen => "'('".to_string(),
+            Token::RParen => "')'".to_string(),
+        }
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '=' | '!' | '&' | '|' => {
+                chars.next();
+                let token = match (c, chars.peek()) {
+                    ('=', Some('=')) => Token::Eq,
+                    ('!', Some('=')) => Token::Ne,
+                    ('&', Some('&')) => Token::And,
+                    ('|', Some('|')) => Token::Or,
+                    ('!', _) => {
+                        tokens.push(Token::Not);
+                        continue;
+                    }
+                    _ => bail!("unexpected '{c}' in condition '{expr}'"),
+                };
+                chars.next();
+                tokens.push(token);
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(ch) if ch == c => break,
+                        Some(ch) => value.push(ch),
+                        None => bail!("unterminated string in condition '{expr}'"),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = String::new();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_alphanumeric() || matches!(ch, '_' | '-' | '.') {
+                        word.push(ch);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+            _ => bail!("unexpected '{c}' in condition '{expr}'"),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => bail!("expected {} but found {}", expected.describe(), token.describe()),
+            None => bail!("expected {} but the condition ended", expected.describe()),
+        }
+    }
+
+    fn or(&mut self) -> Result<Condition> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            left = Condition::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Condition> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            left = Condition::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Condition> {
+        match self.next() {
+            Some(Token::Not) => Ok(Condition::Not(Box::new(self.unary()?))),
+            Some(Token::LParen) => {
+                let inner = self.or()?;
+                self.expect(Token::RParen)?;
+                Ok(inner)
+            }
+            Some(Token::Word(word)) if word == "command" && self.peek() == Some(&Token::LParen) => {
+                self.next();
+                let name = match self.next() {
+                    Some(Token::Str(name)) if !name.is_empty() => name,
+                    _ => bail!("command() takes a quoted command name"),
+                };
+                self.expect(Token::RParen)?;
+                Ok(Condition::Command(name))
+            }
+            Some(Token::Word(word)) => {
+                let fact = parse_fact(&word)?;
+                match self.peek() {
+                    Some(Token::Eq) => {
+                        self.next();
+                        Ok(Condition::Equals(fact, self.literal()?))
+                    }
+                    Some(Token::Ne) => {
+                        self.next();
+                        Ok(Condition::NotEquals(fact, self.literal()?))
+                    }
+                    _ => Ok(Condition::IsSet(fact)),
+                }
+            }
+            Some(token) => bail!("unexpected {} in condition", token.describe()),
+            None => bail!("condition is empty or incomplete"),
+        }
+    }
+
+    fn literal(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Str(value)) | Some(Token::Word(value)) => Ok(value),
+            Some(token) => bail!("expected a value but found {}", token.describe()),
+            None => bail!("expected a value but the condition ended"),
+        }
+    }
+}
+
+fn parse_fact(word: &str) -> Result<Fact> {
+    let fact = match word {
+        "os" => Fact::Os,
+        "arch" => Fact::Arch,
+        "distro" => Fact::Distro,
+        "hostname" => Fact::Hostname,
+        _ => {
+            if let Some(name) = word.strip_prefix("env.")
+                && !name.is_empty()
+            {
+                Fact::Env(name.to_string())
+            } else if let Some(path) = word.strip_prefix("vars.")
+                && !path.is_empty()
+            {
+                Fact::Var(path.split('.').map(String::from).collect())
+            } else {
+                bail!(
+                    "unknown fact '{word}' (expected os, arch, distro, hostname, env.NAME, vars.KEY or command(\"name\"))"
+                );
+            }
+        }
+    };
+    Ok(fact)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_respects_precedence() {
+        let condition = Condition::parse("os == linux || !distro && command('sway')").unwrap();
+        assert_eq!(
+            condition,
+            Condition::Or(
+                Box::new(Condition::Equals(Fact::Os, "linux".into())),
+                Box::new(Condition::And(
+                    Box::new(Condition::Not(Box::new(Condition::IsSet(Fact::Distro)))),
+                    Box::new(Condition::Command("sway".into())),
+                )),
+            )
+        );
+    }
+
+    #[test]
+    fn parse_rejects_unknown_fact_and_trailing_tokens() {
+        assert!(Condition::parse("kernel == 6").is_err());
+        assert!(Condition::parse("os == linux arch").is_err());
+        assert!(Condition::parse("(os == linux").is_err());
+        assert!(Condition::parse("").is_err());
+    }
+}
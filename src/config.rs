@@ -1,5 +1,7 @@
+use anyhow::{Result, bail};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path};
 use toml::map::Map;
 use toml::Value;
 
@@ -17,17 +19,90 @@ pub struct DotmSettings {
     pub packages_dir: String,
     #[serde(default)]
     pub auto_prune: bool,
+    /// Garbage-collect the content store after every deploy
+    #[serde(default)]
+    pub auto_gc: bool,
+    /// Number of deploy generations kept by garbage collection (all if unset)
+    pub keep_generations: Option<usize>,
+    pub secrets: Option<SecretsConfig>,
+    #[serde(default)]
+    pub encryption: EncryptionConfig,
+    /// Defaults for every host, beneath package, role and host vars
+    #[serde(default)]
+    pub vars: Map<String, Value>,
+    /// Deploy `dot-` prefixed package files with a leading `.` in every package
+    #[serde(default)]
+    pub dot_prefix: bool,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretsBackend {
+    File,
+    Command,
+}
+
+/// Where `secret()` calls in templates get their values from.
+#[derive(Debug, Deserialize)]
+pub struct SecretsConfig {
+    pub backend: SecretsBackend,
+    /// Encrypted TOML file (file backend), relative to the dotfiles directory
+    pub file: Option<String>,
+    /// age identity used to decrypt `.age` files
+    pub identity: Option<String>,
+    /// Command that prints the decrypted file to stdout (overrides age/gpg detection)
+    pub decrypt_command: Option<String>,
+    /// Command that prints a single secret (command backend), e.g. "pass show {path}"
+    pub command: Option<String>,
 }
 
 fn default_packages_dir() -> String {
     "packages".to_string()
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq, Clone, Copy)]
+/// How encrypted (`.age`) package files are decrypted at deploy time.
+#[derive(Debug, Default, Deserialize)]
+pub struct EncryptionConfig {
+    /// age identity file used to decrypt `.age` files
+    pub identity: Option<String>,
+    /// Command that prints a decrypted file to stdout (overrides the age default)
+    pub decrypt_command: Option<String>,
+}
+
+#[derive(Debug, Deserialize, serde::Serialize, PartialEq, Eq, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum DeployStrategy {
     Stage,
     Copy,
+    /// Symlink base files straight to the source in `packages/`
+    Link,
+}
+
+/// Which directories of a package are deployed as a single directory symlink.
+///
+/// `fold = true` folds every directory that doesn't already exist as a real
+/// directory in the target (like GNU stow); `fold = [".config/nvim"]` folds
+/// only the listed paths.
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum FoldConfig {
+    All(bool),
+    Paths(Vec<String>),
+}
+
+impl Default for FoldConfig {
+    fn default() -> Self {
+        FoldConfig::All(false)
+    }
+}
+
+impl FoldConfig {
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            FoldConfig::All(all) => *all,
+            FoldConfig::Paths(paths) => !paths.is_empty(),
+        }
+    }
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -49,12 +124,101 @@ pub struct PackageConfig {
     pub ownership: HashMap<String, String>,
     #[serde(default)]
     pub preserve: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub fold: FoldConfig,
+    /// Files never deployed, in gitignore syntax, after the `.dotmignore` files
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// Overrides `[dotm] dot_prefix` for this package
+    pub dot_prefix: Option<bool>,
+    /// Target paths for package files deployed under another name
+    #[serde(default)]
+    pub rename: HashMap<String, String>,
+    /// Condition on the machine's facts; the package is skipped when it is false
+    pub when: Option<String>,
+    /// Defaults for this package's templates only, beneath role and host vars
+    #[serde(default)]
+    pub vars: Map<String, Value>,
+    /// The vars this package's templates use, by dotted path
+    #[serde(default)]
+    pub schema: BTreeMap<String, VarSchema>,
     pub pre_deploy: Option<String>,
     pub post_deploy: Option<String>,
     pub pre_undeploy: Option<String>,
     pub post_undeploy: Option<String>,
 }
 
+/// Declaration of a var a package's templates use, validated by `dotm check`.
+#[derive(Debug, Default, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VarSchema {
+    #[serde(rename = "type")]
+    pub var_type: Option<VarType>,
+    /// Every host deploying the package must set the var (unless it has a default)
+    #[serde(default)]
+    pub required: bool,
+    /// Used when no role or host sets the var, like a package default
+    pub default: Option<Value>,
+    pub description: Option<String>,
+    /// The only values the var may take
+    #[serde(default)]
+    pub allowed: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VarType {
+    String,
+    Integer,
+    /// Integers are accepted too
+    Float,
+    Boolean,
+    Array,
+    Table,
+}
+
+impl VarType {
+    pub fn matches(self, value: &Value) -> bool {
+        matches!(
+            (self, value),
+            (VarType::String, Value::String(_))
+                | (VarType::Integer, Value::Integer(_))
+                | (VarType::Float, Value::Float(_) | Value::Integer(_))
+                | (VarType::Boolean, Value::Boolean(_))
+                | (VarType::Array, Value::Array(_))
+                | (VarType::Table, Value::Table(_))
+        )
+    }
+}
+
+impl std::fmt::Display for VarType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            VarType::String => "string",
+            VarType::Integer => "integer",
+            VarType::Float => "float",
+            VarType::Boolean => "boolean",
+            VarType::Array => "array",
+            VarType::Table => "table",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Whether `path` is relative and stays inside the directory it is joined to.
+pub fn is_contained_path(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().next().is_some() && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Folded directories are symlinked, so a folding package can't use the copy strategy.
+pub fn validate_fold(name: &str, pkg: &PackageConfig) -> Result<()> {
+    if pkg.fold.is_enabled() && pkg.strategy == Some(DeployStrategy::Copy) {
+        bail!("package '{name}': fold requires the stage or link strategy");
+    }
+    Ok(())
+}
+
 pub fn validate_system_packages(root: &RootConfig) -> Vec<String> {
     let mut errors = Vec::new();
     for (name, pkg) in &root.packages {
@@ -118,7 +282,14 @@ pub fn validate_system_packages(root: &RootConfig) -> Vec<String> {
 
 #[derive(Debug, Deserialize)]
 pub struct HostConfig {
+    #[serde(default)]
     pub hostname: String,
+    /// Hostnames this file applies to: a glob (`ci-*`), or a regex between slashes (`/^ci-\d+$/`)
+    #[serde(rename = "match")]
+    pub host_match: Option<String>,
+    /// Breaks ties when several patterns match a hostname; the highest wins
+    #[serde(default)]
+    pub priority: i64,
     pub roles: Vec<String>,
     #[serde(default)]
     pub vars: Map<String, Value>,
@@ -126,7 +297,47 @@ pub struct HostConfig {
 
 #[derive(Debug, Deserialize)]
 pub struct RoleConfig {
-    pub packages: Vec<String>,
+    /// Other roles whose packages and vars this role builds on
+    #[serde(default)]
+    pub includes: Vec<String>,
+    #[serde(default)]
+    pub packages: Vec<RolePackage>,
     #[serde(default)]
     pub vars: Map<String, Value>,
 }
+
+/// A package listed by a role: either a plain name or
+/// `{ name = "sway", when = "command('sway')" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, serde::Serialize)]
+#[serde(from = "RolePackageDef")]
+pub struct RolePackage {
+    pub name: String,
+    /// Condition under which the role selects the package
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RolePackageDef {
+    Name(String),
+    Conditional { name: String, when: Option<String> },
+}
+
+impl From<RolePackageDef> for RolePackage {
+    fn from(def: RolePackageDef) -> Self {
+        match def {
+            RolePackageDef::Name(name) => RolePackage { name, when: None },
+            RolePackageDef::Conditional { name, when } => RolePackage { name, when },
+        }
+    }
+}
+
+impl std::fmt::Display for RolePackage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.when {
+            Some(ref when) => write!(f, "{} (when {when})", self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
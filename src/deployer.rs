@@ -1,6 +1,7 @@
 use crate::scanner::{EntryKind, FileAction};
-use anyhow::{Context, Result};
-use std::os::unix::fs::PermissionsExt;
+use anyhow::{bail, Context, Result};
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
 use std::path::Path;
 
 #[derive(Debug)]
@@ -12,10 +13,28 @@ pub enum DeployResult {
     DryRun,
 }
 
+/// Why deploying to `target_path` would conflict with what is already there: a
+/// real directory (never removed, even with `force`) or, without `force`, a file
+/// dotm doesn't manage. Existing symlinks are always replaced.
+pub fn target_conflict(target_path: &Path, force: bool) -> Option<String> {
+    let metadata = std::fs::symlink_metadata(target_path).ok()?;
+    if metadata.is_dir() {
+        Some(format!("directory already exists and is not managed by dotm: {}", target_path.display()))
+    } else if !metadata.file_type().is_symlink() && !force {
+        Some(format!("file already exists and is not managed by dotm: {}", target_path.display()))
+    } else {
+        None
+    }
+}
+
 /// Deploy a file action via staging: copy/render the real file into `staging_dir`,
 /// then create a symlink from `target_dir` pointing to the staged file.
 ///
-/// For all entry kinds (Base, Override, Template), the staged file is a real file.
+/// For all entry kinds, the staged file is a real file. Templates and encrypted
+/// files are written from `rendered_content` (rendered or decrypted output);
+/// encrypted files, and templates with `private` set because they use secrets,
+/// are created with mode 600.
+/// Folded directories are staged as a copy of the whole tree.
 /// The target path is always a symlink to the staged file's canonical path.
 pub fn deploy_staged(
     action: &FileAction,
@@ -23,7 +42,8 @@ pub fn deploy_staged(
     target_dir: &Path,
     dry_run: bool,
     force: bool,
-    rendered_content: Option<&str>,
+    rendered_content: Option<&[u8]>,
+    private: bool,
 ) -> Result<DeployResult> {
     let staged_path = staging_dir.join(&action.target_rel_path);
     let target_path = target_dir.join(&action.target_rel_path);
@@ -36,19 +56,15 @@ pub fn deploy_staged(
     let was_existing = target_path.is_symlink() || target_path.exists();
 
     // Handle conflicts on the target path
-    if target_path.exists() || target_path.is_symlink() {
-        if target_path.is_symlink() {
-            std::fs::remove_file(&target_path)
-                .with_context(|| format!("failed to remove existing symlink: {}", target_path.display()))?;
-        } else if force {
-            std::fs::remove_file(&target_path)
-                .with_context(|| format!("failed to remove existing file: {}", target_path.display()))?;
-        } else {
-            return Ok(DeployResult::Conflict(format!(
-                "file already exists and is not managed by dotm: {}",
-                target_path.display()
-            )));
-        }
+    if let Some(reason) = target_conflict(&target_path, force) {
+        return Ok(DeployResult::Conflict(reason));
+    }
+    if target_path.is_symlink() {
+        std::fs::remove_file(&target_path)
+            .with_context(|| format!("failed to remove existing symlink: {}", target_path.display()))?;
+    } else if target_path.exists() {
+        std::fs::remove_file(&target_path)
+            .with_context(|| format!("failed to remove existing file: {}", target_path.display()))?;
     }
 
     // Create parent directories for both staged and target paths
@@ -63,16 +79,31 @@ pub fn deploy_staged(
 
     // Stage the file (always a real file in staging_dir)
     match action.kind {
+        EntryKind::Template if private => {
+            let content = rendered_content.unwrap_or_default();
+            write_private(&staged_path, content)
+                .with_context(|| format!("failed to write template to staging: {}", staged_path.display()))?;
+        }
         EntryKind::Template => {
-            let content = rendered_content.unwrap_or("");
+            let content = rendered_content.unwrap_or_default();
             std::fs::write(&staged_path, content)
                 .with_context(|| format!("failed to write template to staging: {}", staged_path.display()))?;
         }
+        EntryKind::Encrypted => {
+            let content = rendered_content.unwrap_or_default();
+            write_private(&staged_path, content)
+                .with_context(|| format!("failed to write decrypted file to staging: {}", staged_path.display()))?;
+        }
         EntryKind::Base | EntryKind::Override => {
             std::fs::copy(&action.source, &staged_path)
                 .with_context(|| format!("failed to copy {} to staging: {}", action.source.display(), staged_path.display()))?;
             copy_permissions(&action.source, &staged_path)?;
         }
+        EntryKind::Directory => {
+            crate::state::remove_managed(&staged_path)
+                .with_context(|| format!("failed to clear staged directory: {}", staged_path.display()))?;
+            copy_tree(&action.source, &staged_path)?;
+        }
     }
 
     // Symlink from target to the staged file's canonical path
@@ -88,19 +119,84 @@ pub fn deploy_staged(
     }
 }
 
+/// Deploy a file action by symlinking the target straight to its source in the
+/// package directory (no staging), so edits at the target edit the repo file.
+///
+/// Used for base files and folded directories of packages with `strategy = "link"`.
+pub fn deploy_link(
+    action: &FileAction,
+    target_dir: &Path,
+    dry_run: bool,
+    force: bool,
+) -> Result<DeployResult> {
+    let target_path = target_dir.join(&action.target_rel_path);
+
+    if !matches!(action.kind, EntryKind::Base | EntryKind::Directory) {
+        bail!(
+            "cannot link {}: only plain files and folded directories are linked",
+            action.target_rel_path.display()
+        );
+    }
+
+    if dry_run {
+        return Ok(DeployResult::DryRun);
+    }
+
+    let abs_source = std::fs::canonicalize(&action.source)
+        .with_context(|| format!("failed to canonicalize source path: {}", action.source.display()))?;
+
+    // Check if the target already exists before removing
+    let was_existing = target_path.is_symlink() || target_path.exists();
+
+    if let Some(reason) = target_conflict(&target_path, force) {
+        return Ok(DeployResult::Conflict(reason));
+    }
+    if target_path.is_symlink() {
+        std::fs::remove_file(&target_path)
+            .with_context(|| format!("failed to remove existing symlink: {}", target_path.display()))?;
+    } else if target_path.exists() {
+        std::fs::remove_file(&target_path)
+            .with_context(|| format!("failed to remove existing file: {}", target_path.display()))?;
+    }
+
+    if let Some(parent) = target_path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create target directory: {}", parent.display()))?;
+    }
+
+    std::os::unix::fs::symlink(&abs_source, &target_path)
+        .with_context(|| format!("failed to create symlink: {} -> {}", target_path.display(), abs_source.display()))?;
+
+    if was_existing {
+        Ok(DeployResult::Updated)
+    } else {
+        Ok(DeployResult::Created)
+    }
+}
+
 /// Deploy a file action by copying directly to the target directory (no staging).
 ///
-/// Used for packages with `strategy = "copy"`. Templates get rendered content
-/// written; everything else is copied. Source permissions are preserved.
+/// Used for packages with `strategy = "copy"` and for encrypted files. Templates get
+/// rendered content written (with mode 600 if `private`), encrypted files get their
+/// decrypted content written with mode 600; everything else is copied. Source
+/// permissions are preserved.
 pub fn deploy_copy(
     action: &FileAction,
     target_dir: &Path,
     dry_run: bool,
     force: bool,
-    rendered_content: Option<&str>,
+    rendered_content: Option<&[u8]>,
+    private: bool,
 ) -> Result<DeployResult> {
     let target_path = target_dir.join(&action.target_rel_path);
 
+    if action.kind == EntryKind::Directory {
+        bail!(
+            "cannot deploy folded directory {} with the copy strategy",
+            action.target_rel_path.display()
+        );
+    }
+
     if dry_run {
         return Ok(DeployResult::DryRun);
     }
@@ -109,19 +205,15 @@ pub fn deploy_copy(
     let was_existing = target_path.is_symlink() || target_path.exists();
 
     // Handle conflicts on the target path
-    if target_path.exists() || target_path.is_symlink() {
-        if target_path.is_symlink() {
-            std::fs::remove_file(&target_path)
-                .with_context(|| format!("failed to remove existing symlink: {}", target_path.display()))?;
-        } else if force {
-            std::fs::remove_file(&target_path)
-                .with_context(|| format!("failed to remove existing file: {}", target_path.display()))?;
-        } else {
-            return Ok(DeployResult::Conflict(format!(
-                "file already exists and is not managed by dotm: {}",
-                target_path.display()
-            )));
-        }
+    if let Some(reason) = target_conflict(&target_path, force) {
+        return Ok(DeployResult::Conflict(reason));
+    }
+    if target_path.is_symlink() {
+        std::fs::remove_file(&target_path)
+            .with_context(|| format!("failed to remove existing symlink: {}", target_path.display()))?;
+    } else if target_path.exists() {
+        std::fs::remove_file(&target_path)
+            .with_context(|| format!("failed to remove existing file: {}", target_path.display()))?;
     }
 
     // Create parent directories
@@ -131,16 +223,27 @@ pub fn deploy_copy(
     }
 
     match action.kind {
+        EntryKind::Template if private => {
+            let content = rendered_content.unwrap_or_default();
+            write_private(&target_path, content)
+                .with_context(|| format!("failed to write template output: {}", target_path.display()))?;
+        }
         EntryKind::Template => {
-            let content = rendered_content.unwrap_or("");
+            let content = rendered_content.unwrap_or_default();
             std::fs::write(&target_path, content)
                 .with_context(|| format!("failed to write template output: {}", target_path.display()))?;
         }
+        EntryKind::Encrypted => {
+            let content = rendered_content.unwrap_or_default();
+            write_private(&target_path, content)
+                .with_context(|| format!("failed to write decrypted file: {}", target_path.display()))?;
+        }
         EntryKind::Base | EntryKind::Override => {
             std::fs::copy(&action.source, &target_path)
                 .with_context(|| format!("failed to copy {} to {}", action.source.display(), target_path.display()))?;
             copy_permissions(&action.source, &target_path)?;
         }
+        EntryKind::Directory => unreachable!("folded directories are rejected above"),
     }
 
     if was_existing {
@@ -160,6 +263,47 @@ pub fn apply_permission_override(path: &Path, mode_str: &str) -> Result<()> {
     Ok(())
 }
 
+/// Write `content` to a new file that is created with mode 600, so decrypted
+/// content is never readable by other users, even briefly.
+fn write_private(path: &Path, content: &[u8]) -> Result<()> {
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(content)?;
+    Ok(())
+}
+
+/// Recursively copy a directory tree, preserving permissions and symlinks.
+fn copy_tree(source: &Path, dest: &Path) -> Result<()> {
+    std::fs::create_dir_all(dest)
+        .with_context(|| format!("failed to create directory: {}", dest.display()))?;
+    copy_permissions(source, dest)?;
+    for entry in std::fs::read_dir(source)
+        .with_context(|| format!("failed to read directory: {}", source.display()))?
+    {
+        let path = entry?.path();
+        let dest_path = dest.join(path.file_name().expect("directory entry has no filename"));
+        if path.is_symlink() {
+            let link = std::fs::read_link(&path)?;
+            std::os::unix::fs::symlink(&link, &dest_path)
+                .with_context(|| format!("failed to create symlink: {}", dest_path.display()))?;
+        } else if path.is_dir() {
+            copy_tree(&path, &dest_path)?;
+        } else {
+            std::fs::copy(&path, &dest_path)
+                .with_context(|| format!("failed to copy {} to {}", path.display(), dest_path.display()))?;
+            copy_permissions(&path, &dest_path)?;
+        }
+    }
+    Ok(())
+}
+
 /// Copy the Unix file permissions from `source` to `dest`.
 fn copy_permissions(source: &Path, dest: &Path) -> Result<()> {
     let metadata = std::fs::metadata(source)
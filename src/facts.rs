@@ -0,0 +1,142 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+/// Facts about the machine dotm is running on, used to evaluate package
+/// conditions and exposed to templates as `dotm.*`.
+///
+/// [`Facts::gather`] reads them from the running system; tests can build one
+/// directly to pretend to be another machine.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Facts {
+    /// Operating system, e.g. `linux` or `macos`
+    pub os: String,
+    /// CPU architecture, e.g. `x86_64` or `aarch64`
+    pub arch: String,
+    /// `ID` from `/etc/os-release`, e.g. `arch` or `fedora`
+    pub distro: Option<String>,
+    /// `PRETTY_NAME` (or `NAME`) from `/etc/os-release`
+    pub distro_name: Option<String>,
+    /// `VERSION_ID` from `/etc/os-release`
+    pub distro_version: Option<String>,
+    pub hostname: String,
+    pub username: String,
+    pub uid: u32,
+    pub home: Option<PathBuf>,
+    /// Kernel release, e.g. `6.9.3-arch1-1`
+    pub kernel: Option<String>,
+    /// Number of logical CPUs
+    pub cpus: usize,
+    /// Total memory in bytes
+    pub memory: Option<u64>,
+    /// Login shell
+    pub shell: Option<String>,
+    /// Environment variables; `PATH` is also used to look up commands
+    #[serde(skip)]
+    pub env: HashMap<String, String>,
+}
+
+impl Facts {
+    pub fn gather() -> Self {
+        let os_release = std::fs::read_to_string("/etc/os-release")
+            .map(|content| parse_os_release(&content))
+            .unwrap_or_default();
+        let uid = nix::unistd::getuid();
+        let user = nix::unistd::User::from_uid(uid).ok().flatten();
+        let env: HashMap<String, String> = std::env::vars().collect();
+
+        Self {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            distro: os_release.get("ID").cloned(),
+            distro_name: os_release
+                .get("PRETTY_NAME")
+                .or_else(|| os_release.get("NAME"))
+                .cloned(),
+            distro_version: os_release.get("VERSION_ID").cloned(),
+            hostname: hostname::get()
+                .map(|h| h.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            username: user
+                .as_ref()
+                .map(|u| u.name.clone())
+                .or_else(|| env.get("USER").cloned())
+                .unwrap_or_default(),
+            uid: uid.as_raw(),
+            home: dirs::home_dir(),
+            kernel: std::fs::read_to_string("/proc/sys/kernel/osrelease")
+                .ok()
+                .map(|k| k.trim().to_string()),
+            cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            memory: std::fs::read_to_string("/proc/meminfo")
+                .ok()
+                .and_then(|content| parse_mem_total(&content)),
+            shell: env
+                .get("SHELL")
+                .cloned()
+                .or_else(|| user.map(|u| u.shell.to_string_lossy().to_string())),
+            env,
+        }
+    }
+
+    /// Whether an executable named `name` is on `PATH`.
+    pub fn has_command(&self, name: &str) -> bool {
+        self.env.get("PATH").is_some_and(|path| find_command(path, name))
+    }
+}
+
+/// Whether an executable named `name` is in one of the directories of `path`.
+pub fn find_command(path: &str, name: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::env::split_paths(path).any(|dir| {
+        let candidate = dir.join(name);
+        candidate.is_file()
+            && std::fs::metadata(&candidate).is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+    })
+}
+
+/// Parse the `KEY=value` lines of an os-release file, unquoting values.
+pub fn parse_os_release(content: &str) -> HashMap<String, String> {
+    content
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| {
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(value);
+            (key.trim().to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// Total memory in bytes from the `MemTotal:` line of `/proc/meminfo`.
+fn parse_mem_total(content: &str) -> Option<u64> {
+    let line = content.lines().find(|l| l.starts_with("MemTotal:"))?;
+    let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kib * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_os_release_unquotes_values() {
+        let release = parse_os_release("NAME=\"Arch Linux\"\nID=arch\n# comment\nVERSION_ID='1'\n");
+        assert_eq!(release["NAME"], "Arch Linux");
+        assert_eq!(release["ID"], "arch");
+        assert_eq!(release["VERSION_ID"], "1");
+    }
+
+    #[test]
+    fn parse_mem_total_in_bytes() {
+        let meminfo = "MemTotal:       16302452 kB\nMemFree:         1203844 kB\n";
+        assert_eq!(parse_mem_total(meminfo), Some(16_302_452 * 1024));
+        assert_eq!(parse_mem_total("MemFree: 1 kB\n"), None);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::history;
+use crate::state::{DeployEntry, DeployState};
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Unreferenced blobs removed (or that would be removed in a dry run)
+    pub removed: Vec<PathBuf>,
+    pub reclaimed_bytes: u64,
+    /// Generations expired because of the retention limit
+    pub expired_generations: Vec<u32>,
+}
+
+/// Delete blobs in `deployed/` and `originals/` that no entry of the current
+/// state or of any retained generation references.
+///
+/// With `keep_generations`, older generations beyond that many are expired
+/// first so their blobs can be collected too.
+///
+/// `state` is the current state; the caller is expected to hold its lock
+/// (see `DeployState::load_locked`).
+pub fn collect(state: &DeployState, keep_generations: Option<usize>, dry_run: bool) -> Result<GcReport> {
+    let state_dir = state.state_dir();
+    let mut report = GcReport::default();
+
+    let mut generations = history::list(state_dir)?;
+    if let Some(keep) = keep_generations
+        && generations.len() > keep
+    {
+        let expired: Vec<_> = generations.drain(..generations.len() - keep).collect();
+        for generation in &expired {
+            if !dry_run {
+                history::remove(state_dir, generation.number)?;
+            }
+            report.expired_generations.push(generation.number);
+        }
+    }
+
+    let mut deployed_refs = HashSet::new();
+    let mut original_refs = HashSet::new();
+    let live = state
+        .entries()
+        .iter()
+        .chain(generations.iter().flat_map(|g| g.entries.iter()));
+    for entry in live {
+        add_refs(entry, &mut deployed_refs, &mut original_refs);
+    }
+
+    sweep(&state.deployed_dir(), &deployed_refs, dry_run, &mut report)?;
+    sweep(&state.originals_dir(), &original_refs, dry_run, &mut report)?;
+
+    Ok(report)
+}
+
+fn add_refs(entry: &DeployEntry, deployed: &mut HashSet<String>, originals: &mut HashSet<String>) {
+    deployed.insert(entry.content_hash.clone());
+    if let Some(ref hash) = entry.encrypted_hash {
+        deployed.insert(hash.clone());
+    }
+    if let Some(ref hash) = entry.original_hash {
+        originals.insert(hash.clone());
+    }
+}
+
+fn sweep(dir: &Path, referenced: &HashSet<String>, dry_run: bool, report: &mut GcReport) -> Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read content store: {}", dir.display()))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name();
+        if referenced.contains(name.to_string_lossy().as_ref()) || !path.is_file() {
+            continue;
+        }
+        report.reclaimed_bytes += entry.metadata()?.len();
+        if !dry_run {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("failed to remove blob: {}", path.display()))?;
+        }
+        report.removed.push(path);
+    }
+    Ok(())
+}
+
+/// Format a byte count for humans, e.g. `12.3 KiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_bytes_picks_unit() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(2048), "2.0 KiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 + 512 * 1024), "5.5 MiB");
+    }
+}
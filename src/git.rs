@@ -32,7 +32,7 @@ pub enum PullResult {
     Error(String),
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize)]
 pub struct GitSummary {
     pub branch: Option<String>,
     pub dirty_count: usize,
@@ -62,6 +62,11 @@ impl GitRepo {
         Ok(name)
     }
 
+    /// Returns the full hash of the HEAD commit, or `None` if there are no commits yet.
+    pub fn head_commit(&self) -> Option<String> {
+        self.repo.head_id().ok().map(|id| id.to_string())
+    }
+
     /// Returns a high-level summary of the repository state: branch, dirty counts, ahead/behind.
     pub fn summary(&self) -> Result<GitSummary> {
         let branch = self.branch_name()?;
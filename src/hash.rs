@@ -14,6 +14,54 @@ pub fn hash_content(content: &[u8]) -> String {
     format!("{:x}", hasher.finalize())
 }
 
+/// Hash a directory tree: every file's relative path and content hash, in sorted order.
+/// Symlinks inside the tree are hashed by their link target.
+pub fn hash_dir(path: &Path) -> Result<String> {
+    let mut entries = Vec::new();
+    collect_dir_hashes(path, path, &mut entries)?;
+    entries.sort();
+
+    let mut hasher = Sha256::new();
+    for (rel, hash) in &entries {
+        hasher.update(rel.as_bytes());
+        hasher.update([0]);
+        hasher.update(hash.as_bytes());
+        hasher.update([b'\n']);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Hash a file, or a directory tree via [`hash_dir`].
+pub fn hash_path(path: &Path) -> Result<String> {
+    if path.is_dir() {
+        hash_dir(path)
+    } else {
+        hash_file(path)
+    }
+}
+
+fn collect_dir_hashes(base: &Path, dir: &Path, out: &mut Vec<(String, String)>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory for hashing: {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let rel = path
+            .strip_prefix(base)
+            .expect("hashed path must be under base directory")
+            .to_string_lossy()
+            .into_owned();
+        if path.is_symlink() {
+            let link = std::fs::read_link(&path)?;
+            out.push((rel, hash_content(link.as_os_str().as_encoded_bytes())));
+        } else if path.is_dir() {
+            collect_dir_hashes(base, &path, out)?;
+        } else {
+            out.push((rel, hash_file(&path)?));
+        }
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -40,4 +88,21 @@ mod tests {
 
         assert_eq!(hash_file(&path).unwrap(), hash_content(content.as_bytes()));
     }
+
+    #[test]
+    fn hash_dir_changes_with_content_and_names() {
+        let dir = TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub")).unwrap();
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+        std::fs::write(dir.path().join("sub/b.txt"), "b").unwrap();
+        let h1 = hash_dir(dir.path()).unwrap();
+        assert_eq!(h1, hash_dir(dir.path()).unwrap());
+
+        std::fs::write(dir.path().join("sub/b.txt"), "changed").unwrap();
+        let h2 = hash_dir(dir.path()).unwrap();
+        assert_ne!(h1, h2);
+
+        std::fs::rename(dir.path().join("a.txt"), dir.path().join("c.txt")).unwrap();
+        assert_ne!(h2, hash_dir(dir.path()).unwrap());
+    }
 }
@@ -0,0 +1,353 @@
+use crate::hash;
+use crate::journal::Journal;
+use crate::scanner::EntryKind;
+use crate::state::{DeployEntry, DeployState, cleanup_empty_parents, remove_managed};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+const GENERATIONS_DIR: &str = "generations";
+
+/// A snapshot of the managed files after a successful deploy.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Generation {
+    pub number: u32,
+    /// Seconds since the Unix epoch
+    pub timestamp: u64,
+    pub host: String,
+    /// Commit of the dotfiles repo at deploy time, if it is a git repo
+    #[serde(default)]
+    pub commit: Option<String>,
+    /// Set when this generation was created by `dotm rollback`
+    #[serde(default)]
+    pub rollback_of: Option<u32>,
+    pub entries: Vec<DeployEntry>,
+}
+
+#[derive(Debug, Default)]
+pub struct RollbackReport {
+    pub restored: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub skipped: Vec<(PathBuf, String)>,
+    /// Files modified since the last deploy, left untouched
+    pub conflicts: Vec<(PathBuf, String)>,
+}
+
+fn generations_dir(state_dir: &Path) -> PathBuf {
+    state_dir.join(GENERATIONS_DIR)
+}
+
+fn generation_path(state_dir: &Path, number: u32) -> PathBuf {
+    generations_dir(state_dir).join(format!("{number}.json"))
+}
+
+/// All recorded generations, oldest first.
+pub fn list(state_dir: &Path) -> Result<Vec<Generation>> {
+    let dir = generations_dir(state_dir);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut generations = Vec::new();
+    for entry in std::fs::read_dir(&dir)
+        .with_context(|| format!("failed to read generations directory: {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let is_generation = path.extension().and_then(|e| e.to_str()) == Some("json")
+            && path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .is_some_and(|s| s.parse::<u32>().is_ok());
+        if is_generation {
+            generations.push(read_generation(&path)?);
+        }
+    }
+    generations.sort_by_key(|g| g.number);
+    Ok(generations)
+}
+
+pub fn load(state_dir: &Path, number: u32) -> Result<Generation> {
+    let path = generation_path(state_dir, number);
+    if !path.exists() {
+        bail!("generation {number} not found");
+    }
+    read_generation(&path)
+}
+
+/// Delete a recorded generation.
+pub fn remove(state_dir: &Path, number: u32) -> Result<()> {
+    let path = generation_path(state_dir, number);
+    std::fs::remove_file(&path)
+        .with_context(|| format!("failed to remove generation: {}", path.display()))
+}
+
+fn read_generation(path: &Path) -> Result<Generation> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read generation: {}", path.display()))?;
+    serde_json::from_str(&content)
+        .with_context(|| format!("failed to parse generation: {}", path.display()))
+}
+
+/// Record a new generation numbered one past the latest.
+pub fn record(
+    state_dir: &Path,
+    host: &str,
+    commit: Option<String>,
+    rollback_of: Option<u32>,
+    entries: &[DeployEntry],
+) -> Result<Generation> {
+    let number = list(state_dir)?.last().map_or(1, |g| g.number + 1);
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    let generation = Generation {
+        number,
+        timestamp,
+        host: host.to_string(),
+        commit,
+        rollback_of,
+        entries: entries.to_vec(),
+    };
+
+    let dir = generations_dir(state_dir);
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("failed to create generations directory: {}", dir.display()))?;
+    let path = generation_path(state_dir, number);
+    std::fs::write(&path, serde_json::to_string_pretty(&generation)?)
+        .with_context(|| format!("failed to write generation: {}", path.display()))?;
+    Ok(generation)
+}
+
+/// Re-materialise the files of generation `number` from the content store and
+/// make it the current state, recording the result as a new generation.
+///
+/// Files that can't be restored (secrets, folded directories whose tree has since
+/// changed, content missing from the store) are skipped and left as they are.
+/// Managed files modified since the last deploy are reported as conflicts and
+/// never overwritten or removed. Files removed by the rollback get their pre-dotm
+/// original back if there was one. Like deploys, a failed rollback undoes its own
+/// changes.
+pub fn rollback(state_dir: &Path, number: u32, commit: Option<String>) -> Result<RollbackReport> {
+    let mut state = DeployState::load_locked(state_dir)?;
+    let generation = load(state_dir, number)?;
+
+    let mut journal = Journal::new();
+    match rollback_journaled(&mut state, &generation, &mut journal) {
+        Ok((report, entries)) => {
+            state.set_entries(entries);
+            state.save()?;
+            record(state_dir, &generation.host, commit, Some(number), state.entries())?;
+            Ok(report)
+        }
+        Err(e) => {
+            journal.rollback()?;
+            Err(e.context(format!("failed to roll back to generation {number}")))
+        }
+    }
+}
+
+fn rollback_journaled(
+    state: &mut DeployState,
+    generation: &Generation,
+    journal: &mut Journal,
+) -> Result<(RollbackReport, Vec<DeployEntry>)> {
+    let mut report = RollbackReport::default();
+    let mut entries = Vec::new();
+
+    // Remove files that are managed now but weren't in the generation
+    let wanted: HashSet<&Path> = generation.entries.iter().map(|e| e.target.as_path()).collect();
+    for entry in state.entries() {
+        if wanted.contains(entry.target.as_path()) {
+            continue;
+        }
+        if is_modified(state, entry) {
+            report.conflicts.push((entry.target.clone(), MODIFIED.to_string()));
+            entries.push(entry.clone());
+            continue;
+        }
+        journal.record(&entry.target)?;
+        if entry.target.is_symlink() || entry.target.is_file() {
+            std::fs::remove_file(&entry.target)
+                .with_context(|| format!("failed to remove: {}", entry.target.display()))?;
+            cleanup_empty_parents(&entry.target);
+        }
+        if entry.has_staged_copy() {
+            journal.record(&entry.staged)?;
+            remove_managed(&entry.staged)
+                .with_context(|| format!("failed to remove staged: {}", entry.staged.display()))?;
+            cleanup_empty_parents(&entry.staged);
+        }
+        state.restore_original(entry)?;
+        report.removed.push(entry.target.clone());
+    }
+
+    for entry in &generation.entries {
+        let current = state.entries().iter().find(|e| e.target == entry.target);
+        if let Some(current) = current
+            && is_modified(state, current)
+        {
+            report.conflicts.push((entry.target.clone(), MODIFIED.to_string()));
+            entries.push(current.clone());
+            continue;
+        }
+        match restore_entry(state, entry, current, journal)? {
+            None => {
+                report.restored.push(entry.target.clone());
+                entries.push(entry.clone());
+            }
+            Some(reason) => {
+                report.skipped.push((entry.target.clone(), reason));
+                if let Some(current) = current {
+                    entries.push(current.clone());
+                }
+            }
+        }
+    }
+
+    Ok((report, entries))
+}
+
+const MODIFIED: &str = "modified since last deploy";
+
+/// Whether a currently managed file was changed by hand since it was deployed.
+fn is_modified(state: &DeployState, entry: &DeployEntry) -> bool {
+    let status = state.check_entry_status(entry);
+    !status.is_missing() && status.is_modified()
+}
+
+/// Put a single generation entry back in place. Returns the reason when it had to be skipped.
+fn restore_entry(
+    state: &DeployState,
+    entry: &DeployEntry,
+    current: Option<&DeployEntry>,
+    journal: &mut Journal,
+) -> Result<Option<String>> {
+    // Never replace files dotm doesn't manage right now
+    if current.is_none() && entry.target.exists() && !entry.target.is_symlink() {
+        return Ok(Some("file exists and is not managed by dotm".to_string()));
+    }
+
+    if entry.linked {
+        if !entry.source.exists() {
+            return Ok(Some("linked source no longer exists".to_string()));
+        }
+        journal.record(&entry.target)?;
+        replace_with_symlink(&entry.target, &entry.source)?;
+        return Ok(None);
+    }
+
+    // Content the store doesn't hold can only be kept if it is still unchanged
+    let unchanged = entry.staged.exists()
+        && hash::hash_path(&entry.staged).is_ok_and(|h| h == entry.content_hash);
+    let content = if entry.kind == EntryKind::Directory || entry.sensitive {
+        if !unchanged {
+            let reason = if entry.sensitive {
+                "contains secrets and is not kept in the content store; redeploy to restore"
+            } else {
+                "folded directory has changed since; redeploy to restore"
+            };
+            return Ok(Some(reason.to_string()));
+        }
+        None
+    } else if unchanged {
+        None
+    } else {
+        match state.load_deployed(&entry.content_hash) {
+            Ok(content) => Some(content),
+            Err(_) => return Ok(Some("content missing from the content store".to_string())),
+        }
+    };
+
+    if let Some(content) = content {
+        journal.record(&entry.staged)?;
+        remove_managed(&entry.staged)?;
+        if let Some(parent) = entry.staged.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+        }
+        std::fs::write(&entry.staged, content)
+            .with_context(|| format!("failed to restore: {}", entry.staged.display()))?;
+
+        // The store keeps content only; take the mode from the entry or the source
+        if let Some(ref mode) = entry.mode {
+            crate::deployer::apply_permission_override(&entry.staged, mode)?;
+        } else if let Ok(metadata) = std::fs::metadata(&entry.source) {
+            let mode = metadata.permissions().mode();
+            std::fs::set_permissions(&entry.staged, std::fs::Permissions::from_mode(mode))?;
+        }
+        if (entry.owner.is_some() || entry.group.is_some())
+            && let Err(e) = crate::metadata::apply_ownership(
+                &entry.staged,
+                entry.owner.as_deref(),
+                entry.group.as_deref(),
+            )
+        {
+            eprintln!("warning: failed to set ownership on {}: {e}", entry.staged.display());
+        }
+    }
+
+    if entry.staged != entry.target {
+        journal.record(&entry.target)?;
+        let abs_staged = std::fs::canonicalize(&entry.staged)
+            .with_context(|| format!("failed to canonicalize staged path: {}", entry.staged.display()))?;
+        replace_with_symlink(&entry.target, &abs_staged)?;
+    }
+
+    Ok(None)
+}
+
+fn replace_with_symlink(target: &Path, dest: &Path) -> Result<()> {
+    if target.is_symlink() || target.is_file() {
+        std::fs::remove_file(target)
+            .with_context(|| format!("failed to remove: {}", target.display()))?;
+    } else if target.exists() {
+        bail!("directory already exists and is not managed by dotm: {}", target.display());
+    }
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+    }
+    std::os::unix::fs::symlink(dest, target)
+        .with_context(|| format!("failed to create symlink: {} -> {}", target.display(), dest.display()))?;
+    Ok(())
+}
+
+/// Format a Unix timestamp as `YYYY-MM-DD HH:MM:SS` (UTC).
+pub fn format_timestamp(timestamp: u64) -> String {
+    let days = (timestamp / 86_400) as i64;
+    let secs = timestamp % 86_400;
+
+    // Civil-from-days (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_timestamp_epoch_and_leap_day() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13:20");
+    }
+}
@@ -0,0 +1,231 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// What was at a path before a deploy touched it.
+#[derive(Debug)]
+enum Snapshot {
+    Absent,
+    Symlink(PathBuf),
+    File {
+        content: Vec<u8>,
+        mode: u32,
+        uid: u32,
+        gid: u32,
+    },
+    Dir {
+        mode: u32,
+        children: Vec<(OsString, Snapshot)>,
+    },
+}
+
+/// Records the prior state of every path a deploy mutates, so a failed deploy
+/// can put the filesystem back exactly as it was.
+///
+/// Call [`Journal::record`] before changing a path; only the first call per path
+/// is kept. [`Journal::rollback`] restores recorded paths in reverse order and
+/// removes directories the deploy created.
+#[derive(Debug, Default)]
+pub struct Journal {
+    entries: Vec<(PathBuf, Snapshot)>,
+    seen: HashSet<PathBuf>,
+    created_dirs: Vec<PathBuf>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Snapshot `path` (file, symlink, or whole directory tree) before it is modified.
+    pub fn record(&mut self, path: &Path) -> Result<()> {
+        if !self.seen.insert(path.to_path_buf()) {
+            return Ok(());
+        }
+
+        // Remember which parent directories don't exist yet; the deploy may create them
+        let mut missing = Vec::new();
+        let mut current = path.parent();
+        while let Some(dir) = current {
+            if dir == Path::new("") || dir.exists() {
+                break;
+            }
+            missing.push(dir.to_path_buf());
+            current = dir.parent();
+        }
+        for dir in missing {
+            if !self.created_dirs.contains(&dir) {
+                self.created_dirs.push(dir);
+            }
+        }
+
+        let snapshot = snapshot(path)
+            .with_context(|| format!("failed to journal {}", path.display()))?;
+        self.entries.push((path.to_path_buf(), snapshot));
+        Ok(())
+    }
+
+    /// Number of paths recorded so far.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Restore every recorded path to its snapshot, newest first, then remove
+    /// directories created since (if they are empty). Keeps going after errors
+    /// and reports the first one.
+    pub fn rollback(self) -> Result<usize> {
+        let mut first_error = None;
+        let mut restored = 0;
+
+        for (path, snapshot) in self.entries.iter().rev() {
+            match restore(path, snapshot) {
+                Ok(()) => restored += 1,
+                Err(e) => {
+                    if first_error.is_none() {
+                        first_error = Some(e.context(format!("failed to roll back {}", path.display())));
+                    }
+                }
+            }
+        }
+
+        let mut created_dirs = self.created_dirs;
+        created_dirs.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+        for dir in created_dirs {
+            let _ = std::fs::remove_dir(&dir);
+        }
+
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(restored),
+        }
+    }
+}
+
+fn snapshot(path: &Path) -> Result<Snapshot> {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return Ok(Snapshot::Absent);
+    };
+
+    if metadata.file_type().is_symlink() {
+        return Ok(Snapshot::Symlink(std::fs::read_link(path)?));
+    }
+
+    if metadata.is_dir() {
+        let mut children = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+            children.push((entry.file_name(), snapshot(&entry.path())?));
+        }
+        return Ok(Snapshot::Dir {
+            mode: metadata.permissions().mode(),
+            children,
+        });
+    }
+
+    Ok(Snapshot::File {
+        content: std::fs::read(path)?,
+        mode: metadata.permissions().mode(),
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+    })
+}
+
+fn restore(path: &Path, snapshot: &Snapshot) -> Result<()> {
+    crate::state::remove_managed(path)?;
+
+    if !matches!(snapshot, Snapshot::Absent)
+        && let Some(parent) = path.parent()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    match snapshot {
+        Snapshot::Absent => {}
+        Snapshot::Symlink(link) => std::os::unix::fs::symlink(link, path)?,
+        Snapshot::File { content, mode, uid, gid } => {
+            std::fs::write(path, content)?;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(*mode))?;
+            // Only succeeds as root; otherwise the file is already ours
+            let _ = std::os::unix::fs::chown(path, Some(*uid), Some(*gid));
+        }
+        Snapshot::Dir { mode, children } => {
+            std::fs::create_dir(path)?;
+            for (name, child) in children {
+                restore(&path.join(name), child)?;
+            }
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(*mode))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn rollback_restores_file_symlink_and_absence() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        let link = dir.path().join("link");
+        let new = dir.path().join("a/b/new");
+        std::fs::write(&file, "before").unwrap();
+        std::os::unix::fs::symlink("/somewhere", &link).unwrap();
+
+        let mut journal = Journal::new();
+        journal.record(&file).unwrap();
+        journal.record(&link).unwrap();
+        journal.record(&new).unwrap();
+
+        std::fs::write(&file, "after").unwrap();
+        std::fs::remove_file(&link).unwrap();
+        std::fs::create_dir_all(new.parent().unwrap()).unwrap();
+        std::fs::write(&new, "created").unwrap();
+
+        assert_eq!(journal.rollback().unwrap(), 3);
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "before");
+        assert_eq!(std::fs::read_link(&link).unwrap(), Path::new("/somewhere"));
+        assert!(!new.exists());
+        assert!(!dir.path().join("a").exists(), "created directories are removed");
+    }
+
+    #[test]
+    fn rollback_restores_directory_tree() {
+        let dir = TempDir::new().unwrap();
+        let tree = dir.path().join("tree");
+        std::fs::create_dir_all(tree.join("sub")).unwrap();
+        std::fs::write(tree.join("sub/file"), "content").unwrap();
+
+        let mut journal = Journal::new();
+        journal.record(&tree).unwrap();
+        std::fs::remove_dir_all(&tree).unwrap();
+        std::fs::write(&tree, "replaced by a file").unwrap();
+
+        journal.rollback().unwrap();
+        assert_eq!(std::fs::read_to_string(tree.join("sub/file")).unwrap(), "content");
+    }
+
+    #[test]
+    fn only_first_snapshot_is_kept() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("file");
+        std::fs::write(&file, "original").unwrap();
+
+        let mut journal = Journal::new();
+        journal.record(&file).unwrap();
+        std::fs::write(&file, "intermediate").unwrap();
+        journal.record(&file).unwrap();
+        std::fs::write(&file, "final").unwrap();
+
+        assert_eq!(journal.len(), 1);
+        journal.rollback().unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "original");
+    }
+}
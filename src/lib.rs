@@ -1,16 +1,23 @@
 pub mod adopt;
+pub mod condition;
 pub mod config;
 pub mod deployer;
 pub mod diff;
+pub mod facts;
+pub mod gc;
 pub mod git;
 pub mod hash;
+pub mod history;
 pub mod hooks;
+pub mod journal;
 pub mod list;
 pub mod loader;
 pub mod metadata;
 pub mod orchestrator;
+pub mod output;
 pub mod resolver;
 pub mod scanner;
+pub mod secrets;
 pub mod state;
 pub mod status;
 pub mod template;
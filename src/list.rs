@@ -1,6 +1,153 @@
-use crate::config::RootConfig;
+use crate::config::{DeployStrategy, RolePackage, RootConfig};
 use crate::loader::ConfigLoader;
 use anyhow::Result;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct PackageSummary {
+    pub name: String,
+    pub description: Option<String>,
+    pub depends: Vec<String>,
+    pub suggests: Vec<String>,
+    pub target: Option<String>,
+    pub strategy: Option<DeployStrategy>,
+    pub system: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoleSummary {
+    pub name: String,
+    pub includes: Vec<String>,
+    pub packages: Vec<RolePackage>,
+}
+
+/// A role with its included roles expanded recursively.
+#[derive(Debug, Serialize)]
+pub struct RoleTree {
+    pub name: String,
+    pub includes: Vec<RoleTree>,
+    pub packages: Vec<RolePackage>,
+    /// Set when the role includes itself through this path; it is not expanded again
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cycle: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostSummary {
+    pub name: String,
+    pub roles: Vec<String>,
+    /// Hostname pattern from the file's `match` field
+    #[serde(rename = "match", skip_serializing_if = "Option::is_none")]
+    pub host_match: Option<String>,
+}
+
+/// A host with its roles expanded to their packages, as shown by `list hosts --tree`.
+#[derive(Debug, Serialize)]
+pub struct HostTree {
+    pub name: String,
+    pub roles: Vec<RoleTree>,
+}
+
+/// All declared packages, sorted by name.
+pub fn packages(root: &RootConfig) -> Vec<PackageSummary> {
+    let mut names: Vec<&String> = root.packages.keys().collect();
+    names.sort();
+    names
+        .into_iter()
+        .map(|name| {
+            let pkg = &root.packages[name];
+            PackageSummary {
+                name: name.clone(),
+                description: pkg.description.clone(),
+                depends: pkg.depends.clone(),
+                suggests: pkg.suggests.clone(),
+                target: pkg.target.clone(),
+                strategy: pkg.strategy,
+                system: pkg.system,
+            }
+        })
+        .collect()
+}
+
+/// All roles with their packages. Roles that fail to load have no packages.
+pub fn roles(loader: &ConfigLoader) -> Result<Vec<RoleSummary>> {
+    Ok(loader
+        .list_roles()?
+        .into_iter()
+        .map(|name| role_summary(loader, name))
+        .collect())
+}
+
+/// All hosts with their roles. Hosts that fail to load have no roles.
+pub fn hosts(loader: &ConfigLoader) -> Result<Vec<HostSummary>> {
+    Ok(loader
+        .list_hosts()?
+        .into_iter()
+        .map(|name| {
+            let (roles, host_match) = loader
+                .load_host(&name)
+                .map(|h| (h.roles, h.host_match))
+                .unwrap_or_default();
+            HostSummary { name, roles, host_match }
+        })
+        .collect())
+}
+
+/// All hosts with their roles (and the roles those include) expanded to packages.
+pub fn tree(loader: &ConfigLoader) -> Result<Vec<HostTree>> {
+    Ok(hosts(loader)?
+        .into_iter()
+        .map(|host| HostTree {
+            name: host.name,
+            roles: host
+                .roles
+                .into_iter()
+                .map(|role| role_tree(loader, role, &mut Vec::new()))
+                .collect(),
+        })
+        .collect())
+}
+
+fn role_summary(loader: &ConfigLoader, name: String) -> RoleSummary {
+    let (includes, packages) = loader
+        .load_role(&name)
+        .map(|r| (r.includes, r.packages))
+        .unwrap_or_default();
+    RoleSummary {
+        name,
+        includes,
+        packages,
+    }
+}
+
+fn role_tree(loader: &ConfigLoader, name: String, stack: &mut Vec<String>) -> RoleTree {
+    if stack.contains(&name) {
+        return RoleTree {
+            name,
+            includes: Vec::new(),
+            packages: Vec::new(),
+            cycle: true,
+        };
+    }
+    let (includes, packages) = loader
+        .load_role(&name)
+        .map(|r| (r.includes, r.packages))
+        .unwrap_or_default();
+
+    stack.push(name.clone());
+    let includes = includes
+        .into_iter()
+        .map(|include| role_tree(loader, include, stack))
+        .collect();
+    stack.pop();
+
+    RoleTree {
+        name,
+        includes,
+        packages,
+        cycle: false,
+    }
+}
 
 pub fn render_packages(root: &RootConfig, verbose: bool) -> String {
     let mut names: Vec<&String> = root.packages.keys().collect();
@@ -48,7 +195,11 @@ pub fn render_roles(loader: &ConfigLoader, verbose: bool) -> Result<String> {
         out.push_str(name);
         if verbose {
             if let Ok(role) = loader.load_role(name) {
-                out.push_str(&format!(" [{}]", role.packages.join(", ")));
+                let packages: Vec<String> = role.packages.iter().map(|p| p.to_string()).collect();
+                out.push_str(&format!(" [{}]", packages.join(", ")));
+                if !role.includes.is_empty() {
+                    out.push_str(&format!(" includes: {}", role.includes.join(", ")));
+                }
             }
         }
         out.push('\n');
@@ -64,6 +215,9 @@ pub fn render_hosts(loader: &ConfigLoader, verbose: bool) -> Result<String> {
         if verbose {
             if let Ok(host) = loader.load_host(name) {
                 out.push_str(&format!(" [{}]", host.roles.join(", ")));
+                if let Some(pattern) = host.host_match {
+                    out.push_str(&format!(" (match '{pattern}')"));
+                }
             }
         }
         out.push('\n');
@@ -71,32 +225,51 @@ pub fn render_hosts(loader: &ConfigLoader, verbose: bool) -> Result<String> {
     Ok(out)
 }
 
+/// Which host file `hostname` resolves to, as shown after `list hosts`.
+pub fn render_resolution(loader: &ConfigLoader, user: Option<&str>, hostname: &str) -> String {
+    match loader.resolve_host_for(user, hostname) {
+        Ok(resolved) => format!("\n{hostname} resolves to {resolved}\n"),
+        Err(e) => format!("\n{e:#}\n"),
+    }
+}
+
 pub fn render_tree(loader: &ConfigLoader) -> Result<String> {
-    let hosts = loader.list_hosts()?;
+    let hosts = tree(loader)?;
     let mut out = String::new();
 
-    for (hi, host_name) in hosts.iter().enumerate() {
+    for (hi, host) in hosts.iter().enumerate() {
         let is_last_host = hi == hosts.len() - 1;
         let host_prefix = if is_last_host { "└── " } else { "├── " };
-        out.push_str(&format!("{host_prefix}{host_name}\n"));
-
-        if let Ok(host) = loader.load_host(host_name) {
-            for (ri, role_name) in host.roles.iter().enumerate() {
-                let is_last_role = ri == host.roles.len() - 1;
-                let branch = if is_last_host { "    " } else { "│   " };
-                let role_prefix = if is_last_role { "└── " } else { "├── " };
-                out.push_str(&format!("{branch}{role_prefix}{role_name}\n"));
-
-                if let Ok(role) = loader.load_role(role_name) {
-                    for (pi, pkg_name) in role.packages.iter().enumerate() {
-                        let is_last_pkg = pi == role.packages.len() - 1;
-                        let inner_branch = if is_last_role { "    " } else { "│   " };
-                        let pkg_prefix = if is_last_pkg { "└── " } else { "├── " };
-                        out.push_str(&format!("{branch}{inner_branch}{pkg_prefix}{pkg_name}\n"));
-                    }
-                }
-            }
+        out.push_str(&format!("{host_prefix}{}\n", host.name));
+
+        let branch = if is_last_host { "    " } else { "│   " };
+        for (ri, role) in host.roles.iter().enumerate() {
+            render_role(&mut out, role, branch, ri == host.roles.len() - 1, false);
         }
     }
     Ok(out)
 }
+
+/// Render a role and its subtree. Included roles are shown in brackets,
+/// before the role's own packages.
+fn render_role(out: &mut String, role: &RoleTree, indent: &str, is_last: bool, included: bool) {
+    let prefix = if is_last { "└── " } else { "├── " };
+    let name = if included {
+        format!("[{}]", role.name)
+    } else {
+        role.name.clone()
+    };
+    let cycle = if role.cycle { " (cycle)" } else { "" };
+    out.push_str(&format!("{indent}{prefix}{name}{cycle}\n"));
+
+    let inner = format!("{indent}{}", if is_last { "    " } else { "│   " });
+    let children = role.includes.len() + role.packages.len();
+    for (i, include) in role.includes.iter().enumerate() {
+        render_role(out, include, &inner, i == children - 1, true);
+    }
+    for (pi, pkg) in role.packages.iter().enumerate() {
+        let is_last_pkg = role.includes.len() + pi == children - 1;
+        let pkg_prefix = if is_last_pkg { "└── " } else { "├── " };
+        out.push_str(&format!("{inner}{pkg_prefix}{pkg}\n"));
+    }
+}
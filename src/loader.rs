@@ -1,30 +1,251 @@
-use crate::config::{HostConfig, RoleConfig, RootConfig};
+use crate::config::{HostConfig, PackageConfig, RoleConfig, RootConfig};
 use anyhow::{Context, Result, bail};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
+/// Per-package config file, read from the root of each package directory.
+pub const PACKAGE_CONFIG_FILE: &str = "package.toml";
+
+/// Directory of extra config files holding `[packages.*]` tables.
+const INCLUDE_DIR: &str = "dotm.d";
+
+/// Host config used when no other host file matches the hostname.
+pub const DEFAULT_HOST: &str = "default";
+
+/// How a hostname was matched to a file in `hosts/`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase", tag = "kind", content = "pattern")]
+pub enum HostMatch {
+    /// `hosts/<hostname>.toml`
+    Exact,
+    /// A glob file name like `ci-*.toml` or a `match` field
+    Pattern(String),
+    /// `hosts/default.toml`
+    Default,
+}
+
+/// The host file a hostname resolves to.
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedHost {
+    pub hostname: String,
+    /// Name of the host file, without `.toml`
+    pub name: String,
+    pub path: PathBuf,
+    pub matched_by: HostMatch,
+}
+
+impl std::fmt::Display for ResolvedHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "hosts/{}.toml", self.name)?;
+        match self.matched_by {
+            HostMatch::Exact => Ok(()),
+            HostMatch::Pattern(ref pattern) => write!(f, " (matches '{pattern}')"),
+            HostMatch::Default => write!(f, " (default)"),
+        }
+    }
+}
+
+/// A hostname pattern: a glob, or a regex between slashes.
+pub enum HostPattern {
+    Glob(globset::GlobMatcher),
+    Regex(regex::Regex),
+}
+
+impl HostPattern {
+    pub fn parse(pattern: &str) -> Result<Self> {
+        if let Some(re) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            let regex = regex::Regex::new(&format!("^(?:{re})$"))
+                .with_context(|| format!("invalid host regex '{pattern}'"))?;
+            return Ok(HostPattern::Regex(regex));
+        }
+        let glob = globset::Glob::new(pattern).with_context(|| format!("invalid host glob '{pattern}'"))?;
+        Ok(HostPattern::Glob(glob.compile_matcher()))
+    }
+
+    pub fn is_match(&self, hostname: &str) -> bool {
+        match self {
+            HostPattern::Glob(glob) => glob.is_match(hostname),
+            HostPattern::Regex(regex) => regex.is_match(hostname),
+        }
+    }
+}
+
+/// Whether a host file name is itself a glob, like `ci-*`.
+fn is_glob(name: &str) -> bool {
+    name.contains(['*', '?', '['])
+}
+
+/// Where a package definition was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefinitionSource {
+    pub file: PathBuf,
+    /// 1-based line of the `[packages.<name>]` table; `None` for a `package.toml`,
+    /// which is the definition as a whole
+    pub line: Option<usize>,
+}
+
+impl std::fmt::Display for DefinitionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{line}", self.file.display()),
+            None => write!(f, "{}", self.file.display()),
+        }
+    }
+}
+
+/// An include file in `dotm.d/`, which may only define packages.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncludeConfig {
+    #[serde(default)]
+    packages: HashMap<String, PackageConfig>,
+}
+
+/// Package names with their position in the file, for error reporting.
+#[derive(Debug, Deserialize)]
+struct PackageSpans {
+    #[serde(default)]
+    packages: HashMap<toml::Spanned<String>, IgnoredAny>,
+}
+
 pub struct ConfigLoader {
     base_dir: PathBuf,
     root: RootConfig,
+    sources: HashMap<String, DefinitionSource>,
+    /// Later definitions of packages already defined, which are ignored
+    duplicates: Vec<(String, DefinitionSource)>,
 }
 
 impl ConfigLoader {
+    /// Load `dotm.toml` and merge in packages defined by `dotm.d/*.toml` includes
+    /// (in file name order) and `packages/<name>/package.toml` files.
+    ///
+    /// A package may only be defined once. Later definitions are ignored and
+    /// reported by [`ConfigLoader::duplicate_packages`].
     pub fn new(base_dir: &Path) -> Result<Self> {
         let config_path = base_dir.join("dotm.toml");
         let content = std::fs::read_to_string(&config_path)
             .with_context(|| format!("failed to read {}", config_path.display()))?;
-        let root: RootConfig = toml::from_str(&content)
+        let RootConfig { dotm, packages } = toml::from_str(&content)
             .with_context(|| format!("failed to parse {}", config_path.display()))?;
 
-        Ok(Self {
+        let mut loader = Self {
             base_dir: base_dir.to_path_buf(),
-            root,
-        })
+            root: RootConfig {
+                dotm,
+                packages: HashMap::new(),
+            },
+            sources: HashMap::new(),
+            duplicates: Vec::new(),
+        };
+        loader.merge_packages(&config_path, &content, packages)?;
+        loader.load_includes()?;
+        loader.load_package_files()?;
+        Ok(loader)
+    }
+
+    fn load_includes(&mut self) -> Result<()> {
+        let include_dir = self.base_dir.join(INCLUDE_DIR);
+        if !include_dir.is_dir() {
+            return Ok(());
+        }
+        for path in toml_files(&include_dir)? {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let include: IncludeConfig = toml::from_str(&content)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            self.merge_packages(&path, &content, include.packages)?;
+        }
+        Ok(())
+    }
+
+    fn load_package_files(&mut self) -> Result<()> {
+        let packages_dir = self.packages_dir();
+        if !packages_dir.is_dir() {
+            return Ok(());
+        }
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(&packages_dir)
+            .with_context(|| format!("failed to read {}", packages_dir.display()))?
+        {
+            let path = entry?.path().join(PACKAGE_CONFIG_FILE);
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+
+        for path in paths {
+            let name = path
+                .parent()
+                .and_then(|p| p.file_name())
+                .and_then(|n| n.to_str())
+                .with_context(|| format!("invalid package directory name: {}", path.display()))?
+                .to_string();
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let config: PackageConfig = toml::from_str(&content)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            self.insert_package(name, config, DefinitionSource { file: path, line: None });
+        }
+        Ok(())
+    }
+
+    fn merge_packages(
+        &mut self,
+        path: &Path,
+        content: &str,
+        packages: HashMap<String, PackageConfig>,
+    ) -> Result<()> {
+        let spans: PackageSpans = toml::from_str(content)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        let lines: HashMap<String, usize> = spans
+            .packages
+            .keys()
+            .map(|key| (key.get_ref().clone(), line_of(content, key.span().start)))
+            .collect();
+
+        let mut names: Vec<_> = packages.into_iter().collect();
+        names.sort_by_key(|(name, _)| lines.get(name).copied().unwrap_or_default());
+        for (name, config) in names {
+            let line = lines.get(&name).copied();
+            self.insert_package(name, config, DefinitionSource { file: path.to_path_buf(), line });
+        }
+        Ok(())
+    }
+
+    fn insert_package(&mut self, name: String, config: PackageConfig, source: DefinitionSource) {
+        if self.sources.contains_key(&name) {
+            self.duplicates.push((name, source));
+            return;
+        }
+        self.sources.insert(name.clone(), source);
+        self.root.packages.insert(name, config);
     }
 
     pub fn root(&self) -> &RootConfig {
         &self.root
     }
 
+    /// Where a package was defined.
+    pub fn package_source(&self, name: &str) -> Option<&DefinitionSource> {
+        self.sources.get(name)
+    }
+
+    /// Packages defined more than once, as messages naming both definitions. Deploy
+    /// refuses to run while there are any; `dotm check` reports them.
+    pub fn duplicate_packages(&self) -> Vec<String> {
+        self.duplicates
+            .iter()
+            .map(|(name, source)| {
+                let first = self.package_source(name).expect("duplicates are of loaded packages");
+                format!("package '{name}' is defined more than once: {first} and {source}")
+            })
+            .collect()
+    }
+
     pub fn base_dir(&self) -> &Path {
         &self.base_dir
     }
@@ -33,16 +254,90 @@ impl ConfigLoader {
         self.base_dir.join(&self.root.dotm.packages_dir)
     }
 
+    /// Load the host config `hostname` resolves to (see [`ConfigLoader::resolve_host`]).
     pub fn load_host(&self, hostname: &str) -> Result<HostConfig> {
-        let path = self.base_dir.join("hosts").join(format!("{hostname}.toml"));
-        if !path.exists() {
-            bail!("host config not found: {}", path.display());
+        self.load_host_for(None, hostname)
+    }
+
+    /// Load the host config `hostname` resolves to for `user` (see [`ConfigLoader::resolve_host_for`]).
+    pub fn load_host_for(&self, user: Option<&str>, hostname: &str) -> Result<HostConfig> {
+        let resolved = self.resolve_host_for(user, hostname)?;
+        read_host(&resolved.path)
+    }
+
+    /// Find the host file for `hostname`: `hosts/<hostname>.toml` if it exists, else
+    /// the matching pattern with the highest `priority` (the first by file name on a
+    /// tie), else `hosts/default.toml`.
+    pub fn resolve_host(&self, hostname: &str) -> Result<ResolvedHost> {
+        self.resolve_host_for(None, hostname)
+    }
+
+    /// Like [`ConfigLoader::resolve_host`], but a host file for `user@hostname` (exact
+    /// or a pattern containing `@`) takes precedence.
+    pub fn resolve_host_for(&self, user: Option<&str>, hostname: &str) -> Result<ResolvedHost> {
+        if let Some(user) = user
+            && let Some(resolved) = self.match_host(&format!("{user}@{hostname}"))?
+        {
+            return Ok(resolved);
         }
-        let content = std::fs::read_to_string(&path)
-            .with_context(|| format!("failed to read {}", path.display()))?;
-        let config: HostConfig = toml::from_str(&content)
-            .with_context(|| format!("failed to parse {}", path.display()))?;
-        Ok(config)
+        if let Some(resolved) = self.match_host(hostname)? {
+            return Ok(resolved);
+        }
+
+        if self.base_dir.join("hosts").join(format!("{DEFAULT_HOST}.toml")).is_file() {
+            return Ok(ResolvedHost {
+                hostname: hostname.to_string(),
+                name: DEFAULT_HOST.to_string(),
+                path: self.base_dir.join("hosts").join(format!("{DEFAULT_HOST}.toml")),
+                matched_by: HostMatch::Default,
+            });
+        }
+        bail!(
+            "host config not found for '{hostname}': no hosts/{hostname}.toml, matching pattern or hosts/{DEFAULT_HOST}.toml"
+        )
+    }
+
+    /// The exact or pattern host file for `key`, a hostname or `user@hostname`.
+    /// Patterns only apply to keys of the same kind: with an `@` or without. A glob
+    /// file name is matched before its file is read; other files are only read for
+    /// their `match` and `priority`, and one that can't be read is passed over (it
+    /// fails when it's the file being loaded).
+    fn match_host(&self, key: &str) -> Result<Option<ResolvedHost>> {
+        let hosts_dir = self.base_dir.join("hosts");
+        let resolved = |name: &str, matched_by| ResolvedHost {
+            hostname: key.to_string(),
+            name: name.to_string(),
+            path: hosts_dir.join(format!("{name}.toml")),
+            matched_by,
+        };
+        let matches = |pattern: &str| {
+            pattern.contains('@') == key.contains('@')
+                && HostPattern::parse(pattern).is_ok_and(|pattern| pattern.is_match(key))
+        };
+
+        if hosts_dir.join(format!("{key}.toml")).is_file() {
+            return Ok(Some(resolved(key, HostMatch::Exact)));
+        }
+
+        let mut best: Option<(i64, ResolvedHost)> = None;
+        for name in self.list_hosts()? {
+            let path = hosts_dir.join(format!("{name}.toml"));
+            let (pattern, priority) = if is_glob(&name) {
+                if !matches(&name) {
+                    continue;
+                }
+                (name.clone(), read_host_header(&path).map(|p| p.priority).unwrap_or_default())
+            } else {
+                match read_host_header(&path) {
+                    Ok(HostHeader { host_match: Some(pattern), priority }) if matches(&pattern) => (pattern, priority),
+                    _ => continue,
+                }
+            };
+            if best.as_ref().is_none_or(|(best_priority, _)| priority > *best_priority) {
+                best = Some((priority, resolved(&name, HostMatch::Pattern(pattern))));
+            }
+        }
+        Ok(best.map(|(_, host)| host))
     }
 
     pub fn list_hosts(&self) -> Result<Vec<String>> {
@@ -64,6 +359,26 @@ impl ConfigLoader {
         Ok(names)
     }
 
+    /// The machines host files are written for: `(None, host)` for `hosts/<host>.toml`
+    /// and `(Some(user), host)` for `hosts/<user>@<host>.toml`. Pattern files (a glob
+    /// name such as `ci-*.toml`, or a `match` field) name no machine and are left out.
+    pub fn concrete_hosts(&self) -> Result<Vec<(Option<String>, String)>> {
+        let hosts_dir = self.base_dir.join("hosts");
+        let mut hosts = Vec::new();
+        for name in self.list_hosts()? {
+            // Unreadable files are reported by `dotm check` on their own
+            let has_match = read_host(&hosts_dir.join(format!("{name}.toml"))).is_ok_and(|c| c.host_match.is_some());
+            if is_glob(&name) || has_match {
+                continue;
+            }
+            hosts.push(match name.split_once('@') {
+                Some((user, host)) => (Some(user.to_string()), host.to_string()),
+                None => (None, name),
+            });
+        }
+        Ok(hosts)
+    }
+
     pub fn list_roles(&self) -> Result<Vec<String>> {
         let roles_dir = self.base_dir.join("roles");
         if !roles_dir.is_dir() {
@@ -95,3 +410,41 @@ impl ConfigLoader {
         Ok(config)
     }
 }
+
+fn read_host(path: &Path) -> Result<HostConfig> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&content).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// The fields of a host file that decide which hostnames it applies to.
+#[derive(Deserialize)]
+struct HostHeader {
+    #[serde(rename = "match")]
+    host_match: Option<String>,
+    #[serde(default)]
+    priority: i64,
+}
+
+fn read_host_header(path: &Path) -> Result<HostHeader> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(toml::from_str(&content)?)
+}
+
+/// All `*.toml` files directly inside `dir`, sorted by name.
+fn toml_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().and_then(|e| e.to_str()) == Some("toml") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// 1-based line number of a byte offset.
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
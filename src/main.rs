@@ -1,6 +1,6 @@
 use clap::{CommandFactory, Parser};
 use dotm::orchestrator::Orchestrator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(name = "dotm", about = "Dotfile manager with composable roles", version)]
@@ -9,10 +9,28 @@ struct Cli {
     #[arg(short, long, default_value = ".")]
     dir: PathBuf,
 
+    /// Output format for status, list, vars and deploy
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Shorthand for --format json
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Deploy profile: prefers hosts/<profile>@<host>.toml and keeps its own state
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(clap::Subcommand)]
 enum Commands {
     /// Deploy configs for the current host
@@ -32,6 +50,12 @@ enum Commands {
         /// Deploy only this package (and its dependencies)
         #[arg(short, long)]
         package: Option<String>,
+        /// Override a var for this deploy, e.g. `--var editor.theme=nord` (value parsed as TOML)
+        #[arg(long = "var", value_name = "KEY=VALUE")]
+        vars: Vec<String>,
+        /// Override vars with the contents of a TOML file
+        #[arg(long, value_name = "FILE")]
+        var_file: Vec<PathBuf>,
     },
     /// Remove all managed symlinks and copies
     Undeploy {
@@ -101,6 +125,23 @@ enum Commands {
         #[command(subcommand)]
         what: ListWhat,
     },
+    /// Show a host's merged variables and where each one was set
+    Vars {
+        /// Only show this var, or the vars in this table (dotted path)
+        key: Option<String>,
+        /// Host to show (defaults to system hostname)
+        #[arg(long)]
+        host: Option<String>,
+        /// Show the vars seen by this package's templates, including its defaults
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Override a var as deploy would, e.g. `--var editor.theme=nord`
+        #[arg(long = "var", value_name = "KEY=VALUE")]
+        vars: Vec<String>,
+        /// Override vars with the contents of a TOML file
+        #[arg(long, value_name = "FILE")]
+        var_file: Vec<PathBuf>,
+    },
     /// Commit all changes in the dotfiles repository
     Commit {
         /// Commit message (auto-generated if not provided)
@@ -140,6 +181,32 @@ enum Commands {
         #[arg(long)]
         system: bool,
     },
+    /// List recorded deploy generations
+    History {
+        /// Operate on system packages
+        #[arg(long)]
+        system: bool,
+    },
+    /// Restore the files of a previous deploy generation
+    Rollback {
+        /// Generation to roll back to (defaults to the one before the current)
+        generation: Option<u32>,
+        /// Operate on system packages (requires root)
+        #[arg(long)]
+        system: bool,
+    },
+    /// Delete content store blobs no longer referenced by the state or history
+    Gc {
+        /// Show what would be removed without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Keep only the newest N generations (overrides keep_generations)
+        #[arg(long)]
+        keep: Option<usize>,
+        /// Operate on system packages (requires root)
+        #[arg(long)]
+        system: bool,
+    },
     /// Pull, deploy, and optionally push in one step
     Sync {
         /// Target host (defaults to system hostname)
@@ -179,11 +246,15 @@ enum ListWhat {
         /// Show host → role → package tree
         #[arg(long)]
         tree: bool,
+        /// Hostname to show the resolved host file for (defaults to system hostname)
+        #[arg(long)]
+        host: Option<String>,
     },
 }
 
 fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
+    let json = cli.json || cli.format == OutputFormat::Json;
 
     match cli.command {
         Commands::Deploy {
@@ -192,6 +263,8 @@ fn main() -> anyhow::Result<()> {
             force,
             system,
             package,
+            vars,
+            var_file,
         } => {
             let hostname = match host {
                 Some(h) => h,
@@ -212,13 +285,15 @@ fn main() -> anyhow::Result<()> {
                 check_system_privileges();
                 system_state_dir()
             } else {
-                dotm_state_dir()
+                dotm_state_dir(cli.profile.as_deref())?
             };
 
             let mut orch = Orchestrator::new(&cli.dir, &target_dir)?
+                .with_profile(cli.profile.clone())?
                 .with_state_dir(&state_dir)
                 .with_system_mode(system)
-                .with_package_filter(package);
+                .with_package_filter(package)
+                .with_var_overrides(var_overrides(&vars, &var_file)?);
 
             if system && !orch.loader().root().packages.values().any(|p| p.system) {
                 println!("no system packages configured");
@@ -226,13 +301,29 @@ fn main() -> anyhow::Result<()> {
             }
 
             let report = orch.deploy(&hostname, dry_run, force)?;
+            if !json {
+                for invalid in &report.invalid_overrides {
+                    eprintln!("warning: skipped {invalid}");
+                }
+            }
 
-            if dry_run {
+            if json {
+                let doc = dotm::output::DeployDocument { dry_run, report: &report };
+                println!("{}", dotm::output::to_json("dotm.deploy", &doc)?);
+            } else if dry_run {
                 println!("Dry run — would deploy {} files:", report.dry_run_actions.len());
                 for path in &report.dry_run_actions {
                     println!("  {}", path.display());
                 }
+                print_skipped_packages(&report.skipped_packages);
+                if !report.var_overrides.is_empty() {
+                    println!("Var overrides:");
+                    for line in dotm::vars::render_entries(&report.var_overrides).lines() {
+                        println!("  {line}");
+                    }
+                }
             } else {
+                print_skipped_packages(&report.skipped_packages);
                 if !report.created.is_empty() {
                     println!("Created {} files:", report.created.len());
                     for path in &report.created {
@@ -273,7 +364,7 @@ fn main() -> anyhow::Result<()> {
                 check_system_privileges();
                 system_state_dir()
             } else {
-                dotm_state_dir()
+                dotm_state_dir(cli.profile.as_deref())?
             };
             let state = dotm::state::DeployState::load_locked(&state_dir)?;
 
@@ -308,7 +399,7 @@ fn main() -> anyhow::Result<()> {
                 check_system_privileges();
                 system_state_dir()
             } else {
-                dotm_state_dir()
+                dotm_state_dir(cli.profile.as_deref())?
             };
             let mut state = dotm::state::DeployState::load_locked(&state_dir)?;
             let removed = if let Some(ref pkg) = package {
@@ -323,12 +414,12 @@ fn main() -> anyhow::Result<()> {
                 check_system_privileges();
                 system_state_dir()
             } else {
-                dotm_state_dir()
+                dotm_state_dir(cli.profile.as_deref())?
             };
             let state = dotm::state::DeployState::load(&state_dir)?;
             let entries = state.entries();
 
-            if entries.is_empty() {
+            if entries.is_empty() && !json {
                 if !short {
                     println!("No files currently managed by dotm.");
                 }
@@ -354,6 +445,19 @@ fn main() -> anyhow::Result<()> {
             let modified: usize = groups.iter().map(|g| g.modified).sum();
             let missing: usize = groups.iter().map(|g| g.missing).sum();
 
+            if json {
+                let git = dotm::git::GitRepo::open(&cli.dir).and_then(|r| r.summary().ok());
+                let doc = dotm::output::StatusDocument {
+                    git: git.as_ref(),
+                    total,
+                    modified,
+                    missing,
+                    packages: &groups,
+                };
+                println!("{}", dotm::output::to_json("dotm.status", &doc)?);
+                return Ok(());
+            }
+
             let color = dotm::status::use_color();
 
             // Git summary (optional — only when in a git repo)
@@ -397,7 +501,7 @@ fn main() -> anyhow::Result<()> {
                 check_system_privileges();
                 system_state_dir()
             } else {
-                dotm_state_dir()
+                dotm_state_dir(cli.profile.as_deref())?
             };
             let state = dotm::state::DeployState::load(&state_dir)?;
             let mut found_diffs = false;
@@ -416,6 +520,32 @@ fn main() -> anyhow::Result<()> {
 
                 found_diffs = true;
 
+                if entry.sensitive {
+                    println!(
+                        "{}: modified (contains secrets, diff not shown)",
+                        entry.target.display()
+                    );
+                    continue;
+                }
+
+                if entry.linked {
+                    println!(
+                        "{}: no longer links to {} (redeploy to relink)",
+                        entry.target.display(),
+                        entry.source.display()
+                    );
+                    continue;
+                }
+
+                if entry.kind == dotm::scanner::EntryKind::Directory {
+                    println!(
+                        "{}: modified (folded directory, compare with {})",
+                        entry.target.display(),
+                        entry.source.display()
+                    );
+                    continue;
+                }
+
                 let current = std::fs::read_to_string(&entry.staged).unwrap_or_default();
                 let original = state
                     .load_deployed(&entry.content_hash)
@@ -439,19 +569,22 @@ fn main() -> anyhow::Result<()> {
                 check_system_privileges();
                 system_state_dir()
             } else {
-                dotm_state_dir()
+                dotm_state_dir(cli.profile.as_deref())?
             };
             let mut state = dotm::state::DeployState::load_locked(&state_dir)?;
             let mut adopted_count = 0;
             let num_entries = state.entries().len();
 
             for idx in 0..num_entries {
-                let (is_modified, is_template, staged, source, target, content_hash) = {
+                let (is_modified, is_template, is_encrypted, is_folded, is_linked, staged, source, target, content_hash) = {
                     let entry = &state.entries()[idx];
                     let status = state.check_entry_status(entry);
                     (
                         status.is_modified(),
                         entry.kind == dotm::scanner::EntryKind::Template,
+                        entry.kind == dotm::scanner::EntryKind::Encrypted,
+                        entry.kind == dotm::scanner::EntryKind::Directory,
+                        entry.linked,
                         entry.staged.clone(),
                         entry.source.clone(),
                         entry.target.clone(),
@@ -471,6 +604,32 @@ fn main() -> anyhow::Result<()> {
                     continue;
                 }
 
+                if is_encrypted {
+                    eprintln!(
+                        "Skipping {} (encrypted — re-encrypt changes into the .age source manually)",
+                        target.display()
+                    );
+                    continue;
+                }
+
+                if is_linked {
+                    eprintln!(
+                        "Skipping {} (linked — the target no longer points to {}, redeploy to relink)",
+                        target.display(),
+                        source.display()
+                    );
+                    continue;
+                }
+
+                if is_folded {
+                    eprintln!(
+                        "Skipping {} (folded directory — copy changes back into {} manually)",
+                        target.display(),
+                        source.display()
+                    );
+                    continue;
+                }
+
                 let current = std::fs::read_to_string(&staged)?;
                 let original = state
                     .load_deployed(&content_hash)
@@ -504,7 +663,7 @@ fn main() -> anyhow::Result<()> {
         }
         Commands::Check { warn_suggestions } => {
             let loader = dotm::loader::ConfigLoader::new(&cli.dir)?;
-            let mut errors: Vec<String> = Vec::new();
+            let mut errors: Vec<String> = loader.duplicate_packages();
 
             // Validate all host configs
             let hosts_dir = cli.dir.join("hosts");
@@ -519,6 +678,11 @@ fn main() -> anyhow::Result<()> {
                             .expect("invalid host filename");
                         match loader.load_host(stem) {
                             Ok(host) => {
+                                if let Some(ref pattern) = host.host_match
+                                    && let Err(e) = dotm::loader::HostPattern::parse(pattern)
+                                {
+                                    errors.push(format!("invalid host config '{}': {:#}", stem, e));
+                                }
                                 for role_name in &host.roles {
                                     if let Err(e) = loader.load_role(role_name) {
                                         errors.push(format!(
@@ -536,6 +700,100 @@ fn main() -> anyhow::Result<()> {
                 }
             }
 
+            // Validate role includes (unknown roles and cycles)
+            for role_name in loader.list_roles()? {
+                if let Err(e) = dotm::resolver::resolve_roles(&loader, std::slice::from_ref(&role_name)) {
+                    errors.push(format!("invalid role '{}': {:#}", role_name, e));
+                }
+            }
+
+            // Validate package conditions, on packages and on role entries
+            for (pkg_name, pkg_config) in &loader.root().packages {
+                if let Some(ref when) = pkg_config.when
+                    && let Err(e) = dotm::condition::Condition::parse(when)
+                {
+                    errors.push(format!("package '{}' has an invalid condition: {}", pkg_name, e));
+                }
+            }
+            for role_name in loader.list_roles()? {
+                let Ok(role) = loader.load_role(&role_name) else {
+                    continue;
+                };
+                for pkg in &role.packages {
+                    if let Some(ref when) = pkg.when
+                        && let Err(e) = dotm::condition::Condition::parse(when)
+                    {
+                        errors.push(format!(
+                            "role '{}' has an invalid condition for package '{}': {}",
+                            role_name, pkg.name, e
+                        ));
+                    }
+                }
+            }
+
+            // Schema defaults must satisfy their own declarations
+            for (pkg_name, pkg_config) in &loader.root().packages {
+                for (path, var) in &pkg_config.schema {
+                    if let Some(ref default) = var.default
+                        && let Some(problem) = dotm::vars::check_value(var, default)
+                    {
+                        errors.push(format!(
+                            "package '{}': default for var '{}' {}",
+                            pkg_name, path, problem
+                        ));
+                    }
+                }
+            }
+
+            // The remaining checks plan each machine a host file is written for; pattern
+            // files are covered by the machines they match. `user@host` files are
+            // checked as that user.
+            let target_dir = dirs::home_dir().unwrap_or_else(|| PathBuf::from("/"));
+            let orch = Orchestrator::new(&cli.dir, &target_dir)?
+                .with_profile(cli.profile.clone())?;
+            let mut hosts = Vec::new();
+            for (user, host) in loader.concrete_hosts()? {
+                let profile = user.or_else(|| cli.profile.clone());
+                hosts.push((Orchestrator::new(&cli.dir, &target_dir)?.with_profile(profile)?, host));
+            }
+
+            // Every host's vars must satisfy the schemas of the packages it deploys
+            for (host_orch, host) in &hosts {
+                // Hosts that can't be planned were reported above
+                if let Ok(var_errors) = host_orch.check_vars(host) {
+                    errors.extend(var_errors);
+                }
+            }
+
+            // Two overrides equally specific for the same target are picked between by name
+            for (host_orch, host) in &hosts {
+                for warning in host_orch.check_overrides(host).unwrap_or_default() {
+                    eprintln!("warning: {warning}");
+                }
+            }
+
+            // Every package's templates must parse. If they do, render them for every
+            // host to catch missing variables too.
+            match orch.check_template_syntax() {
+                Ok(_) => {
+                    for (host_orch, host) in &hosts {
+                        let Ok(template_errors) = host_orch.check_templates(host) else {
+                            continue;
+                        };
+                        for e in template_errors {
+                            let message = format!("{:#}", e);
+                            if !errors.contains(&message) {
+                                errors.push(message);
+                            }
+                        }
+                    }
+                }
+                Err(e) => match e.downcast::<dotm::template::TemplateErrors>() {
+                    Ok(parse_errors) => errors.extend(parse_errors.0.iter().map(|e| e.to_string())),
+                    Err(e) => errors.push(format!("{:#}", e)),
+                },
+            }
+
             // Validate package dependencies
             let root = loader.root();
             for (pkg_name, pkg_config) in &root.packages {
@@ -558,6 +816,10 @@ fn main() -> anyhow::Result<()> {
                     }
                 }
 
+                if let Err(e) = dotm::config::validate_fold(pkg_name, pkg_config) {
+                    errors.push(format!("{:#}", e));
+                }
+
                 // Check package directory exists
                 let pkg_dir = loader.packages_dir().join(pkg_name);
                 if !pkg_dir.is_dir() {
@@ -566,6 +828,26 @@ fn main() -> anyhow::Result<()> {
                         pkg_name,
                         pkg_dir.display()
                     ));
+                } else {
+                    let invalid = dotm::scanner::invalid_overrides(&pkg_dir)?;
+                    for e in &invalid {
+                        errors.push(format!("package '{}': {:#}", pkg_name, e));
+                    }
+                    if invalid.is_empty()
+                        && let Err(e) = orch.check_package_files(pkg_name)
+                    {
+                        errors.push(format!("{:#}", e));
+                    }
+                    if let dotm::config::FoldConfig::Paths(ref paths) = pkg_config.fold {
+                        for path in paths {
+                            if !pkg_dir.join(path).is_dir() {
+                                errors.push(format!(
+                                    "package '{}' folds '{}', which is not a directory in the package",
+                                    pkg_name, path
+                                ));
+                            }
+                        }
+                    }
                 }
             }
 
@@ -583,7 +865,7 @@ fn main() -> anyhow::Result<()> {
             } else {
                 eprintln!("Configuration errors:");
                 for err in &errors {
-                    eprintln!("  - {}", err);
+                    eprintln!("  - {}", err.replace('\n', "\n    "));
                 }
                 std::process::exit(1);
             }
@@ -630,6 +912,7 @@ fn main() -> anyhow::Result<()> {
 
             let packages_dir = loader.packages_dir();
             let pkg_dir = packages_dir.join(&package);
+            let ignore = dotm::scanner::IgnoreRules::load(&cli.dir, &pkg_dir, &pkg_config.ignore)?;
 
             let mut moved = 0;
             for file in &files {
@@ -647,6 +930,14 @@ fn main() -> anyhow::Result<()> {
                     std::process::exit(1);
                 });
 
+                if ignore.is_ignored(rel_path, abs_file.is_dir()) {
+                    eprintln!(
+                        "error: {} is ignored in package '{package}' (.dotmignore or ignore = [...]) and would not be deployed",
+                        rel_path.display()
+                    );
+                    std::process::exit(1);
+                }
+
                 let dest = pkg_dir.join(rel_path);
 
                 if dest.exists() && !force {
@@ -657,12 +948,11 @@ fn main() -> anyhow::Result<()> {
                     std::process::exit(1);
                 }
 
-                if let Some(parent) = dest.parent() {
-                    std::fs::create_dir_all(parent)?;
-                }
-
-                std::fs::rename(&abs_file, &dest)?;
+                let kept = move_unignored(&abs_file, &dest, rel_path, &ignore)?;
                 println!("  {} → {}", abs_file.display(), dest.display());
+                if kept > 0 {
+                    println!("    left {kept} ignored file(s) in place");
+                }
                 moved += 1;
             }
 
@@ -674,21 +964,98 @@ fn main() -> anyhow::Result<()> {
         Commands::List { what } => {
             let loader = dotm::loader::ConfigLoader::new(&cli.dir)?;
             match what {
+                _ if json => {
+                    let output = match what {
+                        ListWhat::Packages { .. } => {
+                            let packages = dotm::list::packages(loader.root());
+                            dotm::output::to_json(
+                                "dotm.packages",
+                                &dotm::output::PackagesDocument { packages: &packages },
+                            )?
+                        }
+                        ListWhat::Roles { .. } => {
+                            let roles = dotm::list::roles(&loader)?;
+                            dotm::output::to_json("dotm.roles", &dotm::output::RolesDocument { roles: &roles })?
+                        }
+                        ListWhat::Hosts { tree: true, .. } => {
+                            let hosts = dotm::list::tree(&loader)?;
+                            dotm::output::to_json("dotm.tree", &dotm::output::TreeDocument { hosts: &hosts })?
+                        }
+                        ListWhat::Hosts { ref host, .. } => {
+                            let hosts = dotm::list::hosts(&loader)?;
+                            let user = current_user(cli.profile.clone());
+                            let resolved = current_hostname(host.clone())
+                                .and_then(|h| loader.resolve_host_for(user.as_deref(), &h).ok());
+                            dotm::output::to_json(
+                                "dotm.hosts",
+                                &dotm::output::HostsDocument { hosts: &hosts, resolved: resolved.as_ref() },
+                            )?
+                        }
+                    };
+                    println!("{output}");
+                }
                 ListWhat::Packages { verbose } => {
                     print!("{}", dotm::list::render_packages(loader.root(), verbose));
                 }
                 ListWhat::Roles { verbose } => {
                     print!("{}", dotm::list::render_roles(&loader, verbose)?);
                 }
-                ListWhat::Hosts { verbose, tree } => {
+                ListWhat::Hosts { verbose, tree, host } => {
                     if tree {
                         print!("{}", dotm::list::render_tree(&loader)?);
                     } else {
                         print!("{}", dotm::list::render_hosts(&loader, verbose)?);
+                        if let Some(hostname) = current_hostname(host) {
+                            let user = current_user(cli.profile.clone());
+                            print!("{}", dotm::list::render_resolution(&loader, user.as_deref(), &hostname));
+                        }
                     }
                 }
             }
         }
+        Commands::Vars { key, host, package, vars, var_file } => {
+            let hostname = match host {
+                Some(h) => h,
+                None => hostname::get()
+                    .map(|h| h.to_string_lossy().to_string())
+                    .unwrap_or_else(|_| {
+                        eprintln!("error: could not detect hostname, use --host to specify");
+                        std::process::exit(1);
+                    }),
+            };
+            let target_dir = dirs::home_dir().unwrap_or_else(|| PathBuf::from("/"));
+            // The same overrides as deploy, so the output shows where values really come from
+            let orch = Orchestrator::new(&cli.dir, &target_dir)?
+                .with_profile(cli.profile.clone())?
+                .with_var_overrides(var_overrides(&vars, &var_file)?);
+            let mut host_vars = orch.host_vars(&hostname)?;
+            if let Some(ref pkg) = package {
+                if !orch.loader().root().packages.contains_key(pkg) {
+                    eprintln!("error: unknown package '{pkg}'");
+                    std::process::exit(1);
+                }
+                host_vars = orch.package_vars(&host_vars, pkg);
+            }
+            let entries = dotm::vars::entries(&host_vars.provenance, key.as_deref());
+
+            if let Some(ref key) = key
+                && entries.is_empty()
+            {
+                eprintln!("error: var '{key}' is not set for host '{hostname}'");
+                std::process::exit(1);
+            }
+
+            if json {
+                let doc = dotm::output::VarsDocument {
+                    host: &hostname,
+                    roles: &host_vars.roles,
+                    vars: &entries,
+                };
+                println!("{}", dotm::output::to_json("dotm.vars", &doc)?);
+            } else {
+                print!("{}", dotm::vars::render_entries(&entries));
+            }
+        }
         Commands::Commit { message } => {
             let git_repo = dotm::git::GitRepo::open(&cli.dir).ok_or_else(|| {
                 anyhow::anyhow!("dotfiles directory is not a git repository")
@@ -789,7 +1156,7 @@ fn main() -> anyhow::Result<()> {
                 check_system_privileges();
                 system_state_dir()
             } else {
-                dotm_state_dir()
+                dotm_state_dir(cli.profile.as_deref())?
             };
 
             // Load existing state to find what's currently managed
@@ -801,6 +1168,7 @@ fn main() -> anyhow::Result<()> {
 
             // Run a deploy scan to determine what *would* be deployed now
             let mut orch = Orchestrator::new(&cli.dir, &target_dir)?
+                .with_profile(cli.profile.clone())?
                 .with_state_dir(&state_dir)
                 .with_system_mode(system);
             let report = orch.deploy(&hostname, true, false)?; // dry run to get the target set
@@ -821,8 +1189,8 @@ fn main() -> anyhow::Result<()> {
                             let _ = std::fs::remove_file(&entry.target);
                             dotm::state::cleanup_empty_parents(&entry.target);
                         }
-                        if entry.staged != entry.target && entry.staged.exists() {
-                            let _ = std::fs::remove_file(&entry.staged);
+                        if entry.has_staged_copy() && entry.staged.exists() {
+                            let _ = dotm::state::remove_managed(&entry.staged);
                             dotm::state::cleanup_empty_parents(&entry.staged);
                         }
                         println!("  - {}", entry.target.display());
@@ -841,6 +1209,7 @@ fn main() -> anyhow::Result<()> {
                 // Re-deploy to update state without orphans
                 drop(existing_state); // release lock
                 let mut orch2 = Orchestrator::new(&cli.dir, &target_dir)?
+                    .with_profile(cli.profile.clone())?
                     .with_state_dir(&state_dir)
                     .with_system_mode(system);
                 orch2.deploy(&hostname, false, true)?;
@@ -849,6 +1218,119 @@ fn main() -> anyhow::Result<()> {
                 println!("No orphaned files to prune.");
             }
         }
+        Commands::Gc { dry_run, keep, system } => {
+            let state_dir = if system {
+                check_system_privileges();
+                system_state_dir()
+            } else {
+                dotm_state_dir(cli.profile.as_deref())?
+            };
+
+            let keep = keep.or_else(|| {
+                dotm::loader::ConfigLoader::new(&cli.dir)
+                    .ok()
+                    .and_then(|l| l.root().dotm.keep_generations)
+            });
+            let state = dotm::state::DeployState::load_locked(&state_dir)?;
+            let report = dotm::gc::collect(&state, keep, dry_run)?;
+
+            let reclaimed = dotm::gc::format_bytes(report.reclaimed_bytes);
+            if dry_run {
+                for path in &report.removed {
+                    println!("  - {}", path.display());
+                }
+                if !report.expired_generations.is_empty() {
+                    println!("Dry run — would expire {} generation(s).", report.expired_generations.len());
+                }
+                println!("Dry run — would remove {} blobs, reclaiming {reclaimed}.", report.removed.len());
+            } else {
+                if !report.expired_generations.is_empty() {
+                    println!("Expired {} generation(s).", report.expired_generations.len());
+                }
+                println!("Removed {} blobs, reclaimed {reclaimed}.", report.removed.len());
+            }
+        }
+        Commands::History { system } => {
+            let state_dir = if system {
+                check_system_privileges();
+                system_state_dir()
+            } else {
+                dotm_state_dir(cli.profile.as_deref())?
+            };
+            let generations = dotm::history::list(&state_dir)?;
+            if generations.is_empty() {
+                println!("No deploy generations recorded.");
+                return Ok(());
+            }
+
+            let current = generations.last().map(|g| g.number);
+            for generation in generations.iter().rev() {
+                let commit = generation
+                    .commit
+                    .as_deref()
+                    .map(|c| &c[..c.len().min(7)])
+                    .unwrap_or("-");
+                let mut line = format!(
+                    "{:>4}  {} UTC  {}  {}  {} files",
+                    generation.number,
+                    dotm::history::format_timestamp(generation.timestamp),
+                    generation.host,
+                    commit,
+                    generation.entries.len()
+                );
+                if let Some(of) = generation.rollback_of {
+                    line.push_str(&format!("  (rollback to {of})"));
+                }
+                if Some(generation.number) == current {
+                    line.push_str("  (current)");
+                }
+                println!("{line}");
+            }
+        }
+        Commands::Rollback { generation, system } => {
+            let state_dir = if system {
+                check_system_privileges();
+                system_state_dir()
+            } else {
+                dotm_state_dir(cli.profile.as_deref())?
+            };
+
+            let number = match generation {
+                Some(n) => n,
+                None => {
+                    let generations = dotm::history::list(&state_dir)?;
+                    if generations.len() < 2 {
+                        eprintln!("error: no previous generation to roll back to");
+                        std::process::exit(1);
+                    }
+                    generations[generations.len() - 2].number
+                }
+            };
+
+            let commit = dotm::git::GitRepo::open(&cli.dir).and_then(|r| r.head_commit());
+            let report = dotm::history::rollback(&state_dir, number, commit)?;
+
+            for path in &report.removed {
+                println!("  - {}", path.display());
+            }
+            for (path, reason) in &report.skipped {
+                eprintln!("  ! {} — {}", path.display(), reason);
+            }
+            if !report.conflicts.is_empty() {
+                eprintln!("Conflicts ({}):", report.conflicts.len());
+                for (path, msg) in &report.conflicts {
+                    eprintln!("  ! {} — {}", path.display(), msg);
+                }
+            }
+            println!(
+                "Rolled back to generation {number}: restored {} files, removed {}.",
+                report.restored.len(),
+                report.removed.len()
+            );
+            if !report.conflicts.is_empty() {
+                std::process::exit(1);
+            }
+        }
         Commands::Sync {
             host,
             no_push,
@@ -905,12 +1387,14 @@ fn main() -> anyhow::Result<()> {
                 check_system_privileges();
                 system_state_dir()
             } else {
-                dotm_state_dir()
+                dotm_state_dir(cli.profile.as_deref())?
             };
 
             let mut orch = Orchestrator::new(&cli.dir, &target_dir)?
+                .with_profile(cli.profile.clone())?
                 .with_state_dir(&state_dir)
-                .with_system_mode(system);
+                .with_system_mode(system)
+                .with_var_overrides(var_overrides(&[], &[])?);
 
             if system && !orch.loader().root().packages.values().any(|p| p.system) {
                 println!("no system packages configured");
@@ -958,11 +1442,50 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn dotm_state_dir() -> PathBuf {
-    dirs::state_dir()
+/// Move `src` to `dest`, leaving ignored entries of a directory where they are.
+/// Returns how many entries were left behind.
+fn move_unignored(
+    src: &Path,
+    dest: &Path,
+    rel_path: &Path,
+    ignore: &dotm::scanner::IgnoreRules,
+) -> anyhow::Result<usize> {
+    if src.is_symlink() || !src.is_dir() {
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::rename(src, dest)?;
+        return Ok(0);
+    }
+
+    std::fs::create_dir_all(dest)?;
+    let mut kept = 0;
+    for entry in std::fs::read_dir(src)? {
+        let path = entry?.path();
+        let name = path.file_name().expect("directory entry has a file name");
+        let rel = rel_path.join(name);
+        if ignore.is_ignored(&rel, path.is_dir()) {
+            kept += 1;
+        } else {
+            kept += move_unignored(&path, &dest.join(name), &rel, ignore)?;
+        }
+    }
+    if kept == 0 {
+        std::fs::remove_dir(src)?;
+    }
+    Ok(kept)
+}
+
+/// The user state directory, or the profile's own directory inside it.
+fn dotm_state_dir(profile: Option<&str>) -> anyhow::Result<PathBuf> {
+    let state_dir = dirs::state_dir()
         .or_else(|| dirs::home_dir().map(|h| h.join(".local/state")))
         .expect("could not determine state directory; set XDG_STATE_HOME")
-        .join("dotm")
+        .join("dotm");
+    match profile {
+        Some(profile) => dotm::state::profile_state_dir(&state_dir, profile),
+        None => Ok(state_dir),
+    }
 }
 
 fn system_state_dir() -> PathBuf {
@@ -975,3 +1498,48 @@ fn check_system_privileges() {
         std::process::exit(1);
     }
 }
+
+/// `profile`, or the current user's name, for finding `user@host` host configs.
+fn current_user(profile: Option<String>) -> Option<String> {
+    profile.or_else(|| Some(dotm::facts::Facts::gather().username).filter(|u| !u.is_empty()))
+}
+
+/// `host`, or the system hostname if it can be determined.
+fn current_hostname(host: Option<String>) -> Option<String> {
+    host.or_else(|| hostname::get().ok().map(|h| h.to_string_lossy().to_string()))
+}
+
+/// Var overrides for a deploy: `DOTM_VAR_*` environment variables, then `--var-file`
+/// files, then `--var` flags, each overriding the ones before.
+fn var_overrides(
+    vars: &[String],
+    var_files: &[PathBuf],
+) -> anyhow::Result<Vec<(String, toml::map::Map<String, toml::Value>)>> {
+    use anyhow::Context;
+
+    let mut layers: Vec<_> = dotm::vars::env_overrides(std::env::vars())?
+        .into_iter()
+        .map(|(name, vars)| (format!("env {name}"), vars))
+        .collect();
+    for path in var_files {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read var file: {}", path.display()))?;
+        let file_vars = toml::from_str(&content)
+            .with_context(|| format!("failed to parse var file: {}", path.display()))?;
+        layers.push((format!("--var-file {}", path.display()), file_vars));
+    }
+    let mut flag_vars = toml::map::Map::new();
+    for arg in vars {
+        flag_vars = dotm::vars::merge_vars(&flag_vars, &dotm::vars::parse_override(arg)?);
+    }
+    if !flag_vars.is_empty() {
+        layers.push(("--var".to_string(), flag_vars));
+    }
+    Ok(layers)
+}
+
+fn print_skipped_packages(skipped: &[String]) {
+    if !skipped.is_empty() {
+        println!("Skipped {} packages (condition not met): {}", skipped.len(), skipped.join(", "));
+    }
+}
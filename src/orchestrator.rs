@@ -1,19 +1,27 @@
-use crate::config::DeployStrategy;
+use crate::condition;
+use crate::config::{self, DeployStrategy, FoldConfig, HostConfig, RoleConfig};
 use crate::deployer::{self, DeployResult};
+use crate::facts::Facts;
 use crate::hash;
+use crate::journal::Journal;
 use crate::loader::ConfigLoader;
 use crate::metadata;
 use crate::resolver;
 use crate::scanner;
+use crate::secrets::{self, SecretStore};
 use crate::state::{DeployEntry, DeployState};
 use crate::template;
 use crate::vars;
 use anyhow::{bail, Context, Result};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use toml::map::Map;
 use toml::Value;
 
+/// Mode for deployed files containing secrets, unless the package sets one explicitly.
+const SENSITIVE_MODE: &str = "600";
+
 pub struct Orchestrator {
     loader: ConfigLoader,
     target_dir: PathBuf,
@@ -21,25 +29,84 @@ pub struct Orchestrator {
     staging_dir: PathBuf,
     system_mode: bool,
     package_filter: Option<String>,
+    facts: Option<Facts>,
+    /// Layers merged on top of host vars, lowest precedence first
+    var_overrides: Vec<(String, Map<String, Value>)>,
+    profile: Option<String>,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, serde::Serialize)]
 pub struct DeployReport {
     pub created: Vec<PathBuf>,
     pub updated: Vec<PathBuf>,
     pub unchanged: Vec<PathBuf>,
+    #[serde(serialize_with = "serialize_conflicts")]
     pub conflicts: Vec<(PathBuf, String)>,
     pub dry_run_actions: Vec<PathBuf>,
     pub orphaned: Vec<PathBuf>,
     pub pruned: Vec<PathBuf>,
+    /// Packages skipped because their `when` condition is false
+    pub skipped_packages: Vec<String>,
+    /// Vars set by `--var`, `--var-file` or `DOTM_VAR_*` overrides
+    pub var_overrides: Vec<vars::VarEntry>,
+    /// Overrides skipped because their suffix doesn't parse
+    pub invalid_overrides: Vec<String>,
+}
+
+/// A host's merged vars and where each of them came from.
+#[derive(Debug, Clone)]
+pub struct HostVars {
+    /// Roles in merge order, included roles first
+    pub roles: Vec<String>,
+    pub vars: Map<String, Value>,
+    pub provenance: vars::Provenance,
+    /// The layers merged into `vars`, lowest precedence first, starting with `[dotm.vars]`
+    layers: Vec<(String, Map<String, Value>)>,
+}
+
+impl HostVars {
+    fn merge(roles: Vec<String>, layers: Vec<(String, Map<String, Value>)>) -> Self {
+        let mut merged = Map::new();
+        let mut provenance = vars::Provenance::new();
+        for (layer, layer_vars) in &layers {
+            merged = vars::merge_vars_tracked(&merged, layer_vars, layer, &mut provenance);
+        }
+        Self {
+            roles,
+            vars: merged,
+            provenance,
+            layers,
+        }
+    }
+
+    /// The vars seen by `package`'s templates: its own defaults go above the global
+    /// defaults and beneath role and host vars.
+    pub fn with_package_defaults(&self, package: &str, defaults: &Map<String, Value>) -> Self {
+        if defaults.is_empty() {
+            return self.clone();
+        }
+        let mut layers = self.layers.clone();
+        layers.insert(1, (format!("package '{package}'"), defaults.clone()));
+        Self::merge(self.roles.clone(), layers)
+    }
+}
+
+/// A host's roles, vars and packages, resolved for a deploy.
+struct HostPlan {
+    /// Vars without any package's defaults
+    vars: HostVars,
+    packages: Vec<String>,
+    /// Packages left out because their condition is false
+    skipped: Vec<String>,
 }
 
 struct PendingAction {
     pkg_name: String,
     action: scanner::FileAction,
     pkg_target: PathBuf,
-    rendered: Option<String>,
+    rendered: Option<Vec<u8>>,
     strategy: DeployStrategy,
+    sensitive: bool,
 }
 
 impl Orchestrator {
@@ -53,6 +120,9 @@ impl Orchestrator {
             staging_dir,
             system_mode: false,
             package_filter: None,
+            facts: None,
+            var_overrides: Vec::new(),
+            profile: None,
         })
     }
 
@@ -71,6 +141,32 @@ impl Orchestrator {
         self
     }
 
+    /// Evaluate package conditions against these facts instead of the running system's.
+    pub fn with_facts(mut self, facts: Facts) -> Self {
+        self.facts = Some(facts);
+        self
+    }
+
+    /// Merge these vars on top of host vars, each layer overriding the ones before.
+    /// Layers are named after where they came from, e.g. `--var`.
+    pub fn with_var_overrides(mut self, overrides: Vec<(String, Map<String, Value>)>) -> Self {
+        self.var_overrides = overrides;
+        self
+    }
+
+    /// Deploy as `profile`: `hosts/<profile>@<hostname>.toml` takes precedence over the
+    /// hostname's own config, and files are staged separately from other profiles.
+    /// Without a profile, the current user's name is tried as `<user>@<hostname>`.
+    pub fn with_profile(mut self, profile: Option<String>) -> Result<Self> {
+        if let Some(ref name) = profile {
+            crate::state::validate_profile(name)?;
+            // A sibling of `.staged/`, so no profile's files end up inside another's tree
+            self.staging_dir = self.loader.base_dir().join(format!(".staged-{name}"));
+        }
+        self.profile = profile;
+        Ok(self)
+    }
+
     pub fn loader(&self) -> &ConfigLoader {
         &self.loader
     }
@@ -84,69 +180,388 @@ impl Orchestrator {
             .unwrap_or(DeployStrategy::Stage)
     }
 
-    pub fn deploy(&mut self, hostname: &str, dry_run: bool, force: bool) -> Result<DeployReport> {
-        let mut report = DeployReport::default();
-        let mut state = self
-            .state_dir
-            .as_ref()
-            .map(|d| DeployState::new(d))
-            .unwrap_or_default();
-
-        let effective_staging_dir = if self.system_mode {
-            self.state_dir
-                .as_ref()
-                .map(|d| d.join(".staged"))
-                .unwrap_or_else(|| self.staging_dir.clone())
-        } else {
-            self.staging_dir.clone()
+    /// Whether an action of a `link` package can be symlinked straight to its source.
+    fn is_linkable(&self, pkg_name: &str, action: &scanner::FileAction) -> bool {
+        if !matches!(action.kind, scanner::EntryKind::Base | scanner::EntryKind::Directory) {
+            return false;
+        }
+        let Some(pkg_config) = self.loader.root().packages.get(pkg_name) else {
+            return true;
         };
+        let rel_path_str = action.target_rel_path.to_str().unwrap_or("");
+        let resolved = metadata::resolve_metadata(pkg_config, rel_path_str);
+        resolved.owner.is_none() && resolved.group.is_none() && resolved.mode.is_none()
+    }
 
-        // 1. Load host config
-        let host = self
-            .loader
-            .load_host(hostname)
-            .with_context(|| format!("failed to load host config for '{hostname}'"))?;
+    /// The machine `##os.`, `##user.`, ... overrides are selected for; the profile
+    /// stands in for the user.
+    fn machine(&self, facts: &Facts) -> scanner::Machine {
+        let mut machine = scanner::Machine::from_facts(facts);
+        if let Some(ref profile) = self.profile {
+            machine.user = Some(profile.clone());
+        }
+        machine
+    }
 
-        // 2. Load roles and collect packages + merge vars
-        let mut all_requested_packages: Vec<String> = Vec::new();
-        let mut merged_vars: Map<String, Value> = Map::new();
+    /// The host config for `hostname`, preferring one for the profile (or user).
+    fn load_host(&self, hostname: &str, facts: &Facts) -> Result<HostConfig> {
+        let user = self.profile.as_deref().or(Some(facts.username.as_str()).filter(|u| !u.is_empty()));
+        self.loader
+            .load_host_for(user, hostname)
+            .with_context(|| format!("failed to load host config for '{hostname}'"))
+    }
 
-        for role_name in &host.roles {
-            let role = self
-                .loader
-                .load_role(role_name)
-                .with_context(|| format!("failed to load role '{role_name}'"))?;
+    /// The merged vars for `hostname`: the `[dotm.vars]` defaults, its roles' vars
+    /// in order, the host's own, then any overrides.
+    pub fn host_vars(&self, hostname: &str) -> Result<HostVars> {
+        let facts = self.facts.clone().unwrap_or_else(Facts::gather);
+        let host = self.load_host(hostname, &facts)?;
+        let roles = resolver::resolve_roles(&self.loader, &host.roles)?;
+        Ok(self.merge_host_vars(hostname, &host, &roles))
+    }
 
-            for pkg in &role.packages {
-                if !all_requested_packages.contains(pkg) {
-                    all_requested_packages.push(pkg.clone());
-                }
+    /// The vars seen by templates of `package` on `hostname`, including the package's
+    /// defaults and those declared in its schema.
+    pub fn package_vars(&self, host_vars: &HostVars, package: &str) -> HostVars {
+        match self.loader.root().packages.get(package) {
+            Some(pkg_config) => {
+                let defaults = vars::merge_vars(&vars::schema_defaults(&pkg_config.schema), &pkg_config.vars);
+                host_vars.with_package_defaults(package, &defaults)
             }
+            None => host_vars.clone(),
+        }
+    }
 
-            merged_vars = vars::merge_vars(&merged_vars, &role.vars);
+    /// Validate the vars of `hostname` against the schemas of the packages it deploys:
+    /// missing required vars, wrong types, disallowed values and undeclared vars.
+    pub fn check_vars(&self, hostname: &str) -> Result<Vec<String>> {
+        let facts = self.facts.clone().unwrap_or_else(Facts::gather);
+        let plan = self.plan_host(hostname, &facts)?;
+
+        let mut errors = Vec::new();
+        let mut schemas = Vec::new();
+        for pkg_name in &plan.packages {
+            let Some(pkg_config) = self.loader.root().packages.get(pkg_name) else {
+                continue;
+            };
+            let pkg_vars = self.package_vars(&plan.vars, pkg_name);
+            errors.extend(vars::validate_schema(
+                pkg_name,
+                &pkg_config.schema,
+                &pkg_vars.vars,
+                &pkg_vars.provenance,
+            ));
+            schemas.push(&pkg_config.schema);
+        }
+        errors.extend(vars::unknown_vars(&schemas, &plan.vars.provenance));
+
+        Ok(errors.into_iter().map(|e| format!("host '{hostname}': {e}")).collect())
+    }
+
+    fn merge_host_vars(&self, hostname: &str, host: &HostConfig, roles: &[(String, RoleConfig)]) -> HostVars {
+        let mut layers = vec![("[dotm.vars]".to_string(), self.loader.root().dotm.vars.clone())];
+        // Included roles are expanded first, so a role's vars override those it includes
+        for (role_name, role) in roles {
+            layers.push((format!("role '{role_name}'"), role.vars.clone()));
         }
+        // Host vars override role vars, and overrides from the command line override both
+        layers.push((format!("host '{hostname}'"), host.vars.clone()));
+        layers.extend(self.var_overrides.iter().cloned());
+
+        HostVars::merge(roles.iter().map(|(name, _)| name.clone()).collect(), layers)
+    }
+
+    /// Work out what deploying `hostname` involves: its roles (with includes
+    /// expanded), merged vars and the packages to deploy.
+    fn plan_host(&self, hostname: &str, facts: &Facts) -> Result<HostPlan> {
+        let host = self.load_host(hostname, facts)?;
+        let roles = resolver::resolve_roles(&self.loader, &host.roles)?;
+        let host_vars = self.merge_host_vars(hostname, &host, &roles);
+        let merged_vars = &host_vars.vars;
+        // Conditions test the host being deployed, which --host may set to another machine
+        let facts = &Facts {
+            hostname: hostname.to_string(),
+            ..facts.clone()
+        };
+
+        let mut all_requested_packages: Vec<String> = Vec::new();
 
-        // Host vars override role vars
-        merged_vars = vars::merge_vars(&merged_vars, &host.vars);
+        // Role package entries may be conditional; conditions see the merged vars
+        for (role_name, role) in &roles {
+            for pkg in &role.packages {
+                let selected = condition::evaluate(pkg.when.as_deref(), facts, merged_vars)
+                    .with_context(|| format!("role '{role_name}': invalid condition for package '{}'", pkg.name))?;
+                if selected && !all_requested_packages.contains(&pkg.name) {
+                    all_requested_packages.push(pkg.name.clone());
+                }
+            }
+        }
 
-        // 3. Resolve dependencies
+        // Resolve dependencies
         let requested_refs: Vec<&str> = all_requested_packages.iter().map(|s| s.as_str()).collect();
         let mut resolved = resolver::resolve_packages(self.loader.root(), &requested_refs)?;
 
-        // 3.5. Apply package filter if set
+        // Apply package filter if set
         if let Some(ref filter) = self.package_filter {
             let filter_refs: Vec<&str> = vec![filter.as_str()];
             let filtered = resolver::resolve_packages(self.loader.root(), &filter_refs)?;
             resolved.retain(|pkg| filtered.contains(pkg));
         }
 
-        // 4. Collect role names for override resolution
-        let role_names: Vec<&str> = host.roles.iter().map(|s| s.as_str()).collect();
+        // Skip packages whose own condition doesn't hold on this machine
+        let mut skipped = Vec::new();
+        for pkg_name in &resolved {
+            let Some(pkg_config) = self.loader.root().packages.get(pkg_name) else {
+                continue;
+            };
+            if !condition::evaluate(pkg_config.when.as_deref(), facts, merged_vars)
+                .with_context(|| format!("package '{pkg_name}': invalid condition"))?
+            {
+                skipped.push(pkg_name.clone());
+            }
+        }
+        resolved.retain(|pkg| !skipped.contains(pkg));
+
+        Ok(HostPlan {
+            vars: host_vars,
+            packages: resolved,
+            skipped,
+        })
+    }
+
+    /// Where a package's files are deployed: its `target`, or the orchestrator's target directory.
+    fn package_target(&self, pkg_name: &str) -> Result<PathBuf> {
+        match self.loader.root().packages.get(pkg_name).and_then(|c| c.target.as_ref()) {
+            Some(target) => Ok(PathBuf::from(expand_path(target, Some(&format!("package '{pkg_name}'")))?)),
+            None => Ok(self.target_dir.clone()),
+        }
+    }
+
+    /// How `pkg_name` is scanned: override selection, ignore rules and target names.
+    fn ignore_rules(&self, pkg_name: &str) -> Result<scanner::IgnoreRules> {
+        let globs = self.loader.root().packages.get(pkg_name).map_or(&[][..], |c| &c.ignore[..]);
+        scanner::IgnoreRules::load(self.loader.base_dir(), &self.loader.packages_dir().join(pkg_name), globs)
+            .with_context(|| format!("package '{pkg_name}'"))
+    }
+
+    /// A template engine with the shared partials and the templates of `packages`,
+    /// leaving out files the packages ignore. Templates of other packages are never
+    /// parsed, so a broken one can't get in the way.
+    fn template_engine<'a>(
+        &self,
+        packages: impl IntoIterator<Item = &'a String>,
+        secrets: Option<Arc<SecretStore>>,
+    ) -> Result<template::TemplateEngine> {
+        let packages_dir = self.loader.packages_dir();
+        let mut files = Vec::new();
+        for pkg_name in packages {
+            let pkg_dir = packages_dir.join(pkg_name);
+            if pkg_dir.is_dir() {
+                files.extend(scanner::template_files(&pkg_dir, &self.ignore_rules(pkg_name)?)?);
+            }
+        }
+        template::TemplateEngine::load(self.loader.base_dir(), &packages_dir, &files, secrets)
+    }
+
+    /// Parse the shared partials and the templates of every declared package, reporting
+    /// all that don't parse as [`template::TemplateErrors`].
+    pub fn check_template_syntax(&self) -> Result<()> {
+        self.template_engine(self.loader.root().packages.keys(), None)?;
+        Ok(())
+    }
+
+    fn scan_options(&self, pkg_name: &str, facts: &Facts) -> Result<scanner::ScanOptions> {
+        let pkg_config = self.loader.root().packages.get(pkg_name);
+        let ignore = self.ignore_rules(pkg_name)?;
+
+        let mut rename = HashMap::new();
+        for (source, target) in pkg_config.into_iter().flat_map(|c| &c.rename) {
+            if !config::is_contained_path(source) || !config::is_contained_path(target) {
+                bail!("package '{pkg_name}': invalid rename '{source}' = '{target}': both must be relative paths without '..'");
+            }
+            rename.insert(PathBuf::from(source), PathBuf::from(target));
+        }
+
+        Ok(scanner::ScanOptions {
+            machine: self.machine(facts),
+            ignore,
+            dot_prefix: pkg_config.and_then(|c| c.dot_prefix).unwrap_or(self.loader.root().dotm.dot_prefix),
+            rename,
+            ..Default::default()
+        })
+    }
+
+    /// Scan a package with its ignore rules and renames, to catch files that would
+    /// deploy to the same target on any host.
+    pub fn check_package_files(&self, pkg_name: &str) -> Result<()> {
+        let facts = self.facts.clone().unwrap_or_else(Facts::gather);
+        let options = self.scan_options(pkg_name, &facts)?;
+        scanner::scan_package_with(&self.loader.packages_dir().join(pkg_name), "", &[], &options)?;
+        Ok(())
+    }
+
+    /// Render every template `hostname` would deploy, without writing anything, and
+    /// return all the errors instead of stopping at the first. Secrets render as
+    /// placeholders.
+    pub fn check_templates(&self, hostname: &str) -> Result<Vec<anyhow::Error>> {
+        let facts = self.facts.clone().unwrap_or_else(Facts::gather);
+        let plan = self.plan_host(hostname, &facts)?;
+        let role_names: Vec<&str> = plan.vars.roles.iter().map(|s| s.as_str()).collect();
+        let packages_dir = self.loader.packages_dir();
+        let mut templates = self.template_engine(&plan.packages, None)?.with_secret_placeholders();
+
+        let mut errors = Vec::new();
+        for pkg_name in &plan.packages {
+            let pkg_dir = packages_dir.join(pkg_name);
+            if !pkg_dir.is_dir() {
+                continue;
+            }
+            let scan_options = self.scan_options(pkg_name, &facts)?;
+            let pkg_target = self.package_target(pkg_name)?;
+            let pkg_vars = self.package_vars(&plan.vars, pkg_name);
+            let actions = scanner::scan_package_with(&pkg_dir, hostname, &role_names, &scan_options)?;
+            for action in actions.iter().filter(|a| a.kind == scanner::EntryKind::Template) {
+                let dotm = template::DotmContext {
+                    facts: &facts,
+                    host: hostname,
+                    roles: &role_names,
+                    package: pkg_name,
+                    target: &pkg_target.join(&action.target_rel_path),
+                    target_dir: &pkg_target,
+                };
+                if let Err(e) = templates.render_file(&action.source, &pkg_vars.vars, Some(&dotm)) {
+                    errors.push(with_var_candidates(e, &pkg_vars.provenance));
+                }
+            }
+        }
+        Ok(errors)
+    }
+
+    /// Targets of `hostname`'s packages where two override variants match equally
+    /// well, so which one is deployed comes down to file names.
+    pub fn check_overrides(&self, hostname: &str) -> Result<Vec<String>> {
+        let facts = self.facts.clone().unwrap_or_else(Facts::gather);
+        let plan = self.plan_host(hostname, &facts)?;
+        let role_names: Vec<&str> = plan.vars.roles.iter().map(|s| s.as_str()).collect();
+        let base_dir = self.loader.base_dir();
+
+        let mut warnings = Vec::new();
+        for pkg_name in &plan.packages {
+            let pkg_dir = self.loader.packages_dir().join(pkg_name);
+            if !pkg_dir.is_dir() {
+                continue;
+            }
+            let scan_options = self.scan_options(pkg_name, &facts)?;
+            for tie in scanner::override_ties(&pkg_dir, hostname, &role_names, &scan_options)? {
+                let variants: Vec<String> = tie
+                    .variants
+                    .iter()
+                    .map(|v| v.strip_prefix(base_dir).unwrap_or(v).display().to_string())
+                    .collect();
+                warnings.push(format!(
+                    "host '{hostname}': overrides for {} are equally specific: {}; using the first",
+                    tie.target_rel_path.display(),
+                    variants.join(", ")
+                ));
+            }
+        }
+        Ok(warnings)
+    }
+
+    /// Deploy all packages for `hostname`. Deploys are transactional: if anything
+    /// fails, every filesystem change made by this run is rolled back.
+    pub fn deploy(&mut self, hostname: &str, dry_run: bool, force: bool) -> Result<DeployReport> {
+        let mut journal = Journal::new();
+        match self.deploy_journaled(hostname, dry_run, force, &mut journal) {
+            Ok(report) => Ok(report),
+            Err(e) => match journal.rollback() {
+                Ok(0) => Err(e),
+                Ok(count) => Err(e.context(format!("deploy failed, rolled back {count} change(s)"))),
+                Err(rollback_err) => Err(e.context(format!(
+                    "deploy failed and rollback was incomplete: {rollback_err:#}"
+                ))),
+            },
+        }
+    }
+
+    fn deploy_journaled(
+        &mut self,
+        hostname: &str,
+        dry_run: bool,
+        force: bool,
+        journal: &mut Journal,
+    ) -> Result<DeployReport> {
+        let duplicates = self.loader.duplicate_packages();
+        if !duplicates.is_empty() {
+            bail!("{}", duplicates.join("\n"));
+        }
+        let mut report = DeployReport::default();
+        let mut state = self
+            .state_dir
+            .as_ref()
+            .map(|d| DeployState::new(d))
+            .unwrap_or_default();
+
+        let effective_staging_dir = if self.system_mode {
+            self.state_dir
+                .as_ref()
+                .map(|d| d.join(".staged"))
+                .unwrap_or_else(|| self.staging_dir.clone())
+        } else {
+            self.staging_dir.clone()
+        };
+
+        // 1-4. Resolve the host's roles, vars and packages
+        let facts = self.facts.clone().unwrap_or_else(Facts::gather);
+        let plan = self.plan_host(hostname, &facts)?;
+        report.skipped_packages = plan
+            .skipped
+            .iter()
+            .filter(|pkg| self.loader.root().packages[*pkg].system == self.system_mode)
+            .cloned()
+            .collect();
+        let override_layers: Vec<&str> = self.var_overrides.iter().map(|(layer, _)| layer.as_str()).collect();
+        report.var_overrides = vars::entries(&plan.vars.provenance, None)
+            .into_iter()
+            .filter(|entry| override_layers.contains(&entry.source.as_str()))
+            .collect();
+        let resolved = plan.packages;
+        let role_names: Vec<&str> = plan.vars.roles.iter().map(|s| s.as_str()).collect();
 
         // Phase 1: Scan all packages and collect pending actions
         let packages_dir = self.loader.packages_dir();
         let mut pending: Vec<PendingAction> = Vec::new();
 
+        // All templates of the deployed packages share one Tera instance so they can
+        // include each other. A dry run never reads secrets (no decrypting, no
+        // prompting for a passphrase).
+        let deployed = resolved
+            .iter()
+            .filter(|pkg| self.loader.root().packages.get(*pkg).is_some_and(|c| c.system) == self.system_mode);
+        let mut templates = if dry_run {
+            self.template_engine(deployed, None)?.with_secret_placeholders()
+        } else {
+            let secrets = self
+                .loader
+                .root()
+                .dotm
+                .secrets
+                .as_ref()
+                .map(|c| SecretStore::from_config(c, self.loader.base_dir()))
+                .transpose()
+                .context("failed to set up secrets backend")?
+                .map(Arc::new);
+            self.template_engine(deployed, secrets)?
+        };
+
+        let encryption = &self.loader.root().dotm.encryption;
+        let identity = encryption
+            .identity
+            .as_deref()
+            .map(|i| expand_path(i, Some("encryption identity")))
+            .transpose()?
+            .map(|i| self.loader.base_dir().join(i));
+
         for pkg_name in &resolved {
             // Filter packages based on system mode
             let is_system = self
@@ -166,27 +581,69 @@ impl Orchestrator {
                 continue;
             }
 
-            let actions = scanner::scan_package(&pkg_dir, hostname, &role_names)?;
+            let pkg_target = self.package_target(pkg_name)?;
 
-            let pkg_target = if let Some(pkg_config) = self.loader.root().packages.get(pkg_name) {
-                if let Some(ref target) = pkg_config.target {
-                    PathBuf::from(expand_path(target, Some(&format!("package '{pkg_name}'")))?)
-                } else {
-                    self.target_dir.clone()
-                }
-            } else {
-                self.target_dir.clone()
+            let pkg_strategy = self.get_pkg_strategy(pkg_name);
+
+            let pkg_config = self.loader.root().packages.get(pkg_name);
+            if let Some(pkg_config) = pkg_config {
+                config::validate_fold(pkg_name, pkg_config)?;
+            }
+            let fold = pkg_config.map(|c| c.fold.clone()).unwrap_or_default();
+            let scan_options = scanner::ScanOptions {
+                fold: match fold {
+                    FoldConfig::Paths(ref paths) => paths.iter().map(PathBuf::from).collect(),
+                    FoldConfig::All(_) => Vec::new(),
+                },
+                fold_target: (fold == FoldConfig::All(true)).then(|| pkg_target.clone()),
+                staging_dir: Some(effective_staging_dir.clone()),
+                ..self.scan_options(pkg_name, &facts)?
             };
 
-            let strategy = self.get_pkg_strategy(pkg_name);
+            let (actions, invalid) = scanner::scan_package_checked(&pkg_dir, hostname, &role_names, &scan_options)?;
+            report
+                .invalid_overrides
+                .extend(invalid.iter().map(|e| format!("package '{pkg_name}': {e:#}")));
+            let pkg_vars = self.package_vars(&plan.vars, pkg_name);
 
             for action in actions {
-                let rendered = if action.kind == scanner::EntryKind::Template {
-                    let tmpl_content = std::fs::read_to_string(&action.source)
-                        .with_context(|| format!("failed to read template: {}", action.source.display()))?;
-                    Some(template::render_template(&tmpl_content, &merged_vars)?)
+                let (rendered, sensitive) = match action.kind {
+                    scanner::EntryKind::Template => {
+                        let dotm = template::DotmContext {
+                            facts: &facts,
+                            host: hostname,
+                            roles: &role_names,
+                            package: pkg_name,
+                            target: &pkg_target.join(&action.target_rel_path),
+                            target_dir: &pkg_target,
+                        };
+                        let output = templates
+                            .render_file(&action.source, &pkg_vars.vars, Some(&dotm))
+                            .map_err(|e| with_var_candidates(e, &pkg_vars.provenance))?;
+                        (Some(output.content.into_bytes()), output.uses_secrets)
+                    }
+                    scanner::EntryKind::Encrypted if !dry_run => {
+                        let plaintext = secrets::decrypt_file(
+                            &action.source,
+                            identity.as_deref(),
+                            encryption.decrypt_command.as_deref(),
+                        )?;
+                        (Some(plaintext), true)
+                    }
+                    scanner::EntryKind::Encrypted => (None, true),
+                    _ => (None, false),
+                };
+
+                // Encrypted files and templates using secrets are never written to
+                // .staged/, which is inside the repo. Linked packages still stage
+                // templates, overrides, and files with metadata overrides (applying
+                // those to the source would change the repo).
+                let strategy = if sensitive {
+                    DeployStrategy::Copy
+                } else if pkg_strategy == DeployStrategy::Link && !self.is_linkable(pkg_name, &action) {
+                    DeployStrategy::Stage
                 } else {
-                    None
+                    pkg_strategy
                 };
 
                 pending.push(PendingAction {
@@ -195,6 +652,7 @@ impl Orchestrator {
                     pkg_target: pkg_target.clone(),
                     rendered,
                     strategy,
+                    sensitive,
                 });
             }
         }
@@ -216,11 +674,29 @@ impl Orchestrator {
             }
         }
 
-        // Phase 3: Load existing state for drift detection
+        // Nothing else may be deployed inside a folded directory
+        for dir in pending.iter().filter(|p| p.action.kind == scanner::EntryKind::Directory) {
+            let dir_target = dir.pkg_target.join(&dir.action.target_rel_path);
+            if let Some(inner) = pending
+                .iter()
+                .find(|p| is_inside(&p.pkg_target.join(&p.action.target_rel_path), &dir_target))
+            {
+                bail!(
+                    "package '{}' deploys {} inside directory {} folded by package '{}'",
+                    inner.pkg_name,
+                    inner.action.target_rel_path.display(),
+                    dir.action.target_rel_path.display(),
+                    dir.pkg_name
+                );
+            }
+        }
+
+        // Phase 3: Load existing state for drift detection, holding the lock
+        // for the rest of a real deploy
         let existing_state = self
             .state_dir
             .as_ref()
-            .map(|d| DeployState::load(d))
+            .map(|d| if dry_run { DeployState::load(d) } else { DeployState::load_locked(d) })
             .transpose()?
             .unwrap_or_default();
 
@@ -230,6 +706,45 @@ impl Orchestrator {
             .map(|e| (e.staged.clone(), e.content_hash.as_str()))
             .collect();
 
+        // Phase 3.5: Entries replaced by folding or unfolding a directory. Their
+        // symlinks are removed up front so the new layout can be deployed, and
+        // they are not reported as orphans.
+        let new_targets: HashSet<PathBuf> = pending
+            .iter()
+            .map(|p| p.pkg_target.join(&p.action.target_rel_path))
+            .collect();
+        let new_folded: Vec<PathBuf> = pending
+            .iter()
+            .filter(|p| p.action.kind == scanner::EntryKind::Directory)
+            .map(|p| p.pkg_target.join(&p.action.target_rel_path))
+            .collect();
+        let mut superseded: HashSet<PathBuf> = HashSet::new();
+        for old_entry in existing_state.entries() {
+            let now_folded = new_folded.iter().any(|d| is_inside(&old_entry.target, d));
+            let now_unfolded = old_entry.kind == scanner::EntryKind::Directory
+                && new_targets.iter().any(|t| is_inside(t, &old_entry.target));
+            if !now_folded && !now_unfolded {
+                continue;
+            }
+            superseded.insert(old_entry.target.clone());
+            if dry_run {
+                continue;
+            }
+            journal.record(&old_entry.target)?;
+            if old_entry.has_staged_copy() {
+                journal.record(&old_entry.staged)?;
+            }
+            if old_entry.target.is_symlink() {
+                std::fs::remove_file(&old_entry.target)
+                    .with_context(|| format!("failed to remove symlink: {}", old_entry.target.display()))?;
+                crate::state::cleanup_empty_parents(&old_entry.target);
+            }
+            if now_unfolded && old_entry.has_staged_copy() {
+                crate::state::remove_managed(&old_entry.staged)
+                    .with_context(|| format!("failed to remove staged directory: {}", old_entry.staged.display()))?;
+            }
+        }
+
         // Phase 4: Deploy each action (with per-package hooks)
         let mut current_pkg: Option<String> = None;
         let mut skip_pkg: Option<String> = None;
@@ -289,7 +804,7 @@ impl Orchestrator {
                     // Drift detection: if staged file exists and was modified since last deploy
                     if staged_path.exists()
                         && let Some(&expected_hash) = existing_hashes.get(&staged_path) {
-                            let current_hash = hash::hash_file(&staged_path)?;
+                            let current_hash = hash::hash_path(&staged_path)?;
                             if current_hash != expected_hash && !force {
                                 eprintln!(
                                     "warning: {} has been modified since last deploy, skipping (use --force to overwrite)",
@@ -304,8 +819,9 @@ impl Orchestrator {
                         }
 
                     // Backup pre-existing file content and metadata before deploying
+                    // (existing directories are never replaced, so there is nothing to back up)
                     let (original_hash, original_owner, original_group, original_mode) =
-                        if !dry_run && target_path.exists() && !target_path.is_symlink() {
+                        if !dry_run && target_path.exists() && !target_path.is_symlink() && !target_path.is_dir() {
                             let content = std::fs::read(&target_path)?;
                             let hash = hash::hash_content(&content);
                             state.store_original(&hash, &content)?;
@@ -316,6 +832,13 @@ impl Orchestrator {
                             (None, None, None, None)
                         };
 
+                    // A conflicting target is left alone, so there is nothing to journal
+                    // (and an unmanaged directory may be large)
+                    if !dry_run && deployer::target_conflict(&target_path, force).is_none() {
+                        journal.record(&target_path)?;
+                        journal.record(&staged_path)?;
+                    }
+
                     let result = deployer::deploy_staged(
                         &p.action,
                         &effective_staging_dir,
@@ -323,17 +846,23 @@ impl Orchestrator {
                         dry_run,
                         force,
                         p.rendered.as_deref(),
+                        p.sensitive,
                     )?;
 
                     match result {
                         DeployResult::Created | DeployResult::Updated => {
                             let content_hash = if !dry_run {
-                                hash::hash_file(&staged_path)?
+                                hash::hash_path(&staged_path)?
                             } else {
                                 String::new()
                             };
 
-                            if !dry_run && self.state_dir.is_some() {
+                            // Folded directories are tracked by tree hash only
+                            if !dry_run
+                                && self.state_dir.is_some()
+                                && !p.sensitive
+                                && p.action.kind != scanner::EntryKind::Directory
+                            {
                                 let content = std::fs::read(&staged_path)?;
                                 state.store_deployed(&content_hash, &content)?;
                             }
@@ -371,6 +900,10 @@ impl Orchestrator {
                                     "",
                                 )
                             };
+                            // Files with secrets stay private unless a mode is set explicitly
+                            if !dry_run && p.sensitive && resolved.mode.is_none() {
+                                deployer::apply_permission_override(&staged_path, SENSITIVE_MODE)?;
+                            }
 
                             let abs_source = std::fs::canonicalize(&p.action.source)
                                 .unwrap_or_else(|_| p.action.source.clone());
@@ -389,6 +922,9 @@ impl Orchestrator {
                                 original_owner,
                                 original_group,
                                 original_mode,
+                                sensitive: p.sensitive,
+                                encrypted_hash: None,
+                                linked: false,
                             });
 
                             if matches!(result, DeployResult::Updated) {
@@ -406,6 +942,83 @@ impl Orchestrator {
                         _ => {}
                     }
                 }
+                DeployStrategy::Link => {
+                    // No drift detection: the target is the source, so edits land in the repo
+
+                    // Backup pre-existing file content and metadata before deploying
+                    let (original_hash, original_owner, original_group, original_mode) =
+                        if !dry_run && target_path.exists() && !target_path.is_symlink() && !target_path.is_dir() {
+                            let content = std::fs::read(&target_path)?;
+                            let hash = hash::hash_content(&content);
+                            state.store_original(&hash, &content)?;
+
+                            let (owner, group, mode) = metadata::read_file_metadata(&target_path)?;
+                            (Some(hash), Some(owner), Some(group), Some(mode))
+                        } else {
+                            (None, None, None, None)
+                        };
+
+                    if !dry_run && deployer::target_conflict(&target_path, force).is_none() {
+                        journal.record(&target_path)?;
+                    }
+
+                    let result = deployer::deploy_link(&p.action, &p.pkg_target, dry_run, force)?;
+
+                    match result {
+                        DeployResult::Created | DeployResult::Updated => {
+                            // Drop the staged copy left over from a previous stage deploy
+                            if let Some(old_entry) = existing_state
+                                .entries()
+                                .iter()
+                                .find(|e| e.target == target_path && e.has_staged_copy())
+                            {
+                                journal.record(&old_entry.staged)?;
+                                let _ = crate::state::remove_managed(&old_entry.staged);
+                                crate::state::cleanup_empty_parents(&old_entry.staged);
+                            }
+
+                            let abs_source = std::fs::canonicalize(&p.action.source)
+                                .unwrap_or_else(|_| p.action.source.clone());
+                            let content_hash = if !dry_run {
+                                hash::hash_path(&abs_source)?
+                            } else {
+                                String::new()
+                            };
+
+                            state.record(DeployEntry {
+                                target: target_path.clone(),
+                                staged: abs_source.clone(), // for link strategy, staged = source
+                                source: abs_source,
+                                content_hash,
+                                original_hash,
+                                kind: p.action.kind,
+                                package: p.pkg_name.clone(),
+                                owner: None,
+                                group: None,
+                                mode: None,
+                                original_owner,
+                                original_group,
+                                original_mode,
+                                sensitive: false,
+                                encrypted_hash: None,
+                                linked: true,
+                            });
+
+                            if matches!(result, DeployResult::Updated) {
+                                report.updated.push(target_path);
+                            } else {
+                                report.created.push(target_path);
+                            }
+                        }
+                        DeployResult::Conflict(msg) => {
+                            report.conflicts.push((target_path, msg));
+                        }
+                        DeployResult::DryRun => {
+                            report.dry_run_actions.push(target_path);
+                        }
+                        _ => {}
+                    }
+                }
                 DeployStrategy::Copy => {
                     // Drift detection: if target exists and was modified since last deploy
                     if target_path.exists() {
@@ -438,12 +1051,17 @@ impl Orchestrator {
                             (None, None, None, None)
                         };
 
+                    if !dry_run && deployer::target_conflict(&target_path, force).is_none() {
+                        journal.record(&target_path)?;
+                    }
+
                     let result = deployer::deploy_copy(
                         &p.action,
                         &p.pkg_target,
                         dry_run,
                         force,
                         p.rendered.as_deref(),
+                        p.sensitive,
                     )?;
 
                     match result {
@@ -454,16 +1072,33 @@ impl Orchestrator {
                                 String::new()
                             };
 
-                            if !dry_run && self.state_dir.is_some() {
+                            if !dry_run && self.state_dir.is_some() && !p.sensitive {
                                 let content = std::fs::read(&target_path)?;
                                 state.store_deployed(&content_hash, &content)?;
                             }
 
+                            // Keep the ciphertext (never the plaintext) of encrypted files
+                            let encrypted_hash = if !dry_run
+                                && self.state_dir.is_some()
+                                && p.action.kind == scanner::EntryKind::Encrypted
+                            {
+                                let ciphertext = std::fs::read(&p.action.source)?;
+                                let hash = hash::hash_content(&ciphertext);
+                                state.store_deployed(&hash, &ciphertext)?;
+                                Some(hash)
+                            } else {
+                                None
+                            };
+
                             // Resolve and apply metadata
                             let resolved = if !dry_run {
                                 if let Some(pkg_config) = self.loader.root().packages.get(&p.pkg_name) {
                                     let rel_path_str = p.action.target_rel_path.to_str().unwrap_or("");
-                                    let resolved = metadata::resolve_metadata(pkg_config, rel_path_str);
+                                    let mut resolved = metadata::resolve_metadata(pkg_config, rel_path_str);
+                                    // Encrypted files are always 600, whatever the permissions table says
+                                    if p.action.kind == scanner::EntryKind::Encrypted {
+                                        resolved.mode = None;
+                                    }
 
                                     if resolved.owner.is_some() || resolved.group.is_some() {
                                         if let Err(e) = metadata::apply_ownership(
@@ -492,6 +1127,10 @@ impl Orchestrator {
                                     "",
                                 )
                             };
+                            // Files with secrets stay private unless a mode is set explicitly
+                            if !dry_run && p.sensitive && resolved.mode.is_none() {
+                                deployer::apply_permission_override(&target_path, SENSITIVE_MODE)?;
+                            }
 
                             let abs_source = std::fs::canonicalize(&p.action.source)
                                 .unwrap_or_else(|_| p.action.source.clone());
@@ -510,6 +1149,9 @@ impl Orchestrator {
                                 original_owner,
                                 original_group,
                                 original_mode,
+                                sensitive: p.sensitive,
+                                encrypted_hash,
+                                linked: false,
                             });
 
                             if matches!(result, DeployResult::Updated) {
@@ -549,22 +1191,21 @@ impl Orchestrator {
 
         // Phase 4.5: Detect orphaned files
         if self.state_dir.is_some() {
-            let new_targets: std::collections::HashSet<PathBuf> = pending
-                .iter()
-                .map(|p| p.pkg_target.join(&p.action.target_rel_path))
-                .collect();
-
             for old_entry in existing_state.entries() {
-                if !new_targets.contains(&old_entry.target) {
+                if !new_targets.contains(&old_entry.target) && !superseded.contains(&old_entry.target) {
                     report.orphaned.push(old_entry.target.clone());
 
                     if !dry_run && self.loader.root().dotm.auto_prune {
+                        journal.record(&old_entry.target)?;
+                        if old_entry.has_staged_copy() {
+                            journal.record(&old_entry.staged)?;
+                        }
                         if old_entry.target.is_symlink() || old_entry.target.exists() {
                             let _ = std::fs::remove_file(&old_entry.target);
                             crate::state::cleanup_empty_parents(&old_entry.target);
                         }
-                        if old_entry.staged != old_entry.target && old_entry.staged.exists() {
-                            let _ = std::fs::remove_file(&old_entry.staged);
+                        if old_entry.has_staged_copy() && old_entry.staged.exists() {
+                            let _ = crate::state::remove_managed(&old_entry.staged);
                             crate::state::cleanup_empty_parents(&old_entry.staged);
                         }
                         report.pruned.push(old_entry.target.clone());
@@ -573,23 +1214,41 @@ impl Orchestrator {
             }
         }
 
-        // Phase 5: Save state
-        if !dry_run && self.state_dir.is_some() {
+        // Phase 5: Save state and record it as a new generation
+        if !dry_run && let Some(ref state_dir) = self.state_dir {
             state.save()?;
+
+            let commit = crate::git::GitRepo::open(self.loader.base_dir()).and_then(|r| r.head_commit());
+            if let Err(e) = crate::history::record(state_dir, hostname, commit, None, state.entries()) {
+                eprintln!("warning: failed to record deploy generation: {e:#}");
+            }
+
+            let settings = &self.loader.root().dotm;
+            if settings.auto_gc {
+                if let Err(e) = crate::gc::collect(&state, settings.keep_generations, false) {
+                    eprintln!("warning: garbage collection failed: {e:#}");
+                }
+            }
         }
 
-        // Warn if .staged/ is not in .gitignore (only relevant for user-mode)
+        // Warn if the staging dir is not in .gitignore (only relevant for user-mode)
         if !dry_run && !self.system_mode {
+            let staged_name = self.staging_dir.file_name().unwrap_or_default().to_string_lossy().into_owned();
             let gitignore_path = self.loader.base_dir().join(".gitignore");
+            let ignores_staged = |line: &str| {
+                let pattern = line.trim().trim_start_matches('/').trim_end_matches('/');
+                pattern == staged_name
+                    || pattern.strip_suffix('*').is_some_and(|prefix| staged_name.starts_with(prefix))
+            };
             let staged_ignored = if gitignore_path.exists() {
                 std::fs::read_to_string(&gitignore_path)
-                    .map(|c| c.lines().any(|l| l.trim() == ".staged" || l.trim() == ".staged/"))
+                    .map(|c| c.lines().any(ignores_staged))
                     .unwrap_or(false)
             } else {
                 false
             };
             if !staged_ignored {
-                eprintln!("warning: '.staged/' is not in your .gitignore — add it to avoid committing staged files");
+                eprintln!("warning: '{staged_name}/' is not in your .gitignore — add it to avoid committing staged files");
             }
         }
 
@@ -597,6 +1256,42 @@ impl Orchestrator {
     }
 }
 
+/// Serialize conflicts as `{"path": ..., "reason": ...}` objects rather than pairs.
+fn serialize_conflicts<S: serde::Serializer>(
+    conflicts: &[(PathBuf, String)],
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    use serde::ser::SerializeSeq;
+
+    #[derive(serde::Serialize)]
+    struct Conflict<'a> {
+        path: &'a Path,
+        reason: &'a str,
+    }
+
+    let mut seq = serializer.serialize_seq(Some(conflicts.len()))?;
+    for (path, reason) in conflicts {
+        seq.serialize_element(&Conflict { path, reason })?;
+    }
+    seq.end()
+}
+
+/// Whether `path` lies strictly inside `dir`.
+fn is_inside(path: &Path, dir: &Path) -> bool {
+    path != dir && path.starts_with(dir)
+}
+
+/// Point template errors about a missing variable at similarly named vars that are set.
+fn with_var_candidates(err: anyhow::Error, provenance: &vars::Provenance) -> anyhow::Error {
+    match err.downcast::<template::TemplateError>() {
+        Ok(mut template_err) => {
+            template_err.add_candidates(provenance);
+            template_err.into()
+        }
+        Err(err) => err,
+    }
+}
+
 /// Expand shell variables and tilde in a path string.
 /// Errors if a referenced environment variable is not defined.
 pub fn expand_path(path: &str, context: Option<&str>) -> Result<String> {
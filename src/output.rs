@@ -0,0 +1,97 @@
+use crate::git::GitSummary;
+use crate::list::{HostSummary, HostTree, PackageSummary, RoleSummary};
+use crate::loader::ResolvedHost;
+use crate::orchestrator::DeployReport;
+use crate::status::PackageStatus;
+use crate::vars::VarEntry;
+use anyhow::Result;
+use serde::Serialize;
+
+/// Version of the JSON documents printed with `--format json`. Bumped on any
+/// incompatible change (removed or renamed fields); new fields may be added freely.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Envelope<'a, T: Serialize> {
+    schema: &'a str,
+    version: u32,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+/// Render `data` as a pretty-printed JSON document tagged with its schema name and version.
+pub fn to_json<T: Serialize>(schema: &str, data: &T) -> Result<String> {
+    Ok(serde_json::to_string_pretty(&Envelope {
+        schema,
+        version: SCHEMA_VERSION,
+        data,
+    })?)
+}
+
+/// `dotm status` (schema `dotm.status`).
+#[derive(Serialize)]
+pub struct StatusDocument<'a> {
+    pub git: Option<&'a GitSummary>,
+    pub total: usize,
+    pub modified: usize,
+    pub missing: usize,
+    pub packages: &'a [PackageStatus],
+}
+
+/// `dotm deploy` (schema `dotm.deploy`).
+#[derive(Serialize)]
+pub struct DeployDocument<'a> {
+    pub dry_run: bool,
+    #[serde(flatten)]
+    pub report: &'a DeployReport,
+}
+
+/// `dotm list packages` (schema `dotm.packages`).
+#[derive(Serialize)]
+pub struct PackagesDocument<'a> {
+    pub packages: &'a [PackageSummary],
+}
+
+/// `dotm list roles` (schema `dotm.roles`).
+#[derive(Serialize)]
+pub struct RolesDocument<'a> {
+    pub roles: &'a [RoleSummary],
+}
+
+/// `dotm list hosts` (schema `dotm.hosts`).
+#[derive(Serialize)]
+pub struct HostsDocument<'a> {
+    pub hosts: &'a [HostSummary],
+    /// The host file this machine (or `--host`) resolves to
+    pub resolved: Option<&'a ResolvedHost>,
+}
+
+/// `dotm list hosts --tree` (schema `dotm.tree`).
+#[derive(Serialize)]
+pub struct TreeDocument<'a> {
+    pub hosts: &'a [HostTree],
+}
+
+/// `dotm vars` (schema `dotm.vars`).
+#[derive(Serialize)]
+pub struct VarsDocument<'a> {
+    pub host: &'a str,
+    pub roles: &'a [String],
+    pub vars: &'a [VarEntry],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelope_carries_schema_and_version() {
+        let report = DeployReport::default();
+        let json = to_json("dotm.deploy", &DeployDocument { dry_run: true, report: &report }).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["schema"], "dotm.deploy");
+        assert_eq!(value["version"], SCHEMA_VERSION);
+        assert_eq!(value["dry_run"], true);
+        assert!(value["conflicts"].as_array().unwrap().is_empty());
+    }
+}
@@ -1,5 +1,6 @@
-use crate::config::RootConfig;
-use anyhow::{Result, bail};
+use crate::config::{RoleConfig, RootConfig};
+use crate::loader::ConfigLoader;
+use anyhow::{Context, Result, bail};
 use std::collections::HashSet;
 
 /// Resolve a list of requested packages into a fully-expanded, dependency-ordered list.
@@ -50,3 +51,48 @@ fn resolve_one(
     resolved.push(pkg.to_string());
     Ok(())
 }
+
+/// Expand a host's roles with the roles they include, loading each once.
+/// Included roles come before the roles that include them, so later roles in
+/// the result take precedence when merging vars and picking overrides.
+/// Circular includes produce an error.
+pub fn resolve_roles(loader: &ConfigLoader, roles: &[String]) -> Result<Vec<(String, RoleConfig)>> {
+    let mut resolved: Vec<(String, RoleConfig)> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+
+    for role in roles {
+        resolve_role(loader, role, &mut resolved, &mut seen, &mut Vec::new())?;
+    }
+
+    Ok(resolved)
+}
+
+fn resolve_role(
+    loader: &ConfigLoader,
+    role: &str,
+    resolved: &mut Vec<(String, RoleConfig)>,
+    seen: &mut HashSet<String>,
+    stack: &mut Vec<String>,
+) -> Result<()> {
+    if seen.contains(role) {
+        return Ok(());
+    }
+
+    if stack.contains(&role.to_string()) {
+        stack.push(role.to_string());
+        bail!("circular role include detected: {}", stack.join(" -> "));
+    }
+
+    let config = loader
+        .load_role(role)
+        .with_context(|| format!("failed to load role '{role}'"))?;
+    stack.push(role.to_string());
+    for include in &config.includes {
+        resolve_role(loader, include, resolved, seen, stack)?;
+    }
+    stack.pop();
+
+    seen.insert(role.to_string());
+    resolved.push((role.to_string(), config));
+    Ok(())
+}
@@ -1,4 +1,5 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
@@ -7,10 +8,14 @@ use std::path::{Path, PathBuf};
 pub enum EntryKind {
     /// Plain base file — deployed as a symlink
     Base,
-    /// Host or role override — deployed as a copy
+    /// Override variant (`##host.`, `##role.`, ...) — deployed as a copy
     Override,
     /// Tera template — rendered and written as a file
     Template,
+    /// Encrypted file (`.age`) — decrypted and written as a private copy
+    Encrypted,
+    /// Folded directory — deployed as a single symlink to the whole directory
+    Directory,
 }
 
 /// Describes what to do with a single file during deployment.
@@ -20,56 +25,487 @@ pub struct FileAction {
     pub source: PathBuf,
     /// The relative path where this file should be deployed (relative to target dir)
     pub target_rel_path: PathBuf,
-    /// What kind of entry this is (base, override, or template)
+    /// What kind of entry this is (base, override, template, ...)
     pub kind: EntryKind,
 }
 
+/// Per-package options that change how a package directory is scanned.
+#[derive(Debug, Default)]
+pub struct ScanOptions {
+    /// Directories (relative to the package) deployed as a single symlink instead of
+    /// being recursed into. They may only contain plain files.
+    pub fold: Vec<PathBuf>,
+    /// Fold every directory of plain files that doesn't exist yet under this target
+    /// directory (GNU stow-style tree folding).
+    pub fold_target: Option<PathBuf>,
+    /// Staging directory; targets that are symlinks into it were folded by a
+    /// previous deploy and stay folded.
+    pub staging_dir: Option<PathBuf>,
+    /// What `##os.`, `##distro.`, `##arch.` and `##user.` overrides are matched against
+    pub machine: Machine,
+    /// Files and directories to skip
+    pub ignore: IgnoreRules,
+    /// Deploy `dot-` prefixed files and directories with a leading `.` instead
+    /// (`dot-config/` → `.config/`)
+    pub dot_prefix: bool,
+    /// Target paths for files deployed under another name, by their path in the
+    /// package (without override suffix or `.tera`/`.age` extension)
+    pub rename: HashMap<PathBuf, PathBuf>,
+}
+
+/// Name of the ignore files, at the root of the dotfiles repo and of each package.
+pub const IGNORE_FILE: &str = ".dotmignore";
+
+/// Package files that are never deployed: the repo's `.dotmignore`, then the
+/// package's own `.dotmignore`, then its `ignore` globs, all in gitignore syntax and
+/// relative to the package. Later rules win, so a package can re-include
+/// (`!README.md`) what the repo ignores.
+#[derive(Debug, Clone)]
+pub struct IgnoreRules(Gitignore);
+
+impl Default for IgnoreRules {
+    fn default() -> Self {
+        IgnoreRules(Gitignore::empty())
+    }
+}
+
+impl IgnoreRules {
+    pub fn load(base_dir: &Path, pkg_dir: &Path, globs: &[String]) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(pkg_dir);
+        for file in [base_dir.join(IGNORE_FILE), pkg_dir.join(IGNORE_FILE)] {
+            if !file.is_file() {
+                continue;
+            }
+            let content = std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read ignore file: {}", file.display()))?;
+            for line in content.lines() {
+                builder
+                    .add_line(Some(file.clone()), line)
+                    .with_context(|| format!("invalid pattern '{line}' in {}", file.display()))?;
+            }
+        }
+        for glob in globs {
+            builder
+                .add_line(None, glob)
+                .with_context(|| format!("invalid ignore pattern '{glob}'"))?;
+        }
+        Ok(Self(builder.build().context("failed to build ignore rules")?))
+    }
+
+    /// Whether `rel_path`, relative to the package, is ignored itself or inside an
+    /// ignored directory.
+    pub fn is_ignored(&self, rel_path: &Path, is_dir: bool) -> bool {
+        self.0.matched_path_or_any_parents(rel_path, is_dir).is_ignore()
+    }
+}
+
+/// The machine overrides are selected for. Unset fields never match a selector.
+#[derive(Debug, Default, Clone)]
+pub struct Machine {
+    pub os: Option<String>,
+    pub distro: Option<String>,
+    pub arch: Option<String>,
+    pub user: Option<String>,
+}
+
+impl Machine {
+    pub fn from_facts(facts: &crate::facts::Facts) -> Self {
+        let non_empty = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+        Self {
+            os: non_empty(&facts.os),
+            distro: facts.distro.clone(),
+            arch: non_empty(&facts.arch),
+            user: non_empty(&facts.username),
+        }
+    }
+}
+
+/// One `kind.value` condition of an override suffix. A variant such as
+/// `app.conf##role.laptop,distro.arch` applies when all of its selectors match.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    Host(String),
+    User(String),
+    Role(String),
+    Distro(String),
+    Os(String),
+    Arch(String),
+}
+
+impl Selector {
+    pub fn parse(selector: &str) -> Result<Self> {
+        let Some((kind, value)) = selector.split_once('.').filter(|(_, v)| !v.is_empty()) else {
+            bail!("invalid override selector '{selector}': expected kind.value, e.g. host.laptop");
+        };
+        let value = value.to_string();
+        Ok(match kind {
+            "host" => Selector::Host(value),
+            "user" => Selector::User(value),
+            "role" => Selector::Role(value),
+            "distro" => Selector::Distro(value),
+            "os" => Selector::Os(value),
+            "arch" => Selector::Arch(value),
+            _ => bail!(
+                "unknown override selector '{kind}' in '{selector}' (expected host, user, role, distro, os or arch)"
+            ),
+        })
+    }
+
+    /// Kinds are ranked host > user > role > distro > os > arch. Each has its own
+    /// bit, so a variant's specificity is decided by its most specific kind first.
+    fn weight(&self) -> u32 {
+        match self {
+            Selector::Host(_) => 1 << 5,
+            Selector::User(_) => 1 << 4,
+            Selector::Role(_) => 1 << 3,
+            Selector::Distro(_) => 1 << 2,
+            Selector::Os(_) => 1 << 1,
+            Selector::Arch(_) => 1,
+        }
+    }
+}
+
+/// The selectors of a variant's file name (everything after `##`), or `None` for a base file.
+pub fn parse_selectors(file_name: &str) -> Result<Option<Vec<Selector>>> {
+    let Some(idx) = file_name.find("##") else {
+        return Ok(None);
+    };
+    file_name[idx + 2..].split(',').map(Selector::parse).collect::<Result<_>>().map(Some)
+}
+
+/// Override variants of one target that match equally well. The first one (by
+/// file name) is deployed.
+#[derive(Debug)]
+pub struct VariantTie {
+    pub target_rel_path: PathBuf,
+    pub variants: Vec<PathBuf>,
+}
+
+/// Who a package is being scanned for.
+struct Selection<'a> {
+    hostname: &'a str,
+    roles: &'a [&'a str],
+    machine: &'a Machine,
+}
+
+/// How specific a matching variant is: the weights of its selector kinds, then the
+/// position of its latest role, so later roles win ties as they do for vars.
+type Rank = (u32, usize);
+
+impl Selection<'_> {
+    /// The rank of a variant with these selectors, or `None` if one doesn't match.
+    fn rank(&self, selectors: &[Selector]) -> Option<Rank> {
+        let mut weight = 0;
+        let mut role_pos = 0;
+        for selector in selectors {
+            let matches = match selector {
+                Selector::Host(host) => host == self.hostname,
+                Selector::User(user) => self.machine.user.as_ref() == Some(user),
+                Selector::Role(role) => match self.roles.iter().rposition(|r| r == role) {
+                    Some(pos) => {
+                        role_pos = role_pos.max(pos + 1);
+                        true
+                    }
+                    None => false,
+                },
+                Selector::Distro(distro) => self.machine.distro.as_ref() == Some(distro),
+                Selector::Os(os) => self.machine.os.as_ref() == Some(os),
+                Selector::Arch(arch) => self.machine.arch.as_ref() == Some(arch),
+            };
+            if !matches {
+                return None;
+            }
+            weight |= selector.weight();
+        }
+        Some((weight, role_pos))
+    }
+}
+
 /// Scan a package directory and resolve overrides for the given host and roles.
 ///
 /// Returns a list of FileActions describing what to deploy.
 pub fn scan_package(pkg_dir: &Path, hostname: &str, roles: &[&str]) -> Result<Vec<FileAction>> {
+    scan_package_with(pkg_dir, hostname, roles, &ScanOptions::default())
+}
+
+/// Like [`scan_package`], with per-package scan options.
+pub fn scan_package_with(
+    pkg_dir: &Path,
+    hostname: &str,
+    roles: &[&str],
+    options: &ScanOptions,
+) -> Result<Vec<FileAction>> {
+    scan(pkg_dir, hostname, roles, options).map(|scan| scan.actions)
+}
+
+/// Like [`scan_package_with`], also returning an error for each override that was
+/// skipped because its suffix can't be parsed, naming the file relative to the package.
+pub fn scan_package_checked(
+    pkg_dir: &Path,
+    hostname: &str,
+    roles: &[&str],
+    options: &ScanOptions,
+) -> Result<(Vec<FileAction>, Vec<anyhow::Error>)> {
+    scan(pkg_dir, hostname, roles, options).map(|scan| (scan.actions, scan.invalid))
+}
+
+/// Targets for which two or more override variants match equally well for the
+/// given host and roles.
+pub fn override_ties(
+    pkg_dir: &Path,
+    hostname: &str,
+    roles: &[&str],
+    options: &ScanOptions,
+) -> Result<Vec<VariantTie>> {
+    scan(pkg_dir, hostname, roles, options).map(|scan| scan.ties)
+}
+
+struct Scan {
+    actions: Vec<FileAction>,
+    ties: Vec<VariantTie>,
+    /// Overrides skipped because their suffix doesn't parse
+    invalid: Vec<anyhow::Error>,
+}
+
+fn scan(pkg_dir: &Path, hostname: &str, roles: &[&str], options: &ScanOptions) -> Result<Scan> {
     let mut files: HashMap<PathBuf, Vec<PathBuf>> = HashMap::new();
+    let mut folded: Vec<PathBuf> = Vec::new();
 
-    collect_files(pkg_dir, pkg_dir, &mut files)
+    collect_files(pkg_dir, pkg_dir, options, &mut files, &mut folded)
         .with_context(|| format!("failed to scan package directory: {}", pkg_dir.display()))?;
 
+    let selection = Selection { hostname, roles, machine: &options.machine };
     let mut actions = Vec::new();
+    let mut ties = Vec::new();
+    let mut invalid = Vec::new();
+
+    for (target_path, variants) in &mut files {
+        // An unknown `##` suffix is most likely a typo, which shouldn't stop the
+        // rest of the deploy; the caller reports it
+        variants.retain(|variant| match parse_selectors(file_name_str(variant)) {
+            Ok(_) => true,
+            Err(e) => {
+                let rel_path = variant.strip_prefix(pkg_dir).unwrap_or(variant);
+                invalid.push(e.context(format!("invalid override {}", rel_path.display())));
+                false
+            }
+        });
+        if variants.is_empty() {
+            continue;
+        }
+        variants.sort();
+        // Variants share a target through renames or `dot-` names only by mistake
+        let base_paths: Vec<PathBuf> = variants
+            .iter()
+            .map(|v| base_path(v.strip_prefix(pkg_dir).expect("collected path must be under package directory")))
+            .collect();
+        if let Some(other) = base_paths.iter().find(|p| **p != base_paths[0]) {
+            bail!(
+                "{} and {} in {} both deploy to {}",
+                base_paths[0].display(),
+                other.display(),
+                pkg_dir.display(),
+                target_path.display()
+            );
+        }
 
-    for (target_path, variants) in &files {
-        let action = resolve_variant(target_path, variants, hostname, roles);
+        let (action, tied) = resolve_variant(target_path, variants, &selection);
+        if tied.len() > 1 {
+            ties.push(VariantTie { target_rel_path: target_path.clone(), variants: tied });
+        }
         actions.push(action);
     }
 
+    for rel_path in folded {
+        actions.push(FileAction {
+            source: pkg_dir.join(&rel_path),
+            target_rel_path: dir_target_path(&rel_path, options),
+            kind: EntryKind::Directory,
+        });
+    }
+
     actions.sort_by(|a, b| a.target_rel_path.cmp(&b.target_rel_path));
-    Ok(actions)
+    ties.sort_by(|a, b| a.target_rel_path.cmp(&b.target_rel_path));
+    invalid.sort_by_key(|e| e.to_string());
+    Ok(Scan { actions, ties, invalid })
 }
 
 /// Recursively collect files, grouping override variants by their canonical path.
+/// Directories listed in `options.fold` are collected as a whole into `folded`.
 fn collect_files(
     base: &Path,
     dir: &Path,
+    options: &ScanOptions,
     files: &mut HashMap<PathBuf, Vec<PathBuf>>,
+    folded: &mut Vec<PathBuf>,
 ) -> Result<()> {
     for entry in
         std::fs::read_dir(dir).with_context(|| format!("failed to read directory: {}", dir.display()))?
     {
         let entry = entry?;
         let path = entry.path();
+        let rel_path = path
+            .strip_prefix(base)
+            .expect("collected path must be under base directory")
+            .to_path_buf();
+
+        // The package's own config and ignore files are never deployed
+        if rel_path == Path::new(crate::loader::PACKAGE_CONFIG_FILE) || rel_path == Path::new(IGNORE_FILE) {
+            continue;
+        }
+        if options.ignore.is_ignored(&rel_path, path.is_dir()) {
+            continue;
+        }
 
         if path.is_dir() {
-            collect_files(base, &path, files)?;
+            // A renamed file deploys elsewhere, so the directory holding it can't be folded
+            let renamed = options.rename.keys().find(|source| source.starts_with(&rel_path) && **source != rel_path);
+            if options.fold.contains(&rel_path) {
+                if let Some(special) = find_special_file(base, &path, &options.ignore)? {
+                    bail!(
+                        "cannot fold {}: it contains an override, template, encrypted or ignored file ({})",
+                        rel_path.display(),
+                        special.display()
+                    );
+                }
+                if let Some(source) = renamed {
+                    bail!("cannot fold {}: it contains a renamed file ({})", rel_path.display(), source.display());
+                }
+                folded.push(rel_path);
+            } else if let Some(ref target_dir) = options.fold_target
+                && renamed.is_none()
+                && can_auto_fold(&target_dir.join(dir_target_path(&rel_path, options)), options.staging_dir.as_deref())
+                && find_special_file(base, &path, &options.ignore)?.is_none()
+            {
+                folded.push(rel_path);
+            } else {
+                collect_files(base, &path, options, files, folded)?;
+            }
         } else {
-            let rel_path = path
-                .strip_prefix(base)
-                .expect("collected path must be under base directory")
-                .to_path_buf();
-            let canonical = canonical_target_path(&rel_path);
+            let canonical = canonical_target_path(&rel_path, options);
             files.entry(canonical).or_default().push(path);
         }
     }
     Ok(())
 }
 
+/// Whether a directory deployed to `target` can be folded: nothing exists there
+/// yet, or it is already a symlink into the staging directory.
+fn can_auto_fold(target: &Path, staging_dir: Option<&Path>) -> bool {
+    if !target.is_symlink() {
+        return !target.exists();
+    }
+    match (std::fs::canonicalize(target), staging_dir.map(std::fs::canonicalize)) {
+        (Ok(resolved), Some(Ok(staging))) => resolved.starts_with(staging),
+        _ => false,
+    }
+}
+
+/// Find a file under `dir` that needs per-file handling (override, template or
+/// encrypted file) or is ignored, which prevents the directory from being folded.
+fn find_special_file(base: &Path, dir: &Path, ignore: &IgnoreRules) -> Result<Option<PathBuf>> {
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("failed to read directory: {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let rel_path = path.strip_prefix(base).expect("collected path must be under base directory");
+        if ignore.is_ignored(rel_path, path.is_dir()) {
+            return Ok(Some(path));
+        }
+        if path.is_dir() {
+            if let Some(found) = find_special_file(base, &path, ignore)? {
+                return Ok(Some(found));
+            }
+        } else {
+            let name = file_name_str(&path);
+            if name.contains("##") || name.ends_with(".tera") || is_encrypted_name(name) {
+                return Ok(Some(path));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Template files in a package, including template overrides such as
+/// `config.tera##host.laptop`, leaving out what `ignore` excludes.
+pub fn template_files(pkg_dir: &Path, ignore: &IgnoreRules) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_template_files(pkg_dir, pkg_dir, ignore, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn collect_template_files(base: &Path, dir: &Path, ignore: &IgnoreRules, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("failed to read directory: {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let rel_path = path.strip_prefix(base).expect("collected path must be under base directory");
+        if ignore.is_ignored(rel_path, path.is_dir()) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_template_files(base, &path, ignore, files)?;
+        } else if is_template_name(file_name_str(&path)) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Errors for every file in a package whose override suffix can't be parsed,
+/// naming the file relative to the package.
+pub fn invalid_overrides(pkg_dir: &Path) -> Result<Vec<anyhow::Error>> {
+    let mut errors = Vec::new();
+    collect_invalid_overrides(pkg_dir, pkg_dir, &mut errors)?;
+    errors.sort_by_key(|e| e.to_string());
+    Ok(errors)
+}
+
+fn collect_invalid_overrides(base: &Path, dir: &Path, errors: &mut Vec<anyhow::Error>) -> Result<()> {
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("failed to read directory: {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_invalid_overrides(base, &path, errors)?;
+        } else if let Err(e) = parse_selectors(file_name_str(&path)) {
+            let rel_path = path.strip_prefix(base).unwrap_or(&path);
+            errors.push(e.context(format!("invalid override {}", rel_path.display())));
+        }
+    }
+    Ok(())
+}
+
+/// Extension marking a package file as encrypted.
+const ENCRYPTED_EXT: &str = ".age";
+
+/// Whether a variant's name (ignoring any `##` suffix) marks it as encrypted.
+fn is_encrypted_name(name: &str) -> bool {
+    let base = name.find("##").map_or(name, |idx| &name[..idx]);
+    base.ends_with(ENCRYPTED_EXT)
+}
+
+/// Whether a variant's name (ignoring any `##` suffix) marks it as a template,
+/// e.g. `config.tera` or `config.tera##host.laptop`.
+pub fn is_template_name(name: &str) -> bool {
+    let base = name.find("##").map_or(name, |idx| &name[..idx]);
+    base.ends_with(".tera")
+}
+
+/// Kind for an override variant: encrypted overrides stay encrypted, and
+/// template overrides are rendered.
+fn override_kind(source: &Path) -> EntryKind {
+    let name = file_name_str(source);
+    if is_encrypted_name(name) {
+        EntryKind::Encrypted
+    } else if is_template_name(name) {
+        EntryKind::Template
+    } else {
+        EntryKind::Override
+    }
+}
+
 /// Extract filename as a UTF-8 string, panicking with a descriptive message on non-UTF-8 paths.
 fn file_name_str(path: &Path) -> &str {
     path.file_name()
@@ -78,8 +514,34 @@ fn file_name_str(path: &Path) -> &str {
         .expect("filename is not valid UTF-8")
 }
 
-/// Strip `##` suffix and `.tera` extension to get the canonical target path.
-fn canonical_target_path(rel_path: &Path) -> PathBuf {
+/// Strip `##` suffix and `.tera`/`.age` extension, then apply the package's
+/// renames or `dot-` translation, to get the canonical target path.
+fn canonical_target_path(rel_path: &Path, options: &ScanOptions) -> PathBuf {
+    let base = base_path(rel_path);
+    if let Some(target) = options.rename.get(&base) {
+        return target.clone();
+    }
+    if options.dot_prefix {
+        translate_dot_prefix(&base)
+    } else {
+        base
+    }
+}
+
+/// Target path of a directory deployed as a whole, after renames or `dot-` translation.
+fn dir_target_path(rel_path: &Path, options: &ScanOptions) -> PathBuf {
+    if let Some(target) = options.rename.get(rel_path) {
+        return target.clone();
+    }
+    if options.dot_prefix {
+        translate_dot_prefix(rel_path)
+    } else {
+        rel_path.to_path_buf()
+    }
+}
+
+/// A package file's path without its `##` suffix and `.tera`/`.age` extension.
+fn base_path(rel_path: &Path) -> PathBuf {
     let file_name = file_name_str(rel_path);
 
     // Strip ## suffix first
@@ -89,8 +551,11 @@ fn canonical_target_path(rel_path: &Path) -> PathBuf {
         file_name
     };
 
-    // Strip .tera extension
-    let base_name = base_name.strip_suffix(".tera").unwrap_or(base_name);
+    // Strip .tera or .age extension
+    let base_name = base_name
+        .strip_suffix(".tera")
+        .or_else(|| base_name.strip_suffix(ENCRYPTED_EXT))
+        .unwrap_or(base_name);
 
     if let Some(parent) = rel_path.parent() {
         if parent == Path::new("") {
@@ -103,52 +568,72 @@ fn canonical_target_path(rel_path: &Path) -> PathBuf {
     }
 }
 
-/// Given all variants of a file, pick the best one for this host/roles.
-fn resolve_variant(
-    target_path: &Path,
-    variants: &[PathBuf],
-    hostname: &str,
-    roles: &[&str],
-) -> FileAction {
-    let host_suffix = format!("##host.{hostname}");
+/// Replace a `dot-` prefix with `.` in every component of `path`.
+fn translate_dot_prefix(path: &Path) -> PathBuf {
+    path.components()
+        .map(|component| {
+            let name = component.as_os_str().to_string_lossy();
+            match name.strip_prefix("dot-") {
+                Some(rest) if !rest.is_empty() => format!(".{rest}"),
+                _ => name.into_owned(),
+            }
+        })
+        .collect()
+}
 
-    // Priority 1: host override
-    if let Some(source) = variants
-        .iter()
-        .find(|v| file_name_str(v).contains(&host_suffix))
-    {
-        return FileAction {
-            source: source.clone(),
-            target_rel_path: target_path.to_path_buf(),
-            kind: EntryKind::Override,
+/// Given all variants of a file, pick the best one for this host/roles. Also
+/// returns the overrides tied for the best match, if more than one. Variants must
+/// have valid override suffixes.
+fn resolve_variant(target_path: &Path, variants: &[PathBuf], selection: &Selection) -> (FileAction, Vec<PathBuf>) {
+    // Priority 1: the most specific matching override
+    let mut best: Option<(Rank, Vec<PathBuf>)> = None;
+    for variant in variants {
+        let Ok(Some(selectors)) = parse_selectors(file_name_str(variant)) else {
+            continue;
         };
-    }
-
-    // Priority 2: role override (last matching role wins)
-    for role in roles.iter().rev() {
-        let role_suffix = format!("##role.{role}");
-        if let Some(source) = variants
-            .iter()
-            .find(|v| file_name_str(v).contains(&role_suffix))
-        {
-            return FileAction {
-                source: source.clone(),
-                target_rel_path: target_path.to_path_buf(),
-                kind: EntryKind::Override,
-            };
+        let Some(rank) = selection.rank(&selectors) else {
+            continue;
+        };
+        match best {
+            Some((best_rank, ref mut tied)) if rank == best_rank => tied.push(variant.clone()),
+            Some((best_rank, _)) if rank < best_rank => {}
+            _ => best = Some((rank, vec![variant.clone()])),
         }
     }
+    if let Some((_, tied)) = best {
+        let source = tied[0].clone();
+        let action = FileAction {
+            target_rel_path: target_path.to_path_buf(),
+            kind: override_kind(&source),
+            source,
+        };
+        return (action, tied);
+    }
 
-    // Priority 3: template (base file with .tera extension)
+    // Priority 2: template (base file with .tera extension)
     if let Some(source) = variants.iter().find(|v| {
         let name = file_name_str(v);
         name.ends_with(".tera") && !name.contains("##")
     }) {
-        return FileAction {
+        let action = FileAction {
             source: source.clone(),
             target_rel_path: target_path.to_path_buf(),
             kind: EntryKind::Template,
         };
+        return (action, Vec::new());
+    }
+
+    // Priority 3: encrypted base file (.age extension)
+    if let Some(source) = variants.iter().find(|v| {
+        let name = file_name_str(v);
+        is_encrypted_name(name) && !name.contains("##")
+    }) {
+        let action = FileAction {
+            source: source.clone(),
+            target_rel_path: target_path.to_path_buf(),
+            kind: EntryKind::Encrypted,
+        };
+        return (action, Vec::new());
     }
 
     // Priority 4: plain base file
@@ -156,13 +641,14 @@ fn resolve_variant(
         .iter()
         .find(|v| {
             let name = file_name_str(v);
-            !name.contains("##") && !name.ends_with(".tera")
+            !name.contains("##") && !name.ends_with(".tera") && !is_encrypted_name(name)
         })
         .unwrap_or(&variants[0]);
 
-    FileAction {
+    let action = FileAction {
         source: source.clone(),
         target_rel_path: target_path.to_path_buf(),
         kind: EntryKind::Base,
-    }
+    };
+    (action, Vec::new())
 }
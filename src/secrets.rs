@@ -0,0 +1,264 @@
+use crate::config::{SecretsBackend, SecretsConfig};
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use toml::map::Map;
+use toml::Value;
+
+/// A source of secret values, looked up by a provider-specific path.
+pub trait SecretProvider: Send + Sync {
+    fn get(&self, path: &str) -> Result<String>;
+}
+
+/// Reads secrets from an encrypted TOML file in the dotfiles repo.
+///
+/// The file is decrypted once (with `age`, `gpg`, or a custom command) and
+/// secrets are addressed by dotted key path, e.g. `github.token`.
+pub struct FileProvider {
+    file: PathBuf,
+    decrypt_command: String,
+    decrypted: Mutex<Option<Map<String, Value>>>,
+}
+
+impl FileProvider {
+    pub fn new(file: &Path, identity: Option<&Path>, decrypt_command: Option<&str>) -> Result<Self> {
+        let decrypt_command = match decrypt_command {
+            Some(cmd) => cmd.to_string(),
+            None => default_decrypt_command(file, identity)?,
+        };
+        Ok(Self {
+            file: file.to_path_buf(),
+            decrypt_command,
+            decrypted: Mutex::new(None),
+        })
+    }
+
+    fn decrypt(&self) -> Result<Map<String, Value>> {
+        if !self.file.exists() {
+            bail!("secrets file not found: {}", self.file.display());
+        }
+        let plaintext = run_with_arg(&self.decrypt_command, &self.file.to_string_lossy())
+            .with_context(|| format!("failed to decrypt secrets file: {}", self.file.display()))?;
+        let table: Map<String, Value> = toml::from_str(&plaintext)
+            .with_context(|| format!("failed to parse decrypted secrets file: {}", self.file.display()))?;
+        Ok(table)
+    }
+}
+
+impl SecretProvider for FileProvider {
+    fn get(&self, path: &str) -> Result<String> {
+        let mut decrypted = self.decrypted.lock().expect("secrets lock poisoned");
+        if decrypted.is_none() {
+            *decrypted = Some(self.decrypt()?);
+        }
+        let table = decrypted.as_ref().expect("secrets were just decrypted");
+
+        let mut current: Option<&Value> = None;
+        for (i, key) in path.split('.').enumerate() {
+            current = if i == 0 {
+                table.get(key)
+            } else {
+                current.and_then(|v| v.as_table()).and_then(|t| t.get(key))
+            };
+        }
+
+        match current {
+            Some(Value::String(s)) => Ok(s.clone()),
+            Some(Value::Table(_)) | Some(Value::Array(_)) => {
+                bail!("secret '{path}' is not a scalar value")
+            }
+            Some(other) => Ok(other.to_string()),
+            None => bail!("secret '{path}' not found in {}", self.file.display()),
+        }
+    }
+}
+
+/// Runs an arbitrary command (e.g. `pass show`) to fetch each secret.
+///
+/// `{path}` in the command is replaced with the secret path; without a
+/// placeholder the path is appended as the last argument. Trailing newlines
+/// are stripped from the output.
+pub struct CommandProvider {
+    command: String,
+}
+
+impl CommandProvider {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+}
+
+impl SecretProvider for CommandProvider {
+    fn get(&self, path: &str) -> Result<String> {
+        let output = run_with_arg(&self.command, path)
+            .with_context(|| format!("failed to fetch secret '{path}'"))?;
+        Ok(output.trim_end_matches(['\n', '\r']).to_string())
+    }
+}
+
+/// A configured secret provider with a per-run cache, so each secret is
+/// fetched (and each file decrypted) at most once per deploy.
+pub struct SecretStore {
+    provider: Box<dyn SecretProvider>,
+    cache: Mutex<HashMap<String, String>>,
+}
+
+impl SecretStore {
+    pub fn new(provider: Box<dyn SecretProvider>) -> Self {
+        Self {
+            provider,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Build a store from the `[dotm.secrets]` config. Relative paths are
+    /// resolved against the dotfiles directory.
+    pub fn from_config(config: &SecretsConfig, base_dir: &Path) -> Result<Self> {
+        let provider: Box<dyn SecretProvider> = match config.backend {
+            SecretsBackend::File => {
+                let Some(ref file) = config.file else {
+                    bail!("secrets backend 'file' requires a 'file' setting");
+                };
+                let file = base_dir.join(crate::orchestrator::expand_path(file, Some("secrets file"))?);
+                let identity = config
+                    .identity
+                    .as_deref()
+                    .map(|i| crate::orchestrator::expand_path(i, Some("secrets identity")))
+                    .transpose()?
+                    .map(PathBuf::from);
+                Box::new(FileProvider::new(
+                    &file,
+                    identity.as_deref(),
+                    config.decrypt_command.as_deref(),
+                )?)
+            }
+            SecretsBackend::Command => {
+                let Some(ref command) = config.command else {
+                    bail!("secrets backend 'command' requires a 'command' setting");
+                };
+                Box::new(CommandProvider::new(command))
+            }
+        };
+        Ok(Self::new(provider))
+    }
+
+    pub fn get(&self, path: &str) -> Result<String> {
+        if let Some(value) = self.cache.lock().expect("secrets lock poisoned").get(path) {
+            return Ok(value.clone());
+        }
+        let value = self.provider.get(path)?;
+        self.cache
+            .lock()
+            .expect("secrets lock poisoned")
+            .insert(path.to_string(), value.clone());
+        Ok(value)
+    }
+}
+
+/// Decrypt an encrypted file and return its plaintext bytes.
+///
+/// Uses `decrypt_command` when given, otherwise `age` or `gpg` depending on the
+/// file extension (`age` requires an identity).
+pub fn decrypt_file(file: &Path, identity: Option<&Path>, decrypt_command: Option<&str>) -> Result<Vec<u8>> {
+    let command = match decrypt_command {
+        Some(cmd) => cmd.to_string(),
+        None => default_decrypt_command(file, identity)?,
+    };
+    run_with_arg_bytes(&command, &file.to_string_lossy())
+        .with_context(|| format!("failed to decrypt {}", file.display()))
+}
+
+/// Pick a decryption command based on the encrypted file's extension.
+fn default_decrypt_command(file: &Path, identity: Option<&Path>) -> Result<String> {
+    match file.extension().and_then(|e| e.to_str()) {
+        Some("age") => match identity {
+            Some(id) => Ok(format!("age --decrypt -i '{}'", id.display())),
+            None => bail!("age-encrypted file requires an 'identity' setting"),
+        },
+        Some("gpg") | Some("asc") => Ok("gpg --quiet --batch --decrypt".to_string()),
+        _ => bail!(
+            "cannot infer how to decrypt {} — use a .age/.gpg file or set 'decrypt_command'",
+            file.display()
+        ),
+    }
+}
+
+/// Run `command` via `sh -c`, substituting `arg` for `{path}` (or appending it).
+/// The argument is passed positionally so it is never interpreted by the shell.
+fn run_with_arg(command: &str, arg: &str) -> Result<String> {
+    String::from_utf8(run_with_arg_bytes(command, arg)?).context("command output is not valid UTF-8")
+}
+
+fn run_with_arg_bytes(command: &str, arg: &str) -> Result<Vec<u8>> {
+    let script = if command.contains("{path}") {
+        command.replace("{path}", "\"$1\"")
+    } else {
+        format!("{command} \"$1\"")
+    };
+
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(&script)
+        .arg("sh")
+        .arg(arg)
+        .output()
+        .with_context(|| format!("failed to run '{command}'"))?;
+
+    if !output.status.success() {
+        bail!(
+            "command '{}' exited with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn default_decrypt_command_for_age_requires_identity() {
+        assert!(default_decrypt_command(Path::new("s.toml.age"), None).is_err());
+        let cmd = default_decrypt_command(Path::new("s.toml.age"), Some(Path::new("/k.txt"))).unwrap();
+        assert!(cmd.starts_with("age --decrypt -i"));
+    }
+
+    #[test]
+    fn default_decrypt_command_for_gpg() {
+        let cmd = default_decrypt_command(Path::new("s.toml.gpg"), None).unwrap();
+        assert!(cmd.starts_with("gpg"));
+    }
+
+    #[test]
+    fn default_decrypt_command_rejects_unknown_extension() {
+        assert!(default_decrypt_command(Path::new("s.toml"), None).is_err());
+    }
+
+    #[test]
+    fn run_with_arg_does_not_expand_shell_syntax() {
+        let out = run_with_arg("printf %s", "$(echo pwned)").unwrap();
+        assert_eq!(out, "$(echo pwned)");
+    }
+
+    #[test]
+    fn store_caches_lookups() {
+        let dir = TempDir::new().unwrap();
+        let counter = dir.path().join("count");
+        let store = SecretStore::new(Box::new(CommandProvider::new(&format!(
+            "echo x >> '{}'; echo",
+            counter.display()
+        ))));
+        store.get("a").unwrap();
+        store.get("a").unwrap();
+        assert_eq!(std::fs::read_to_string(&counter).unwrap().lines().count(), 1);
+    }
+}
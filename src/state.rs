@@ -1,11 +1,11 @@
 use crate::hash;
 use crate::scanner::EntryKind;
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use fs2::FileExt;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct FileStatus {
     pub exists: bool,
     pub content_modified: bool,
@@ -58,6 +58,25 @@ impl FileStatus {
 
 const STATE_FILE: &str = "dotm-state.json";
 const CURRENT_VERSION: u32 = 2;
+const PROFILES_DIR: &str = "profiles";
+
+/// State directory of a deploy profile, inside `state_dir`. Each profile tracks
+/// its own files, so deploying one never treats another's files as orphans.
+pub fn profile_state_dir(state_dir: &Path, profile: &str) -> Result<PathBuf> {
+    validate_profile(profile)?;
+    Ok(state_dir.join(PROFILES_DIR).join(profile))
+}
+
+/// Profile names end up in paths, so only plain names are allowed.
+pub fn validate_profile(profile: &str) -> Result<()> {
+    let valid = !profile.is_empty()
+        && !profile.starts_with('.')
+        && profile.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        bail!("invalid profile name '{profile}': use letters, digits, '-', '_' and '.'");
+    }
+    Ok(())
+}
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct DeployState {
@@ -92,6 +111,22 @@ pub struct DeployEntry {
     pub original_group: Option<String>,
     #[serde(default)]
     pub original_mode: Option<String>,
+    /// Content includes secrets and is kept out of the deployed/ content store
+    #[serde(default)]
+    pub sensitive: bool,
+    /// For encrypted files, hash of the ciphertext stored in deployed/ in place of the plaintext
+    #[serde(default)]
+    pub encrypted_hash: Option<String>,
+    /// Target links straight to the source (link strategy); `staged` is the source itself
+    #[serde(default)]
+    pub linked: bool,
+}
+
+impl DeployEntry {
+    /// Whether `staged` is a copy owned by dotm, separate from both target and source.
+    pub fn has_staged_copy(&self) -> bool {
+        !self.linked && self.staged != self.target
+    }
 }
 
 impl DeployState {
@@ -173,6 +208,10 @@ impl DeployState {
         &mut self.entries
     }
 
+    pub fn set_entries(&mut self, entries: Vec<DeployEntry>) {
+        self.entries = entries;
+    }
+
     pub fn update_entry_hash(&mut self, index: usize, new_hash: String) {
         if let Some(entry) = self.entries.get_mut(index) {
             entry.content_hash = new_hash;
@@ -186,8 +225,20 @@ impl DeployState {
 
         let mut status = FileStatus::ok();
 
+        // Linked targets are the source itself: edits are repo changes, not drift.
+        // Only a replaced or redirected link counts as modified.
+        if entry.linked {
+            if !entry.source.exists() {
+                return FileStatus::missing();
+            }
+            let points_to_source = entry.target.is_symlink()
+                && std::fs::canonicalize(&entry.target).ok().as_ref() == Some(&entry.source);
+            status.content_modified = !points_to_source;
+            return status;
+        }
+
         if entry.staged.exists() {
-            if let Ok(current_hash) = hash::hash_file(&entry.staged)
+            if let Ok(current_hash) = hash::hash_path(&entry.staged)
                 && current_hash != entry.content_hash
             {
                 status.content_modified = true;
@@ -220,6 +271,10 @@ impl DeployState {
         status
     }
 
+    pub fn state_dir(&self) -> &Path {
+        &self.state_dir
+    }
+
     pub fn originals_dir(&self) -> PathBuf {
         self.state_dir.join("originals")
     }
@@ -274,6 +329,40 @@ impl DeployState {
         Ok(())
     }
 
+    /// Write the pre-dotm content of `entry` back to its target, with the original
+    /// metadata if it was recorded. Does nothing for files dotm created.
+    pub fn restore_original(&self, entry: &DeployEntry) -> Result<()> {
+        let Some(ref orig_hash) = entry.original_hash else {
+            return Ok(());
+        };
+
+        // Restore original content (replacing the symlink, never writing through it)
+        let original_content = self.load_original(orig_hash)?;
+        if entry.target.is_symlink() {
+            std::fs::remove_file(&entry.target)
+                .with_context(|| format!("failed to remove: {}", entry.target.display()))?;
+        }
+        if let Some(parent) = entry.target.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+        }
+        std::fs::write(&entry.target, &original_content)
+            .with_context(|| format!("failed to restore: {}", entry.target.display()))?;
+
+        // Restore original metadata if recorded
+        if entry.original_owner.is_some() || entry.original_group.is_some() {
+            let _ = crate::metadata::apply_ownership(
+                &entry.target,
+                entry.original_owner.as_deref(),
+                entry.original_group.as_deref(),
+            );
+        }
+        if let Some(ref orig_mode) = entry.original_mode {
+            let _ = crate::deployer::apply_permission_override(&entry.target, orig_mode);
+        }
+        Ok(())
+    }
+
     /// Restore files to their pre-dotm state.
     /// Files with original_hash get their original content written back with original metadata.
     /// Files without original_hash (dotm created them) get removed.
@@ -288,24 +377,8 @@ impl DeployState {
                 }
             }
 
-            if let Some(ref orig_hash) = entry.original_hash {
-                // Restore original content
-                let original_content = self.load_original(orig_hash)?;
-                std::fs::write(&entry.target, &original_content)
-                    .with_context(|| format!("failed to restore: {}", entry.target.display()))?;
-
-                // Restore original metadata if recorded
-                if entry.original_owner.is_some() || entry.original_group.is_some() {
-                    let _ = crate::metadata::apply_ownership(
-                        &entry.target,
-                        entry.original_owner.as_deref(),
-                        entry.original_group.as_deref(),
-                    );
-                }
-                if let Some(ref orig_mode) = entry.original_mode {
-                    let _ = crate::deployer::apply_permission_override(&entry.target, orig_mode);
-                }
-
+            if entry.original_hash.is_some() {
+                self.restore_original(entry)?;
                 restored += 1;
             } else {
                 // No original — file was created by dotm, remove it
@@ -318,8 +391,8 @@ impl DeployState {
             }
 
             // Clean up staged file if separate from target
-            if entry.staged != entry.target && entry.staged.exists() {
-                std::fs::remove_file(&entry.staged)
+            if entry.has_staged_copy() && entry.staged.exists() {
+                remove_managed(&entry.staged)
                     .with_context(|| format!("failed to remove staged: {}", entry.staged.display()))?;
                 cleanup_empty_parents(&entry.staged);
             }
@@ -358,8 +431,8 @@ impl DeployState {
                     removed += 1;
                 }
 
-                if entry.staged != entry.target && entry.staged.exists() {
-                    std::fs::remove_file(&entry.staged)
+                if entry.has_staged_copy() && entry.staged.exists() {
+                    remove_managed(&entry.staged)
                         .with_context(|| format!("failed to remove staged file: {}", entry.staged.display()))?;
                     cleanup_empty_parents(&entry.staged);
                 }
@@ -386,8 +459,8 @@ impl DeployState {
                 removed += 1;
             }
 
-            if entry.staged.exists() {
-                std::fs::remove_file(&entry.staged)
+            if entry.has_staged_copy() && entry.staged.exists() {
+                remove_managed(&entry.staged)
                     .with_context(|| format!("failed to remove staged file: {}", entry.staged.display()))?;
                 cleanup_empty_parents(&entry.staged);
             }
@@ -415,6 +488,18 @@ impl DeployState {
     }
 }
 
+/// Remove a managed path: a staged directory tree for folded directories,
+/// otherwise a single file or symlink. Missing paths are ignored.
+pub fn remove_managed(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() && !path.is_symlink() {
+        std::fs::remove_dir_all(path)
+    } else if path.exists() || path.is_symlink() {
+        std::fs::remove_file(path)
+    } else {
+        Ok(())
+    }
+}
+
 pub fn cleanup_empty_parents(path: &Path) {
     let mut current = path.parent();
     while let Some(parent) = current {
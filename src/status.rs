@@ -1,10 +1,12 @@
 use crate::git::GitSummary;
 use crate::state::{DeployEntry, FileStatus};
 use crossterm::style::Stylize;
+use serde::Serialize;
 use std::collections::BTreeMap;
 use std::io::IsTerminal;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+#[derive(Serialize)]
 pub struct PackageStatus {
     pub name: String,
     pub total: usize,
@@ -15,19 +17,21 @@ pub struct PackageStatus {
     pub files: Vec<FileEntry>,
 }
 
+#[derive(Serialize)]
 pub struct FileEntry {
+    pub target: PathBuf,
     pub display_path: String,
     pub status: FileStatus,
 }
 
 pub fn group_by_package(entries: &[DeployEntry], statuses: &[FileStatus]) -> Vec<PackageStatus> {
-    let mut groups: BTreeMap<&str, Vec<(String, FileStatus)>> = BTreeMap::new();
+    let mut groups: BTreeMap<&str, Vec<(PathBuf, FileStatus)>> = BTreeMap::new();
 
     for (entry, status) in entries.iter().zip(statuses.iter()) {
         groups
             .entry(&entry.package)
             .or_default()
-            .push((display_path(&entry.target), status.clone()));
+            .push((entry.target.clone(), status.clone()));
     }
 
     groups
@@ -43,8 +47,9 @@ pub fn group_by_package(entries: &[DeployEntry], statuses: &[FileStatus]) -> Vec
                 .count();
             let file_entries = files
                 .into_iter()
-                .map(|(display_path, status)| FileEntry {
-                    display_path,
+                .map(|(target, status)| FileEntry {
+                    display_path: display_path(&target),
+                    target,
                     status,
                 })
                 .collect();
@@ -355,6 +360,9 @@ mod tests {
             original_owner: None,
             original_group: None,
             original_mode: None,
+            sensitive: false,
+            encrypted_hash: None,
+            linked: false,
         }
     }
 
@@ -1,18 +1,763 @@
+use crate::facts::Facts;
+use crate::secrets::SecretStore;
 use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use tera::Tera;
 use toml::map::Map;
 use toml::Value;
 
+/// The output of a template render.
+#[derive(Debug)]
+pub struct Rendered {
+    pub content: String,
+    /// Whether the template read any values via `secret()`
+    pub uses_secrets: bool,
+}
+
+/// The `dotm` namespace of a template's context: machine facts plus details of
+/// the deploy and the file being rendered. It takes precedence over a user
+/// var named `dotm`.
+#[derive(Debug, Serialize)]
+pub struct DotmContext<'a> {
+    #[serde(flatten)]
+    pub facts: &'a Facts,
+    /// Host config being deployed (may differ from the system hostname)
+    pub host: &'a str,
+    /// Active roles, including included roles
+    pub roles: &'a [&'a str],
+    pub package: &'a str,
+    /// Where the rendered file is deployed
+    pub target: &'a Path,
+    /// Target directory of the package
+    pub target_dir: &'a Path,
+}
+
+/// A template that failed to parse or render, with what is known about where and why.
+#[derive(Debug, Default)]
+pub struct TemplateError {
+    /// The template the error happened in, relative to the dotfiles directory
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// Variable that wasn't found, e.g. `editor.theme`
+    pub missing_var: Option<String>,
+    /// The underlying Tera error
+    pub message: String,
+    pub host: Option<String>,
+    pub roles: Vec<String>,
+    /// Defined vars similar to the missing one, with the layer each came from
+    pub candidates: Vec<(String, String)>,
+}
+
+impl TemplateError {
+    /// Build from a Tera error raised while parsing or rendering `name`. `sources`
+    /// maps template names to their file and content, for locating the error.
+    fn from_tera(err: &tera::Error, name: &str, sources: &HashMap<String, (PathBuf, String)>) -> Self {
+        // The innermost Tera error says what went wrong; the outer ones only name the
+        // template being parsed or rendered
+        let mut inner = err;
+        while let Some(next) = std::error::Error::source(inner).and_then(|e| e.downcast_ref::<tera::Error>()) {
+            inner = next;
+        }
+        let mut message = match std::error::Error::source(inner) {
+            Some(cause) => cause.to_string(),
+            None => inner.to_string(),
+        };
+
+        let mut position = None;
+        let mut missing_var = None;
+        let mut active = name.to_string();
+        match &inner.kind {
+            // Tera reports syntax errors and missing variables only as text
+            tera::ErrorKind::Msg(msg) => {
+                if let Some(syntax) = SyntaxError::parse(msg) {
+                    position = Some(syntax.position);
+                    // The position is enough; keep the reason, not the drawing
+                    if let Some(reason) = syntax.reason {
+                        message = reason;
+                    }
+                } else if let Some((var, template)) = parse_missing_var(msg) {
+                    message = format!("variable `{var}` is not defined");
+                    missing_var = Some(var);
+                    // Looked up in the template that uses it, which may be an include
+                    active = template;
+                }
+            }
+            tera::ErrorKind::TemplateNotFound(template) => message = format!("template `{template}` not found"),
+            tera::ErrorKind::MissingParent { current, parent } => {
+                message = format!("parent template `{parent}` not found");
+                active = current.clone();
+            }
+            tera::ErrorKind::FilterNotFound(filter) => message = format!("unknown filter `{filter}`"),
+            tera::ErrorKind::TestNotFound(test) => message = format!("unknown test `{test}`"),
+            tera::ErrorKind::FunctionNotFound(function) => message = format!("unknown function `{function}`"),
+            _ => {}
+        }
+
+        let (file, source) = sources
+            .get(&active)
+            .or_else(|| sources.get(name))
+            .cloned()
+            .unwrap_or_else(|| (PathBuf::from(name), String::new()));
+        let position = position.or_else(|| missing_var.as_deref().and_then(|var| find_var(&source, var)));
+
+        Self {
+            file,
+            line: position.map(|(line, _)| line),
+            column: position.map(|(_, column)| column),
+            message,
+            missing_var,
+            ..Default::default()
+        }
+    }
+
+    /// Suggest defined vars that look like the missing one.
+    pub fn add_candidates(&mut self, provenance: &crate::vars::Provenance) {
+        let Some(ref missing) = self.missing_var else {
+            return;
+        };
+        let last_segment = |path: &str| path.rsplit('.').next().map(str::to_string);
+        let threshold = (missing.len() / 3).max(2);
+
+        let mut scored: Vec<(usize, &String, &Vec<crate::vars::VarSource>)> = provenance
+            .iter()
+            .filter(|(path, _)| *path != missing)
+            .map(|(path, sources)| (crate::vars::edit_distance(missing, path), path, sources))
+            .filter(|(distance, path, _)| *distance <= threshold || last_segment(path) == last_segment(missing))
+            .collect();
+        scored.sort_by_key(|(distance, path, _)| (*distance, (*path).clone()));
+
+        self.candidates = scored
+            .into_iter()
+            .take(3)
+            .map(|(_, path, sources)| (path.clone(), sources.last().map(|s| s.layer.clone()).unwrap_or_default()))
+            .collect();
+    }
+}
+
+impl std::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{line}")?;
+            if let Some(column) = self.column {
+                write!(f, ":{column}")?;
+            }
+        }
+        write!(f, ": {}", self.message)?;
+        if let Some(ref host) = self.host {
+            write!(f, "\n  host '{host}'")?;
+            if !self.roles.is_empty() {
+                write!(f, " with roles: {}", self.roles.join(", "))?;
+            }
+        }
+        for (path, layer) in &self.candidates {
+            write!(f, "\n  did you mean `{path}` (set by {layer})?")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// Several templates that failed to parse.
+#[derive(Debug)]
+pub struct TemplateErrors(pub Vec<TemplateError>);
+
+impl std::fmt::Display for TemplateErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let messages: Vec<String> = self.0.iter().map(|e| e.to_string()).collect();
+        f.write_str(&messages.join("\n"))
+    }
+}
+
+impl std::error::Error for TemplateErrors {}
+
+/// `Variable `x` not found in context while rendering 'name'` → (x, name)
+fn parse_missing_var(message: &str) -> Option<(String, String)> {
+    let rest = message.strip_prefix("Variable `")?;
+    let (var, rest) = rest.split_once('`')?;
+    let rest = rest.strip_prefix(" not found in context while rendering '")?;
+    let (template, _) = rest.split_once('\'')?;
+    Some((var.to_string(), template.to_string()))
+}
+
+/// A Tera syntax error, which draws the offending line under ` --> line:column`
+/// and usually ends with `= reason`.
+struct SyntaxError {
+    position: (usize, usize),
+    reason: Option<String>,
+}
+
+impl SyntaxError {
+    fn parse(message: &str) -> Option<Self> {
+        let (_, rest) = message.split_once("--> ")?;
+        let (line, column) = rest.split_whitespace().next()?.split_once(':')?;
+        Some(Self {
+            position: (line.parse().ok()?, column.parse().ok()?),
+            reason: message.lines().rev().find_map(|l| l.trim().strip_prefix("= ")).map(str::to_string),
+        })
+    }
+}
+
+/// First line and column (1-based) where `var` appears as a whole name in `source`.
+fn find_var(source: &str, var: &str) -> Option<(usize, usize)> {
+    let is_name_char = |c: char| c.is_alphanumeric() || c == '_' || c == '.';
+    for (index, line) in source.lines().enumerate() {
+        for (offset, _) in line.match_indices(var) {
+            let before = line[..offset].chars().next_back();
+            let after = line[offset + var.len()..].chars().next();
+            if !before.is_some_and(is_name_char) && !after.is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                return Some((index + 1, line[..offset].chars().count() + 1));
+            }
+        }
+    }
+    None
+}
+
 /// Render a Tera template string with the given variables.
 pub fn render_template(template_str: &str, vars: &Map<String, Value>) -> Result<String> {
+    render_template_with_secrets(template_str, vars, None).map(|r| r.content)
+}
+
+/// Render a Tera template string with the given variables and a `secret(path=...)`
+/// function backed by `secrets`. Calling `secret()` without a configured store is an error.
+pub fn render_template_with_secrets(
+    template_str: &str,
+    vars: &Map<String, Value>,
+    secrets: Option<Arc<SecretStore>>,
+) -> Result<Rendered> {
+    render_template_with_context(template_str, vars, secrets, None)
+}
+
+/// Like [`render_template_with_secrets`], with the `dotm` namespace available as `{{ dotm.* }}`.
+pub fn render_template_with_context(
+    template_str: &str,
+    vars: &Map<String, Value>,
+    secrets: Option<Arc<SecretStore>>,
+    dotm: Option<&DotmContext>,
+) -> Result<Rendered> {
+    const NAME: &str = "__dotm_template";
+    let sources = HashMap::from([(NAME.to_string(), (PathBuf::from("<template>"), template_str.to_string()))]);
+
     let mut tera = Tera::default();
-    tera.add_raw_template("__dotm_template", template_str)
-        .context("failed to parse template")?;
+    tera.add_raw_template(NAME, template_str)
+        .map_err(|e| TemplateError::from_tera(&e, NAME, &sources))?;
+
+    let used = Arc::new(AtomicBool::new(false));
+    let env = Arc::new(Mutex::new(environment(dotm)));
+    register_functions(&mut tera, secrets, Arc::clone(&used), Arc::new(Mutex::new(None)), env);
+
+    let content = tera
+        .render(NAME, &build_context(vars, dotm))
+        .map_err(|e| TemplateError::from_tera(&e, NAME, &sources))?;
+
+    Ok(Rendered {
+        content,
+        uses_secrets: used.load(Ordering::Relaxed),
+    })
+}
+
+/// Directory of shared partials, relative to the dotfiles directory.
+pub const PARTIALS_DIR: &str = "templates";
+
+/// One Tera instance holding the shared partials and the templates of the packages
+/// being deployed, so templates can `include`, `extends` and `import` each other.
+///
+/// Files under `templates/` are named by their path inside it
+/// (`{% include "palette.tera" %}`); `.tera` files in packages are named by
+/// their path in the dotfiles directory
+/// (`{% include "packages/shell/.aliases.tera" %}`).
+pub struct TemplateEngine {
+    tera: Tera,
+    /// Template name → (path relative to the dotfiles directory, content)
+    sources: HashMap<String, (PathBuf, String)>,
+    base_dir: PathBuf,
+    packages_dir: PathBuf,
+    used_secrets: Arc<AtomicBool>,
+    /// Package of the template being rendered, for `read_file()` and `file_exists()`
+    package_dir: Arc<Mutex<Option<PathBuf>>>,
+    /// Environment of the template being rendered, for `env()` and `command_exists()`
+    env: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl TemplateEngine {
+    /// Load the partials in `base_dir/templates/` and the package templates `templates`
+    /// (files under `packages_dir`, see [`crate::scanner::template_files`]).
+    ///
+    /// Fails with [`TemplateErrors`] listing every template that doesn't parse.
+    pub fn load(
+        base_dir: &Path,
+        packages_dir: &Path,
+        templates: &[PathBuf],
+        secrets: Option<Arc<SecretStore>>,
+    ) -> Result<Self> {
+        let mut sources: HashMap<String, (PathBuf, String)> = HashMap::new();
+
+        let partials_dir = base_dir.join(PARTIALS_DIR);
+        for path in walk_files(&partials_dir)? {
+            let content = read_template(&path)?;
+            sources.insert(template_name(&partials_dir, &path), (relative_to(base_dir, &path), content));
+        }
+        for path in templates {
+            let content = read_template(path)?;
+            sources.insert(template_name(base_dir, path), (relative_to(base_dir, path), content));
+        }
+
+        // Parse each template on its own first, to report every broken one at once
+        let mut errors: Vec<TemplateError> = sources
+            .iter()
+            .filter_map(|(name, (_, content))| tera::Template::new(name, None, content).err().map(|e| (name, e)))
+            .map(|(name, e)| TemplateError::from_tera(&e, name, &sources))
+            .collect();
+        if !errors.is_empty() {
+            errors.sort_by(|a, b| a.file.cmp(&b.file));
+            return Err(TemplateErrors(errors).into());
+        }
+
+        let mut tera = Tera::default();
+        // Dotfiles aren't HTML; never escape, whatever the partial's extension
+        tera.autoescape_on(Vec::new());
+        tera.add_raw_templates(sources.iter().map(|(name, (_, content))| (name.as_str(), content.as_str())))
+            .context("failed to load templates")?;
+
+        let used_secrets = Arc::new(AtomicBool::new(false));
+        let package_dir = Arc::new(Mutex::new(None));
+        let env = Arc::new(Mutex::new(HashMap::new()));
+        register_functions(
+            &mut tera,
+            secrets,
+            Arc::clone(&used_secrets),
+            Arc::clone(&package_dir),
+            Arc::clone(&env),
+        );
+
+        Ok(Self {
+            tera,
+            sources,
+            base_dir: base_dir.to_path_buf(),
+            packages_dir: packages_dir.to_path_buf(),
+            used_secrets,
+            package_dir,
+            env,
+        })
+    }
+
+    /// Replace `secret()` with a function returning a placeholder, for checking
+    /// templates without decrypting anything. Rendered templates still report
+    /// [`Rendered::uses_secrets`].
+    pub fn with_secret_placeholders(mut self) -> Self {
+        let used_secrets = Arc::clone(&self.used_secrets);
+        self.tera.register_function("secret", move |args: &HashMap<String, tera::Value>| {
+            let path = string_arg(args, "path", "secret")?;
+            used_secrets.store(true, Ordering::Relaxed);
+            Ok(tera::Value::String(format!("<secret:{path}>")))
+        });
+        self
+    }
 
-    let context = toml_map_to_tera_context(vars);
+    /// Render the template file at `source`, loading it first if it isn't part of the
+    /// engine yet (e.g. a file outside `packages/`).
+    ///
+    /// Errors are [`TemplateError`]s carrying the file, position and the host and roles
+    /// from `dotm`.
+    pub fn render_file(
+        &mut self,
+        source: &Path,
+        vars: &Map<String, Value>,
+        dotm: Option<&DotmContext>,
+    ) -> Result<Rendered> {
+        let name = template_name(&self.base_dir, source);
+        if !self.sources.contains_key(&name) {
+            let content = read_template(source)?;
+            self.sources
+                .insert(name.clone(), (relative_to(&self.base_dir, source), content.clone()));
+            self.tera
+                .add_raw_template(&name, &content)
+                .map_err(|e| TemplateError::from_tera(&e, &name, &self.sources))?;
+        }
+
+        self.used_secrets.store(false, Ordering::Relaxed);
+        *self.package_dir.lock().unwrap() = source
+            .strip_prefix(&self.packages_dir)
+            .ok()
+            .and_then(|rel| rel.components().next())
+            .map(|pkg| self.packages_dir.join(pkg));
+        *self.env.lock().unwrap() = environment(dotm);
+        let content = self.tera.render(&name, &build_context(vars, dotm)).map_err(|e| {
+            let mut err = TemplateError::from_tera(&e, &name, &self.sources);
+            if let Some(dotm) = dotm {
+                err.host = Some(dotm.host.to_string());
+                err.roles = dotm.roles.iter().map(|r| r.to_string()).collect();
+            }
+            err
+        })?;
 
-    tera.render("__dotm_template", &context)
-        .context("failed to render template")
+        Ok(Rendered {
+            content,
+            uses_secrets: self.used_secrets.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Name of a template file in the engine: its path relative to `base`.
+fn template_name(base: &Path, path: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn relative_to(base: &Path, path: &Path) -> PathBuf {
+    path.strip_prefix(base).unwrap_or(path).to_path_buf()
+}
+
+fn read_template(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("failed to read template: {}", path.display()))
+}
+
+/// All files under `dir`, recursively and sorted. A missing directory has none.
+fn walk_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !dir.is_dir() {
+        return Ok(files);
+    }
+    for entry in std::fs::read_dir(dir).with_context(|| format!("failed to read directory: {}", dir.display()))? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(walk_files(&path)?);
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn register_functions(
+    tera: &mut Tera,
+    secrets: Option<Arc<SecretStore>>,
+    used_secrets: Arc<AtomicBool>,
+    package_dir: Arc<Mutex<Option<PathBuf>>>,
+    env: Arc<Mutex<HashMap<String, String>>>,
+) {
+    tera.register_function(
+        "secret",
+        SecretFunction {
+            store: secrets,
+            used: used_secrets,
+        },
+    );
+    tera.register_function("command_exists", CommandExists { env: Arc::clone(&env) });
+    tera.register_function("env", Env { env });
+    tera.register_function("path_join", path_join);
+    tera.register_function(
+        "file_exists",
+        FileExists {
+            package_dir: Arc::clone(&package_dir),
+        },
+    );
+    tera.register_function("read_file", ReadFile { package_dir });
+
+    tera.register_filter("hex_to_rgb", hex_to_rgb);
+    tera.register_filter("lighten", lighten);
+    tera.register_filter("darken", darken);
+    tera.register_filter("to_json", to_json);
+    tera.register_filter("to_toml", to_toml);
+    tera.register_filter("to_yaml", to_yaml);
+}
+
+/// The environment `env()` and `command_exists()` see: the facts' when rendering
+/// with a `dotm` context, as `when` conditions do, else the process's.
+fn environment(dotm: Option<&DotmContext>) -> HashMap<String, String> {
+    match dotm {
+        Some(dotm) => dotm.facts.env.clone(),
+        None => std::env::vars().collect(),
+    }
+}
+
+fn build_context(vars: &Map<String, Value>, dotm: Option<&DotmContext>) -> tera::Context {
+    let mut context = toml_map_to_tera_context(vars);
+    if let Some(dotm) = dotm {
+        context.insert("dotm", dotm);
+    }
+    context
+}
+
+/// Tera function `secret(path="...")`, recording whether it was called.
+struct SecretFunction {
+    store: Option<Arc<SecretStore>>,
+    used: Arc<AtomicBool>,
+}
+
+impl tera::Function for SecretFunction {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let path = args
+            .get("path")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| tera::Error::msg("secret() requires a string `path` argument"))?;
+        let store = self.store.as_ref().ok_or_else(|| {
+            tera::Error::msg("secret() was called but no [dotm.secrets] backend is configured")
+        })?;
+        let value = store
+            .get(path)
+            .map_err(|e| tera::Error::msg(format!("{e:#}")))?;
+        self.used.store(true, Ordering::Relaxed);
+        Ok(tera::Value::String(value))
+    }
+}
+
+fn string_arg<'a>(args: &'a HashMap<String, tera::Value>, name: &str, function: &str) -> tera::Result<&'a str> {
+    args.get(name)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| tera::Error::msg(format!("{function}() requires a string `{name}` argument")))
+}
+
+/// `command_exists(name="sway")`: whether an executable is on `PATH`.
+struct CommandExists {
+    env: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl tera::Function for CommandExists {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let name = string_arg(args, "name", "command_exists")?;
+        let env = self.env.lock().unwrap();
+        let found = env.get("PATH").is_some_and(|path| crate::facts::find_command(path, name));
+        Ok(tera::Value::Bool(found))
+    }
+}
+
+/// `env(name="EDITOR", default="vi")`: an environment variable. Errors when it is
+/// unset and there is no default.
+struct Env {
+    env: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl tera::Function for Env {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let name = string_arg(args, "name", "env")?;
+        match (self.env.lock().unwrap().get(name), args.get("default")) {
+            (Some(value), _) => Ok(tera::Value::String(value.clone())),
+            (None, Some(default)) => Ok(default.clone()),
+            (None, None) => Err(tera::Error::msg(format!(
+                "environment variable '{name}' is not set and env() has no default"
+            ))),
+        }
+    }
+}
+
+/// `path_join(parts=["~/.config", "nvim"])`
+fn path_join(args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let parts = args
+        .get("parts")
+        .and_then(|v| v.as_array())
+        .ok_or_else(|| tera::Error::msg("path_join() requires an array `parts` argument"))?;
+    let mut path = PathBuf::new();
+    for part in parts {
+        let part = part
+            .as_str()
+            .ok_or_else(|| tera::Error::msg("path_join() parts must be strings"))?;
+        path.push(part);
+    }
+    Ok(tera::Value::String(path.to_string_lossy().into_owned()))
+}
+
+/// Resolve a path given to a template function: `~` is expanded and relative
+/// paths are relative to the package of the template being rendered.
+fn resolve_path(path: &str, package_dir: &Mutex<Option<PathBuf>>, function: &str) -> tera::Result<PathBuf> {
+    let expanded = PathBuf::from(shellexpand::tilde(path).as_ref());
+    if expanded.is_absolute() {
+        return Ok(expanded);
+    }
+    match package_dir.lock().unwrap().as_ref() {
+        Some(dir) => Ok(dir.join(expanded)),
+        None => Err(tera::Error::msg(format!(
+            "{function}(): relative path '{path}' needs a package template"
+        ))),
+    }
+}
+
+/// `file_exists(path="~/.local/bin/starship")`
+struct FileExists {
+    package_dir: Arc<Mutex<Option<PathBuf>>>,
+}
+
+impl tera::Function for FileExists {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let path = resolve_path(string_arg(args, "path", "file_exists")?, &self.package_dir, "file_exists")?;
+        Ok(tera::Value::Bool(path.exists()))
+    }
+}
+
+/// `read_file(path="snippets/prompt.sh")`: the contents of a file, relative to the package.
+struct ReadFile {
+    package_dir: Arc<Mutex<Option<PathBuf>>>,
+}
+
+impl tera::Function for ReadFile {
+    fn call(&self, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+        let path = resolve_path(string_arg(args, "path", "read_file")?, &self.package_dir, "read_file")?;
+        std::fs::read_to_string(&path)
+            .map(tera::Value::String)
+            .map_err(|e| tera::Error::msg(format!("read_file(): failed to read {}: {e}", path.display())))
+    }
+}
+
+/// Parse `#rrggbb`, `#rgb` (the `#` is optional) into its channels.
+fn parse_hex(value: &tera::Value, filter: &str) -> tera::Result<[u8; 3]> {
+    let invalid = || tera::Error::msg(format!("{filter}: expected a hex colour like \"#ff8800\", got {value}"));
+    let hex = value.as_str().ok_or_else(invalid)?;
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    let hex: String = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 => hex.to_string(),
+        _ => return Err(invalid()),
+    };
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn format_hex(rgb: [u8; 3]) -> tera::Value {
+    tera::Value::String(format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2]))
+}
+
+/// `"#ff8800" | hex_to_rgb` → `[255, 136, 0]`
+fn hex_to_rgb(value: &tera::Value, _: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let rgb = parse_hex(value, "hex_to_rgb")?;
+    Ok(serde_json::json!(rgb))
+}
+
+/// Mix a colour towards `toward` by the filter's `amount` (0.0 to 1.0).
+fn mix(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+    toward: u8,
+    filter: &str,
+) -> tera::Result<tera::Value> {
+    let amount = args
+        .get("amount")
+        .and_then(|v| v.as_f64())
+        .ok_or_else(|| tera::Error::msg(format!("{filter} requires a numeric `amount` argument")))?;
+    if !(0.0..=1.0).contains(&amount) {
+        return Err(tera::Error::msg(format!("{filter}: amount must be between 0 and 1")));
+    }
+    let rgb = parse_hex(value, filter)?
+        .map(|c| (f64::from(c) + (f64::from(toward) - f64::from(c)) * amount).round() as u8);
+    Ok(format_hex(rgb))
+}
+
+/// `"#336699" | lighten(amount=0.2)`
+fn lighten(value: &tera::Value, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    mix(value, args, 255, "lighten")
+}
+
+/// `"#336699" | darken(amount=0.2)`
+fn darken(value: &tera::Value, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    mix(value, args, 0, "darken")
+}
+
+/// `vars | to_json(pretty=true)`
+fn to_json(value: &tera::Value, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let pretty = args.get("pretty").and_then(|v| v.as_bool()).unwrap_or(false);
+    let json = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    };
+    json.map(tera::Value::String).map_err(|e| tera::Error::msg(format!("to_json: {e}")))
+}
+
+/// `settings | to_toml`: a document for tables, an inline value otherwise.
+fn to_toml(value: &tera::Value, _: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let toml = if value.is_object() {
+        toml::to_string(value).map_err(|e| tera::Error::msg(format!("to_toml: {e}")))?
+    } else {
+        Value::try_from(value)
+            .map_err(|e| tera::Error::msg(format!("to_toml: {e}")))?
+            .to_string()
+    };
+    Ok(tera::Value::String(toml))
+}
+
+/// `settings | to_yaml`: block-style YAML.
+fn to_yaml(value: &tera::Value, _: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let mut out = String::new();
+    match value {
+        tera::Value::Object(map) if !map.is_empty() => write_yaml_map(&mut out, map, 0),
+        tera::Value::Array(items) if !items.is_empty() => write_yaml_seq(&mut out, items, 0),
+        scalar => {
+            out.push_str(&yaml_scalar(scalar));
+            out.push('\n');
+        }
+    }
+    Ok(tera::Value::String(out))
+}
+
+fn write_yaml_map(out: &mut String, map: &serde_json::Map<String, tera::Value>, indent: usize) {
+    for (key, value) in map {
+        out.push_str(&" ".repeat(indent));
+        out.push_str(&yaml_string(key));
+        out.push(':');
+        write_yaml_child(out, value, indent);
+    }
+}
+
+fn write_yaml_seq(out: &mut String, items: &[tera::Value], indent: usize) {
+    for item in items {
+        out.push_str(&" ".repeat(indent));
+        out.push('-');
+        write_yaml_child(out, item, indent);
+    }
+}
+
+fn write_yaml_child(out: &mut String, value: &tera::Value, indent: usize) {
+    match value {
+        tera::Value::Object(map) if !map.is_empty() => {
+            out.push('\n');
+            write_yaml_map(out, map, indent + 2);
+        }
+        tera::Value::Array(items) if !items.is_empty() => {
+            out.push('\n');
+            write_yaml_seq(out, items, indent + 2);
+        }
+        scalar => {
+            out.push(' ');
+            out.push_str(&yaml_scalar(scalar));
+            out.push('\n');
+        }
+    }
+}
+
+fn yaml_scalar(value: &tera::Value) -> String {
+    match value {
+        tera::Value::Null => "null".to_string(),
+        tera::Value::String(s) => yaml_string(s),
+        tera::Value::Object(_) => "{}".to_string(),
+        tera::Value::Array(_) => "[]".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Strings stay plain when YAML would read them back as the same string;
+/// anything else is double-quoted (JSON strings are valid YAML).
+fn yaml_string(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '/'))
+        && !s.starts_with(['-', '.'])
+        && s.parse::<f64>().is_err()
+        && !matches!(
+            s.to_ascii_lowercase().as_str(),
+            "true" | "false" | "yes" | "no" | "on" | "off" | "null" | "y" | "n"
+        );
+    if plain {
+        s.to_string()
+    } else {
+        serde_json::to_string(s).unwrap_or_default()
+    }
 }
 
 fn toml_map_to_tera_context(vars: &Map<String, Value>) -> tera::Context {
@@ -1,3 +1,7 @@
+use crate::config::{VarSchema, VarType};
+use anyhow::{Context, Result, bail};
+use serde::Serialize;
+use std::collections::BTreeMap;
 use toml::map::Map;
 use toml::Value;
 
@@ -20,3 +24,303 @@ pub fn merge_vars(base: &Map<String, Value>, overlay: &Map<String, Value>) -> Ma
 
     result
 }
+
+/// A layer (e.g. `role 'dev'`) that set a var, and the value it set.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VarSource {
+    pub layer: String,
+    pub value: Value,
+}
+
+/// For each var (as a dotted path), the layers that set it in merge order.
+/// The last layer is the one whose value is used.
+pub type Provenance = BTreeMap<String, Vec<VarSource>>;
+
+/// Like [`merge_vars`], additionally recording in `provenance` which vars
+/// `layer` (e.g. `role 'dev'`) sets.
+pub fn merge_vars_tracked(
+    base: &Map<String, Value>,
+    overlay: &Map<String, Value>,
+    layer: &str,
+    provenance: &mut Provenance,
+) -> Map<String, Value> {
+    record_layer(overlay, "", layer, provenance);
+    merge_vars(base, overlay)
+}
+
+fn record_layer(map: &Map<String, Value>, prefix: &str, layer: &str, provenance: &mut Provenance) {
+    for (key, value) in map {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match value {
+            Value::Table(table) if !table.is_empty() => {
+                // A table replaces a scalar of the same name
+                provenance.remove(&path);
+                record_layer(table, &path, layer, provenance);
+            }
+            _ => {
+                // A scalar replaces a whole table of the same name
+                let nested = format!("{path}.");
+                provenance.retain(|k, _| !k.starts_with(&nested));
+                provenance.entry(path).or_default().push(VarSource {
+                    layer: layer.to_string(),
+                    value: value.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// A merged var, the layer that set it and the values it overrode.
+#[derive(Debug, Serialize)]
+pub struct VarEntry {
+    pub path: String,
+    pub value: Value,
+    pub source: String,
+    /// Earlier layers that set the var, in merge order
+    pub overrides: Vec<VarSource>,
+}
+
+/// The merged vars at or under `key` (a dotted path), or all of them.
+pub fn entries(provenance: &Provenance, key: Option<&str>) -> Vec<VarEntry> {
+    provenance
+        .iter()
+        .filter(|(path, _)| {
+            key.is_none_or(|key| path.as_str() == key || path.strip_prefix(key).is_some_and(|r| r.starts_with('.')))
+        })
+        .filter_map(|(path, sources)| {
+            let (last, earlier) = sources.split_last()?;
+            Some(VarEntry {
+                path: path.clone(),
+                value: last.value.clone(),
+                source: last.layer.clone(),
+                overrides: earlier.to_vec(),
+            })
+        })
+        .collect()
+}
+
+/// One line per var, `path = value`, with where it came from as a trailing comment.
+pub fn render_entries(entries: &[VarEntry]) -> String {
+    let assignments: Vec<String> = entries.iter().map(|e| format!("{} = {}", e.path, e.value)).collect();
+    let width = assignments.iter().map(|a| a.len()).max().unwrap_or(0);
+
+    let mut out = String::new();
+    for (entry, assignment) in entries.iter().zip(&assignments) {
+        out.push_str(&format!("{assignment:<width$}  # {}", entry.source));
+        if !entry.overrides.is_empty() {
+            let overridden: Vec<String> = entry
+                .overrides
+                .iter()
+                .rev()
+                .map(|o| format!("{} = {}", o.layer, o.value))
+                .collect();
+            out.push_str(&format!(", overrides {}", overridden.join(", ")));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Prefix of environment variables that override vars, e.g. `DOTM_VAR_EDITOR__THEME`.
+pub const ENV_PREFIX: &str = "DOTM_VAR_";
+
+/// Parse a TOML value, falling back to a plain string for bare words like `nord`.
+pub fn parse_value(value: &str) -> Value {
+    toml::from_str::<Map<String, Value>>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+/// Vars map holding `value` at the dotted `path`.
+pub fn from_path(path: &str, value: Value) -> Result<Map<String, Value>> {
+    if path.split('.').any(|segment| segment.is_empty()) {
+        bail!("invalid var name '{path}'");
+    }
+    let mut nested = value;
+    for segment in path.rsplit('.') {
+        let mut table = Map::new();
+        table.insert(segment.to_string(), nested);
+        nested = Value::Table(table);
+    }
+    match nested {
+        Value::Table(table) => Ok(table),
+        _ => unreachable!("a path has at least one segment"),
+    }
+}
+
+/// Parse a `--var key.path=value` override.
+pub fn parse_override(arg: &str) -> Result<Map<String, Value>> {
+    let Some((path, value)) = arg.split_once('=') else {
+        bail!("invalid var override '{arg}': expected key.path=value");
+    };
+    from_path(path.trim(), parse_value(value.trim())).with_context(|| format!("invalid var override '{arg}'"))
+}
+
+/// Var overrides from `DOTM_VAR_*` environment variables, as `(name, vars)` pairs
+/// sorted by name. `__` separates path segments and names are lowercased, so
+/// `DOTM_VAR_EDITOR__FONT_SIZE=12` sets `editor.font_size`.
+pub fn env_overrides(env: impl IntoIterator<Item = (String, String)>) -> Result<Vec<(String, Map<String, Value>)>> {
+    let mut overrides = Vec::new();
+    for (name, value) in env {
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let path = key.to_lowercase().replace("__", ".");
+        let vars = from_path(&path, parse_value(&value)).with_context(|| format!("invalid {name}"))?;
+        overrides.push((name, vars));
+    }
+    overrides.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(overrides)
+}
+
+/// The value at a dotted `path`, e.g. `editor.theme`.
+pub fn lookup<'a>(vars: &'a Map<String, Value>, path: &str) -> Option<&'a Value> {
+    let mut segments = path.split('.');
+    let mut value = vars.get(segments.next()?)?;
+    for segment in segments {
+        value = value.as_table()?.get(segment)?;
+    }
+    Some(value)
+}
+
+/// The defaults declared by a package's schema, as a vars map.
+pub fn schema_defaults(schema: &BTreeMap<String, VarSchema>) -> Map<String, Value> {
+    let mut defaults = Map::new();
+    for (path, var) in schema {
+        if let Some(ref default) = var.default
+            && let Ok(table) = from_path(path, default.clone())
+        {
+            defaults = merge_vars(&defaults, &table);
+        }
+    }
+    defaults
+}
+
+/// What is wrong with `value` for a var declared by `schema`, if anything,
+/// e.g. `must be a string, not integer 3`.
+pub fn check_value(schema: &VarSchema, value: &Value) -> Option<String> {
+    if let Some(var_type) = schema.var_type
+        && !var_type.matches(value)
+    {
+        let article = if matches!(var_type, VarType::Integer | VarType::Array) { "an" } else { "a" };
+        return Some(format!("must be {article} {var_type}, not {}", describe(value)));
+    }
+    if !schema.allowed.is_empty() && !schema.allowed.contains(value) {
+        let allowed: Vec<String> = schema.allowed.iter().map(|v| v.to_string()).collect();
+        return Some(format!("is {value}, expected one of: {}", allowed.join(", ")));
+    }
+    None
+}
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Table(_) => "a table".to_string(),
+        Value::Array(_) => "an array".to_string(),
+        other => format!("{} {other}", other.type_str()),
+    }
+}
+
+/// Check the vars seen by a package against its schema: required vars that aren't
+/// set, and values of the wrong type or not among the allowed ones.
+pub fn validate_schema(
+    package: &str,
+    schema: &BTreeMap<String, VarSchema>,
+    vars: &Map<String, Value>,
+    provenance: &Provenance,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+    for (path, var) in schema {
+        match lookup(vars, path) {
+            None if var.required => {
+                let description = var.description.as_ref().map(|d| format!(" ({d})")).unwrap_or_default();
+                errors.push(format!("package '{package}' requires var '{path}'{description}, which is not set"));
+            }
+            None => {}
+            Some(value) => {
+                if let Some(problem) = check_value(var, value) {
+                    let layer = provenance
+                        .get(path)
+                        .and_then(|sources| sources.last())
+                        .map(|s| format!(" (set by {})", s.layer))
+                        .unwrap_or_default();
+                    errors.push(format!("var '{path}'{layer} {problem}, for package '{package}'"));
+                }
+            }
+        }
+    }
+    errors
+}
+
+/// Vars that look like they belong to a schema but aren't declared in any. Each
+/// set var is followed down the tables that hold declared vars; it is unknown when it
+/// leaves them next to declared vars (like a typo of a sibling), or at the top level
+/// under a name close to a declared one (like a typo of the table).
+pub fn unknown_vars(schemas: &[&BTreeMap<String, VarSchema>], provenance: &Provenance) -> Vec<String> {
+    let declared: Vec<&String> = schemas.iter().flat_map(|schema| schema.keys()).collect();
+    // Tables holding declared vars: `editor` for `editor.font_size`
+    let declared_tables: Vec<&str> = declared
+        .iter()
+        .flat_map(|path| path.match_indices('.').map(|(i, _)| &path[..i]))
+        .collect();
+    let top_level: Vec<&str> = declared.iter().filter_map(|path| path.split('.').next()).collect();
+    let similar = |path: &str| {
+        declared
+            .iter()
+            .filter(|d| edit_distance(path, d) <= 2)
+            .min_by_key(|d| edit_distance(path, d))
+            .copied()
+    };
+
+    let mut errors = Vec::new();
+    for (path, sources) in provenance {
+        // Tables may be declared as a whole
+        let covered = declared
+            .iter()
+            .any(|d| path == *d || path.strip_prefix(d.as_str()).is_some_and(|rest| rest.starts_with('.')));
+        if covered {
+            continue;
+        }
+        let segments: Vec<&str> = path.split('.').collect();
+        let outside = (1..=segments.len())
+            .find(|&n| !declared_tables.contains(&segments[..n].join(".").as_str()))
+            .unwrap_or(segments.len());
+        let unknown = if outside > 1 {
+            true
+        } else {
+            let name = segments[0];
+            let threshold = (name.chars().count() / 3).max(1);
+            similar(path).is_some() || top_level.iter().any(|t| edit_distance(name, t) <= threshold)
+        };
+        if !unknown {
+            continue;
+        }
+
+        let layer = sources.last().map(|s| s.layer.as_str()).unwrap_or_default();
+        let mut message = format!("unknown var '{path}' (set by {layer})");
+        if let Some(similar) = similar(path) {
+            message.push_str(&format!(", did you mean '{similar}'?"));
+        }
+        errors.push(message);
+    }
+    errors
+}
+
+/// Levenshtein distance between two strings.
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -1,4 +1,4 @@
-use assert_cmd::Command;
+use assert_cmd::cargo::cargo_bin_cmd;
 use predicates::prelude::*;
 use std::path::Path;
 use tempfile::TempDir;
@@ -22,8 +22,7 @@ fn copy_dir_recursive(src: &Path, dst: &Path) {
 
 #[test]
 fn cli_version() {
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .arg("--version")
         .assert()
         .success()
@@ -32,8 +31,7 @@ fn cli_version() {
 
 #[test]
 fn cli_help() {
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .arg("--help")
         .assert()
         .success()
@@ -45,20 +43,50 @@ fn cli_check_valid_config() {
     let dotfiles = TempDir::new().unwrap();
     copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
 
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .args(["-d", dotfiles.path().to_str().unwrap(), "check"])
         .assert()
         .success()
         .stdout(predicate::str::contains("valid"));
 }
 
+#[test]
+fn cli_check_reports_duplicate_packages() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    std::fs::create_dir_all(dotfiles.path().join("dotm.d")).unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.d/extra.toml"),
+        "\n[packages.shell]\ndescription = \"again\"\n",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "check"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("package 'shell' is defined more than once"))
+        .stderr(predicate::str::contains("dotm.toml:4"))
+        .stderr(predicate::str::contains("extra.toml:2"));
+
+    // Other commands still run; deploy refuses to
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "list", "packages"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("shell"));
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "deploy", "--host", "testhost", "--dry-run"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("package 'shell' is defined more than once"));
+}
+
 #[test]
 fn cli_check_missing_config() {
     let empty = TempDir::new().unwrap();
 
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .args(["-d", empty.path().to_str().unwrap(), "check"])
         .assert()
         .failure();
@@ -69,8 +97,7 @@ fn cli_init_creates_package_dir() {
     let dotfiles = TempDir::new().unwrap();
     copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
 
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .args(["-d", dotfiles.path().to_str().unwrap(), "init", "newpkg"])
         .assert()
         .success()
@@ -84,8 +111,7 @@ fn cli_deploy_dry_run() {
     let dotfiles = TempDir::new().unwrap();
     copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
 
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .args([
             "-d",
             dotfiles.path().to_str().unwrap(),
@@ -106,8 +132,7 @@ fn cli_status_no_state() {
     copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
 
     // Override XDG_STATE_HOME so status doesn't pick up the real user's state
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .env("XDG_STATE_HOME", state_dir.path())
         .args(["-d", dotfiles.path().to_str().unwrap(), "status"])
         .assert()
@@ -115,10 +140,55 @@ fn cli_status_no_state() {
         .stdout(predicate::str::contains("No files"));
 }
 
+#[test]
+fn cli_deploy_with_auto_gc() {
+    let dotfiles = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        config.replace("[dotm]\n", "[dotm]\nauto_gc = true\nkeep_generations = 1\n"),
+    )
+    .unwrap();
+
+    for version in 1..=2 {
+        std::fs::write(
+            dotfiles.path().join("packages/shell/.bashrc"),
+            format!("# version {version}\n"),
+        )
+        .unwrap();
+        cargo_bin_cmd!("dotm")
+            .env("HOME", target.path())
+            .env("XDG_STATE_HOME", state_dir.path())
+            .args(["-d", dotfiles.path().to_str().unwrap(), "deploy", "--host", "testhost"])
+            .assert()
+            .success()
+            .stderr(predicate::str::contains("garbage collection failed").not());
+    }
+
+    let generations = std::fs::read_dir(state_dir.path().join("dotm/generations")).unwrap().count();
+    assert_eq!(generations, 1);
+}
+
+#[test]
+fn cli_history_no_generations() {
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    cargo_bin_cmd!("dotm")
+        .env("XDG_STATE_HOME", state_dir.path())
+        .args(["-d", dotfiles.path().to_str().unwrap(), "history"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No deploy generations"));
+}
+
 #[test]
 fn cli_completions_bash() {
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .args(["completions", "bash"])
         .assert()
         .success()
@@ -127,8 +197,7 @@ fn cli_completions_bash() {
 
 #[test]
 fn cli_completions_zsh() {
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .args(["completions", "zsh"])
         .assert()
         .success()
@@ -140,8 +209,7 @@ fn cli_list_packages() {
     let dotfiles = TempDir::new().unwrap();
     copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
 
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .args(["-d", dotfiles.path().to_str().unwrap(), "list", "packages"])
         .assert()
         .success()
@@ -154,8 +222,7 @@ fn cli_list_roles() {
     let dotfiles = TempDir::new().unwrap();
     copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
 
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .args(["-d", dotfiles.path().to_str().unwrap(), "list", "roles"])
         .assert()
         .success()
@@ -163,13 +230,36 @@ fn cli_list_roles() {
         .stdout(predicate::str::contains("dev"));
 }
 
+#[test]
+fn cli_list_roles_json_keeps_conditions() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    std::fs::write(
+        dotfiles.path().join("roles/dev.toml"),
+        "packages = [{ name = \"editor\", when = \"os == linux\" }]\n",
+    )
+    .unwrap();
+
+    let output = cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "--json", "list", "roles"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let dev = doc["roles"].as_array().unwrap().iter().find(|r| r["name"] == "dev").unwrap();
+    assert_eq!(dev["packages"][0]["name"], "editor");
+    assert_eq!(dev["packages"][0]["when"], "os == linux");
+    let desktop = doc["roles"].as_array().unwrap().iter().find(|r| r["name"] == "desktop").unwrap();
+    assert_eq!(desktop["packages"][0], serde_json::json!({ "name": "shell" }));
+}
+
 #[test]
 fn cli_list_hosts() {
     let dotfiles = TempDir::new().unwrap();
     copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
 
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .args(["-d", dotfiles.path().to_str().unwrap(), "list", "hosts"])
         .assert()
         .success()
@@ -181,8 +271,7 @@ fn cli_list_hosts_tree() {
     let dotfiles = TempDir::new().unwrap();
     copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
 
-    Command::cargo_bin("dotm")
-        .unwrap()
+    cargo_bin_cmd!("dotm")
         .args([
             "-d",
             dotfiles.path().to_str().unwrap(),
@@ -195,3 +284,407 @@ fn cli_list_hosts_tree() {
         .stdout(predicate::str::contains("testhost"))
         .stdout(predicate::str::contains("shell"));
 }
+
+#[test]
+fn cli_deploy_dry_run_json() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    let output = cargo_bin_cmd!("dotm")
+        .args([
+            "-d",
+            dotfiles.path().to_str().unwrap(),
+            "deploy",
+            "--host",
+            "testhost",
+            "--dry-run",
+            "--format",
+            "json",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(doc["schema"], "dotm.deploy");
+    assert_eq!(doc["version"], 1);
+    assert_eq!(doc["dry_run"], true);
+    assert_eq!(doc["dry_run_actions"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn cli_status_json_no_state() {
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    let output = cargo_bin_cmd!("dotm")
+        .env("XDG_STATE_HOME", state_dir.path())
+        .args(["-d", dotfiles.path().to_str().unwrap(), "status", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(doc["schema"], "dotm.status");
+    assert_eq!(doc["total"], 0);
+    assert!(doc["packages"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn cli_list_packages_json() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    let output = cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "--json", "list", "packages"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(doc["schema"], "dotm.packages");
+    let names: Vec<&str> = doc["packages"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| p["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, vec!["editor", "shell"]);
+    assert_eq!(doc["packages"][0]["depends"][0], "shell");
+}
+
+#[test]
+fn cli_list_hosts_tree_json() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    let output = cargo_bin_cmd!("dotm")
+        .args([
+            "-d",
+            dotfiles.path().to_str().unwrap(),
+            "list",
+            "hosts",
+            "--tree",
+            "--json",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(doc["schema"], "dotm.tree");
+    assert_eq!(doc["hosts"][0]["name"], "testhost");
+    assert!(doc["hosts"][0]["roles"][0]["packages"].is_array());
+}
+
+#[test]
+fn cli_check_reports_role_include_cycle() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    std::fs::write(
+        dotfiles.path().join("roles/dev.toml"),
+        "includes = [\"desktop\"]\npackages = [\"editor\"]\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dotfiles.path().join("roles/desktop.toml"),
+        "includes = [\"dev\"]\npackages = [\"shell\"]\n",
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "check"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("circular role include detected"));
+}
+
+#[test]
+fn cli_check_reports_invalid_condition() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    let config_path = dotfiles.path().join("dotm.toml");
+    let config = std::fs::read_to_string(&config_path).unwrap();
+    std::fs::write(
+        &config_path,
+        config.replace(
+            "description = \"Shell configuration\"",
+            "description = \"Shell configuration\"\nwhen = \"kernel == 6\"",
+        ),
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "check"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("package 'shell' has an invalid condition"));
+}
+
+#[test]
+fn cli_check_reports_all_template_errors() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    let pkg_dir = dotfiles.path().join("packages/editor/.config/nvim");
+    std::fs::write(pkg_dir.join("colors.lua.tera"), "vim.cmd('colorscheme {{ editor.themes }}')\n").unwrap();
+    std::fs::write(pkg_dir.join("prompt.lua.tera"), "-- {{ shell.prompt }}\n-- {{ undefined_var }}\n").unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "check"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "packages/editor/.config/nvim/colors.lua.tera:1:25: variable `editor.themes` is not defined",
+        ))
+        .stderr(predicate::str::contains("host 'testhost' with roles: desktop, dev"))
+        .stderr(predicate::str::contains("did you mean `editor.theme` (set by host 'testhost')?"))
+        .stderr(predicate::str::contains(
+            "packages/editor/.config/nvim/prompt.lua.tera:2:7: variable `undefined_var` is not defined",
+        ));
+}
+
+#[test]
+fn cli_reports_invalid_overrides_once() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    std::fs::write(dotfiles.path().join("hosts/otherhost.toml"), "roles = [\"desktop\"]\n").unwrap();
+    std::fs::write(dotfiles.path().join("packages/shell/.bashrc##hots.testhost"), "typo\n").unwrap();
+    let dir = dotfiles.path().to_str().unwrap();
+    let target = TempDir::new().unwrap();
+
+    let output = cargo_bin_cmd!("dotm").args(["-d", dir, "check"]).output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("invalid override .bashrc##hots.testhost").count(), 1, "{stderr}");
+
+    let output = cargo_bin_cmd!("dotm")
+        .args(["-d", dir, "--json", "deploy", "--host", "testhost", "--dry-run"])
+        .env("HOME", target.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(!String::from_utf8(output.stderr).unwrap().contains("invalid override"));
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let invalid = doc["invalid_overrides"].as_array().unwrap();
+    assert_eq!(invalid.len(), 1);
+    assert!(invalid[0].as_str().unwrap().starts_with("package 'shell': invalid override .bashrc##hots.testhost:"));
+}
+
+#[test]
+fn cli_check_warns_about_tied_overrides() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    let pkg_dir = dotfiles.path().join("packages/shell");
+    std::fs::write(pkg_dir.join(".bashrc##host.testhost,role.desktop"), "a\n").unwrap();
+    std::fs::write(pkg_dir.join(".bashrc##role.desktop,host.testhost"), "b\n").unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "check"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "warning: host 'testhost': overrides for .bashrc are equally specific: \
+             packages/shell/.bashrc##host.testhost,role.desktop, packages/shell/.bashrc##role.desktop,host.testhost; using the first",
+        ));
+
+    std::fs::write(pkg_dir.join(".profile##hots.testhost"), "c\n").unwrap();
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "check"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "package 'shell': invalid override .profile##hots.testhost: unknown override selector 'hots'",
+        ));
+}
+
+#[test]
+fn cli_vars_shows_provenance() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    std::fs::write(
+        dotfiles.path().join("roles/dev.toml"),
+        "packages = [\"editor\"]\n\n[vars]\neditor.theme = \"default\"\neditor.font = \"mono\"\n",
+    )
+    .unwrap();
+    let dir = dotfiles.path().to_str().unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dir, "vars", "--host", "testhost", "editor"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("editor.font = \"mono\"      # role 'dev'\n"))
+        .stdout(predicate::str::contains(
+            "editor.theme = \"gruvbox\"  # host 'testhost', overrides role 'dev' = \"default\"\n",
+        ))
+        .stdout(predicate::str::contains("shell.prompt").not());
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dir, "vars", "--host", "testhost", "editor.colour"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("var 'editor.colour' is not set for host 'testhost'"));
+}
+
+#[test]
+fn cli_vars_shows_overrides() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "vars", "--host", "testhost", "--var", "editor.theme=nord"])
+        .env("DOTM_VAR_SHELL__PROMPT", "minimal")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "editor.theme = \"nord\"     # --var, overrides host 'testhost' = \"gruvbox\"",
+        ))
+        .stdout(predicate::str::contains(
+            "shell.prompt = \"minimal\"  # env DOTM_VAR_SHELL__PROMPT, overrides role 'desktop' = \"fancy\"",
+        ));
+}
+
+#[test]
+fn cli_check_validates_vars_against_package_schema() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    let config_path = dotfiles.path().join("dotm.toml");
+    let config = std::fs::read_to_string(&config_path).unwrap();
+    std::fs::write(
+        &config_path,
+        format!(
+            "{config}\n[packages.editor.schema]\n\
+             \"editor.theme\" = {{ type = \"string\", allowed = [\"nord\", \"gruvbox\"] }}\n\
+             \"editor.font\" = {{ type = \"string\", required = true }}\n\
+             \"editor.size\" = {{ type = \"integer\", default = \"big\" }}\n"
+        ),
+    )
+    .unwrap();
+    let host_path = dotfiles.path().join("hosts/testhost.toml");
+    let host = std::fs::read_to_string(&host_path).unwrap();
+    std::fs::write(&host_path, format!("{host}editor.themes = \"nord\"\n")).unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "check"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "package 'editor': default for var 'editor.size' must be an integer, not string \"big\"",
+        ))
+        .stderr(predicate::str::contains(
+            "host 'testhost': package 'editor' requires var 'editor.font', which is not set",
+        ))
+        .stderr(predicate::str::contains(
+            "host 'testhost': unknown var 'editor.themes' (set by host 'testhost'), did you mean 'editor.theme'?",
+        ));
+}
+
+#[test]
+fn cli_deploy_dry_run_shows_var_overrides() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    let target = TempDir::new().unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args([
+            "-d",
+            dotfiles.path().to_str().unwrap(),
+            "deploy",
+            "--host",
+            "testhost",
+            "--dry-run",
+            "--var",
+            "editor.theme=nord",
+        ])
+        .env("HOME", target.path())
+        .env("DOTM_VAR_SHELL__PROMPT", "minimal")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Var overrides:"))
+        .stdout(predicate::str::contains(
+            "editor.theme = \"nord\"     # --var, overrides host 'testhost' = \"gruvbox\"",
+        ))
+        .stdout(predicate::str::contains(
+            "shell.prompt = \"minimal\"  # env DOTM_VAR_SHELL__PROMPT, overrides role 'desktop' = \"fancy\"",
+        ));
+}
+
+#[test]
+fn cli_list_hosts_shows_resolved_host_file() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    std::fs::write(dotfiles.path().join("hosts/default.toml"), "roles = [\"desktop\"]\n").unwrap();
+    let dir = dotfiles.path().to_str().unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dir, "list", "hosts", "--host", "container-3f9a"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("container-3f9a resolves to hosts/default.toml (default)"));
+
+    // An unknown hostname deploys with the default host config
+    let target = TempDir::new().unwrap();
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dir, "deploy", "--host", "container-3f9a", "--dry-run"])
+        .env("HOME", target.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".bashrc"))
+        .stdout(predicate::str::contains("init.lua").not());
+}
+
+#[test]
+fn cli_add_leaves_ignored_files_in_place() {
+    let dotfiles = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        format!(
+            "[dotm]\ntarget = \"~\"\n\n[packages.nvim]\ntarget = \"{}\"\nignore = [\".git/\"]\n",
+            target.path().display()
+        ),
+    )
+    .unwrap();
+    std::fs::create_dir_all(dotfiles.path().join("packages/nvim")).unwrap();
+    let nvim = target.path().join(".config/nvim");
+    std::fs::create_dir_all(nvim.join(".git")).unwrap();
+    std::fs::write(nvim.join("init.lua"), "-- init").unwrap();
+    std::fs::write(nvim.join(".git/HEAD"), "ref").unwrap();
+    let dir = dotfiles.path().to_str().unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dir, "add", "nvim", nvim.join(".git").to_str().unwrap()])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("error: .config/nvim/.git is ignored in package 'nvim'"));
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dir, "add", "nvim", nvim.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("left 1 ignored file(s) in place"));
+    assert!(dotfiles.path().join("packages/nvim/.config/nvim/init.lua").is_file());
+    assert!(!dotfiles.path().join("packages/nvim/.config/nvim/.git").exists());
+    assert!(nvim.join(".git/HEAD").is_file());
+    assert!(!nvim.join("init.lua").exists());
+}
+
+#[test]
+fn cli_check_reports_invalid_renames() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        config.replace("[packages.shell]", "[packages.shell]\nrename = { \".bashrc\" = \"../.bashrc\" }"),
+    )
+    .unwrap();
+
+    cargo_bin_cmd!("dotm")
+        .args(["-d", dotfiles.path().to_str().unwrap(), "check"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "package 'shell': invalid rename '.bashrc' = '../.bashrc': both must be relative paths without '..'",
+        ));
+}
@@ -0,0 +1,31 @@
+//! Fixture helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use dotm::orchestrator::{DeployReport, Orchestrator};
+use std::path::Path;
+
+/// Copy a fixture directory, leaving out any `.staged/` left behind by other runs.
+pub fn copy_dir_recursive(src: &Path, dst: &Path) {
+    for entry in std::fs::read_dir(src).unwrap() {
+        let entry = entry.unwrap();
+        let src_path = entry.path();
+        let dst_path = dst.join(entry.file_name());
+        if src_path.is_dir() {
+            if src_path.file_name().unwrap() == ".staged" {
+                continue;
+            }
+            std::fs::create_dir_all(&dst_path).unwrap();
+            copy_dir_recursive(&src_path, &dst_path);
+        } else {
+            std::fs::copy(&src_path, &dst_path).unwrap();
+        }
+    }
+}
+
+/// Deploy `testhost` from `dotfiles` into `target`, tracking state in `state_dir`.
+pub fn deploy(dotfiles: &Path, target: &Path, state_dir: &Path) -> DeployReport {
+    let mut orch = Orchestrator::new(dotfiles, target)
+        .unwrap()
+        .with_state_dir(state_dir);
+    orch.deploy("testhost", false, false).unwrap()
+}
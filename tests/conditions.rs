@@ -0,0 +1,207 @@
+use dotm::condition::{Condition, evaluate};
+use dotm::facts::Facts;
+use dotm::orchestrator::Orchestrator;
+use std::collections::HashMap;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::TempDir;
+use toml::map::Map;
+use toml::Value;
+
+fn facts(path: &Path) -> Facts {
+    Facts {
+        os: "linux".to_string(),
+        arch: "x86_64".to_string(),
+        distro: Some("arch".to_string()),
+        hostname: "box".to_string(),
+        env: HashMap::from([
+            ("PATH".to_string(), path.display().to_string()),
+            ("WAYLAND_DISPLAY".to_string(), "wayland-0".to_string()),
+        ]),
+        ..Default::default()
+    }
+}
+
+fn bin_dir_with(commands: &[&str]) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    for name in commands {
+        let path = dir.path().join(name);
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    dir
+}
+
+fn vars(toml_src: &str) -> Map<String, Value> {
+    toml::from_str(toml_src).unwrap()
+}
+
+#[test]
+fn evaluate_facts_env_and_vars() {
+    let bin = bin_dir_with(&[]);
+    let facts = facts(bin.path());
+    let vars = vars("laptop = true\n[gpu]\nvendor = \"amd\"\n");
+
+    let check = |expr: &str| evaluate(Some(expr), &facts, &vars).unwrap();
+    assert!(check("os == linux && arch == \"x86_64\""));
+    assert!(check("distro != 'fedora'"));
+    assert!(check("env.WAYLAND_DISPLAY"));
+    assert!(!check("env.DISPLAY"));
+    assert!(check("vars.laptop && vars.gpu.vendor == amd"));
+    assert!(!check("vars.missing || hostname == other"));
+    assert!(check("!(os == macos)"));
+    assert!(evaluate(None, &facts, &vars).unwrap());
+}
+
+#[test]
+fn evaluate_command_searches_path() {
+    let bin = bin_dir_with(&["sway"]);
+    std::fs::write(bin.path().join("notexec"), "").unwrap();
+    let facts = facts(bin.path());
+
+    assert!(evaluate(Some("command('sway')"), &facts, &Map::new()).unwrap());
+    assert!(!evaluate(Some("command('hyprland')"), &facts, &Map::new()).unwrap());
+    assert!(!evaluate(Some("command('notexec')"), &facts, &Map::new()).unwrap());
+}
+
+#[test]
+fn parse_errors_name_the_problem() {
+    let err = Condition::parse("kernel == 6").unwrap_err();
+    assert!(err.to_string().contains("unknown fact 'kernel'"));
+    let err = Condition::parse("command(sway)").unwrap_err();
+    assert!(err.to_string().contains("quoted command name"));
+}
+
+fn setup_dotfiles() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    std::fs::write(
+        root.join("dotm.toml"),
+        r#"[dotm]
+target = "~"
+
+[packages.shell]
+
+[packages.sway]
+when = "command('sway')"
+
+[packages.mac]
+when = "os == macos"
+
+[packages.gpu]
+"#,
+    )
+    .unwrap();
+    for (pkg, file) in [("shell", ".bashrc"), ("sway", ".config/sway/config"), ("mac", ".macrc"), ("gpu", ".gpurc")] {
+        let path = root.join("packages").join(pkg).join(file);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, pkg).unwrap();
+    }
+    std::fs::create_dir_all(root.join("roles")).unwrap();
+    std::fs::write(
+        root.join("roles/desktop.toml"),
+        "packages = [\"shell\", \"sway\", \"mac\", { name = \"gpu\", when = \"vars.gpu\" }]\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("hosts")).unwrap();
+    std::fs::write(root.join("hosts/box.toml"), "hostname = \"box\"\nroles = [\"desktop\"]\n").unwrap();
+    dir
+}
+
+#[test]
+fn deploy_skips_packages_whose_condition_is_false() {
+    let dotfiles = setup_dotfiles();
+    let target = TempDir::new().unwrap();
+    let bin = bin_dir_with(&[]);
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_facts(facts(bin.path()));
+    let report = orch.deploy("box", false, false).unwrap();
+
+    assert!(target.path().join(".bashrc").exists());
+    assert!(!target.path().join(".config/sway/config").exists());
+    assert!(!target.path().join(".macrc").exists());
+    assert!(!target.path().join(".gpurc").exists(), "role entry condition is false");
+    assert_eq!(report.skipped_packages, vec!["sway", "mac"]);
+}
+
+#[test]
+fn deploy_includes_packages_whose_condition_holds() {
+    let dotfiles = setup_dotfiles();
+    std::fs::write(
+        dotfiles.path().join("hosts/box.toml"),
+        "hostname = \"box\"\nroles = [\"desktop\"]\n\n[vars]\ngpu = \"amd\"\n",
+    )
+    .unwrap();
+    let target = TempDir::new().unwrap();
+    let bin = bin_dir_with(&["sway"]);
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_facts(facts(bin.path()));
+    let report = orch.deploy("box", false, false).unwrap();
+
+    assert!(target.path().join(".config/sway/config").exists());
+    assert!(target.path().join(".gpurc").exists());
+    assert_eq!(report.skipped_packages, vec!["mac"]);
+}
+
+#[test]
+fn hostname_condition_uses_the_deployed_host() {
+    let dotfiles = setup_dotfiles();
+    std::fs::write(
+        dotfiles.path().join("roles/desktop.toml"),
+        "packages = [\"shell\", { name = \"gpu\", when = \"hostname == laptop\" }]\n",
+    )
+    .unwrap();
+    std::fs::write(dotfiles.path().join("hosts/laptop.toml"), "roles = [\"desktop\"]\n").unwrap();
+    let target = TempDir::new().unwrap();
+    let bin = bin_dir_with(&[]);
+
+    // The machine is "box", but --host laptop deploys the laptop config
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_facts(facts(bin.path()));
+    orch.deploy("laptop", false, false).unwrap();
+
+    assert!(target.path().join(".gpurc").exists());
+}
+
+#[test]
+fn templates_see_the_same_environment_as_conditions() {
+    let dotfiles = setup_dotfiles();
+    std::fs::write(
+        dotfiles.path().join("packages/sway/.config/sway/env.tera"),
+        "{{ command_exists(name=\"sway\") }} {{ env(name=\"WAYLAND_DISPLAY\") }}",
+    )
+    .unwrap();
+    let target = TempDir::new().unwrap();
+    let bin = bin_dir_with(&["sway"]);
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_facts(facts(bin.path()));
+    orch.deploy("box", false, false).unwrap();
+
+    let rendered = std::fs::read_to_string(target.path().join(".config/sway/env")).unwrap();
+    assert_eq!(rendered, "true wayland-0");
+}
+
+#[test]
+fn deploy_fails_on_invalid_condition() {
+    let dotfiles = setup_dotfiles();
+    std::fs::write(
+        dotfiles.path().join("roles/desktop.toml"),
+        "packages = [{ name = \"shell\", when = \"os ==\" }]\n",
+    )
+    .unwrap();
+    let target = TempDir::new().unwrap();
+    let bin = bin_dir_with(&[]);
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_facts(facts(bin.path()));
+    let err = orch.deploy("box", false, false).unwrap_err();
+    assert!(format!("{err:#}").contains("invalid condition for package 'shell'"));
+}
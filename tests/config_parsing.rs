@@ -1,4 +1,4 @@
-use dotm::config::{validate_system_packages, HostConfig, RoleConfig, RootConfig};
+use dotm::config::{validate_fold, validate_system_packages, FoldConfig, HostConfig, RoleConfig, RootConfig};
 
 #[test]
 fn parse_minimal_root_config() {
@@ -99,7 +99,9 @@ packages = ["games", "gamemode"]
 gamemode.renice = 10
 "#;
     let config: RoleConfig = toml::from_str(toml_str).unwrap();
-    assert_eq!(config.packages, vec!["games", "gamemode"]);
+    let names: Vec<&str> = config.packages.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, ["games", "gamemode"]);
+    assert!(config.packages.iter().all(|p| p.when.is_none()));
     let gamemode = config.vars.get("gamemode").unwrap().as_table().unwrap();
     assert_eq!(gamemode.get("renice").unwrap().as_integer().unwrap(), 10);
 }
@@ -110,7 +112,9 @@ fn parse_role_config_no_vars() {
 packages = ["zsh", "ssh"]
 "#;
     let config: RoleConfig = toml::from_str(toml_str).unwrap();
-    assert_eq!(config.packages, vec!["zsh", "ssh"]);
+    let names: Vec<&str> = config.packages.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, ["zsh", "ssh"]);
+    assert!(config.packages.iter().all(|p| p.when.is_none()));
     assert!(config.vars.is_empty());
 }
 
@@ -289,3 +293,38 @@ group = "root"
     let errors = validate_system_packages(&config);
     assert!(errors.is_empty(), "expected no errors, got: {:?}", errors);
 }
+
+#[test]
+fn parse_fold_settings() {
+    let toml_str = r#"
+[dotm]
+target = "~"
+[packages.all]
+fold = true
+[packages.some]
+fold = [".config/nvim"]
+[packages.none]
+"#;
+    let config: RootConfig = toml::from_str(toml_str).unwrap();
+    assert_eq!(config.packages["all"].fold, FoldConfig::All(true));
+    assert_eq!(
+        config.packages["some"].fold,
+        FoldConfig::Paths(vec![".config/nvim".to_string()])
+    );
+    assert!(!config.packages["none"].fold.is_enabled());
+}
+
+#[test]
+fn validate_fold_requires_stage_strategy() {
+    let toml_str = r#"
+[dotm]
+target = "~"
+[packages.bad]
+strategy = "copy"
+fold = true
+"#;
+    let config: RootConfig = toml::from_str(toml_str).unwrap();
+    let err = validate_fold("bad", &config.packages["bad"]).unwrap_err();
+    assert!(err.to_string().contains("fold requires the stage or link strategy"));
+    assert!(validate_system_packages(&config).is_empty());
+}
@@ -28,6 +28,9 @@ fn copy_strategy_status_detects_drift() {
         original_owner: None,
         original_group: None,
         original_mode: None,
+        sensitive: false,
+        encrypted_hash: None,
+        linked: false,
     });
 
     // File not modified yet
@@ -21,7 +21,7 @@ fn stage_base_file_copies_to_staging_and_symlinks_target() {
         kind: EntryKind::Base,
     };
 
-    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, None).unwrap();
+    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, None, false).unwrap();
     assert!(matches!(result, DeployResult::Created));
 
     // Staged file should be a real file with the right content
@@ -55,7 +55,7 @@ fn stage_template_renders_to_staging_and_symlinks_target() {
     };
 
     let rendered = "rendered template output";
-    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, Some(rendered)).unwrap();
+    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, Some(rendered.as_bytes()), false).unwrap();
     assert!(matches!(result, DeployResult::Created));
 
     // Staged file should contain the rendered content
@@ -73,6 +73,29 @@ fn stage_template_renders_to_staging_and_symlinks_target() {
     );
 }
 
+#[test]
+fn stage_private_template_is_created_with_mode_600() {
+    let staging_dir = TempDir::new().unwrap();
+    let target_dir = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+
+    let source_path = source_dir.path().join("netrc.tera");
+    std::fs::write(&source_path, "{{ secret(\"netrc\") }}").unwrap();
+
+    let action = FileAction {
+        source: source_path,
+        target_rel_path: PathBuf::from(".netrc"),
+        kind: EntryKind::Template,
+    };
+
+    deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, Some(b"machine x password y"), true).unwrap();
+
+    let staged = staging_dir.path().join(".netrc");
+    let mode = std::fs::metadata(&staged).unwrap().permissions().mode() & 0o777;
+    assert_eq!(mode, 0o600);
+    assert_eq!(std::fs::read_to_string(&staged).unwrap(), "machine x password y");
+}
+
 #[test]
 fn stage_preserves_source_permissions() {
     let staging_dir = TempDir::new().unwrap();
@@ -89,7 +112,7 @@ fn stage_preserves_source_permissions() {
         kind: EntryKind::Base,
     };
 
-    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, None).unwrap();
+    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, None, false).unwrap();
     assert!(matches!(result, DeployResult::Created));
 
     let staged = staging_dir.path().join("script.sh");
@@ -111,7 +134,7 @@ fn copy_strategy_copies_directly_to_target() {
         kind: EntryKind::Base,
     };
 
-    let result = deploy_copy(&action, target_dir.path(), false, false, None).unwrap();
+    let result = deploy_copy(&action, target_dir.path(), false, false, None, false).unwrap();
     assert!(matches!(result, DeployResult::Created));
 
     let target = target_dir.path().join(".config/app.conf");
@@ -138,7 +161,7 @@ fn stage_detects_conflict_with_unmanaged_file() {
         kind: EntryKind::Base,
     };
 
-    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, None).unwrap();
+    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, None, false).unwrap();
     assert!(matches!(result, DeployResult::Conflict(_)));
 
     // Nothing should have been staged
@@ -163,7 +186,7 @@ fn stage_force_overwrites_unmanaged_file() {
         kind: EntryKind::Base,
     };
 
-    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, true, None).unwrap();
+    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, true, None, false).unwrap();
     assert!(matches!(result, DeployResult::Updated));
 
     // Staged file should exist
@@ -191,7 +214,7 @@ fn stage_dry_run_creates_nothing() {
         kind: EntryKind::Base,
     };
 
-    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), true, false, None).unwrap();
+    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), true, false, None, false).unwrap();
     assert!(matches!(result, DeployResult::DryRun));
 
     assert!(!staging_dir.path().join(".config/app.conf").exists(), "dry run should not create staged file");
@@ -235,11 +258,11 @@ fn deploy_staged_returns_updated_when_replacing_symlink() {
     };
 
     // First deploy — should be Created
-    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, None).unwrap();
+    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, None, false).unwrap();
     assert!(matches!(result, DeployResult::Created));
 
     // Second deploy — target is now a symlink, should be Updated
-    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, None).unwrap();
+    let result = deploy_staged(&action, staging_dir.path(), target_dir.path(), false, false, None, false).unwrap();
     assert!(matches!(result, DeployResult::Updated));
 }
 
@@ -258,10 +281,10 @@ fn deploy_copy_returns_updated_when_replacing_file() {
     };
 
     // First deploy — should be Created
-    let result = deploy_copy(&action, target_dir.path(), false, false, None).unwrap();
+    let result = deploy_copy(&action, target_dir.path(), false, false, None, false).unwrap();
     assert!(matches!(result, DeployResult::Created));
 
     // Second deploy — target already exists, should be Updated
-    let result = deploy_copy(&action, target_dir.path(), false, true, None).unwrap();
+    let result = deploy_copy(&action, target_dir.path(), false, true, None, false).unwrap();
     assert!(matches!(result, DeployResult::Updated));
 }
@@ -0,0 +1,261 @@
+mod common;
+
+use common::{copy_dir_recursive, deploy};
+use dotm::orchestrator::Orchestrator;
+use dotm::scanner::EntryKind;
+use dotm::state::DeployState;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Copy the basic fixture and give the editor package the given `fold` setting.
+fn setup_fold_fixture(dotfiles: &Path, fold: &str) {
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles);
+    let config = std::fs::read_to_string(dotfiles.join("dotm.toml")).unwrap();
+    let config = config.replace(
+        "suggests = [\"theme\"]",
+        &format!("suggests = [\"theme\"]\nfold = {fold}"),
+    );
+    std::fs::write(dotfiles.join("dotm.toml"), config).unwrap();
+    std::fs::write(
+        dotfiles.join("packages/editor/.config/nvim/plugins.lua"),
+        "return {}\n",
+    )
+    .unwrap();
+}
+
+#[test]
+fn fold_path_deploys_directory_as_single_symlink() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "[\".config/nvim\"]");
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    let nvim = target.path().join(".config/nvim");
+    assert!(nvim.is_symlink(), "folded directory should be a symlink");
+    assert!(!target.path().join(".config").is_symlink());
+    assert_eq!(
+        std::fs::read_to_string(nvim.join("plugins.lua")).unwrap(),
+        "return {}\n"
+    );
+
+    let state = DeployState::load(state_dir.path()).unwrap();
+    let editor: Vec<_> = state.entries().iter().filter(|e| e.package == "editor").collect();
+    assert_eq!(editor.len(), 1, "folded directory should be a single state entry");
+    assert_eq!(editor[0].kind, EntryKind::Directory);
+    assert!(state.check_entry_status(editor[0]).is_ok());
+}
+
+#[test]
+fn fold_path_deploys_renamed_directory_at_its_new_name() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "[\".config/nvim\"]");
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    let config = config.replace(
+        "fold = [\".config/nvim\"]",
+        "fold = [\".config/nvim\"]\nrename = { \".config/nvim\" = \".config/neovim\" }",
+    );
+    std::fs::write(dotfiles.path().join("dotm.toml"), config).unwrap();
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    let neovim = target.path().join(".config/neovim");
+    assert!(neovim.is_symlink(), "folded directory should be deployed under its new name");
+    assert!(neovim.join("plugins.lua").exists());
+    assert!(!target.path().join(".config/nvim").exists());
+
+    let state = DeployState::load(state_dir.path()).unwrap();
+    let entry = state.entries().iter().find(|e| e.kind == EntryKind::Directory).unwrap();
+    assert_eq!(entry.target, neovim);
+}
+
+#[test]
+fn fold_all_keeps_directories_with_renamed_files_unfolded() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "true");
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    let config = config.replace(
+        "fold = true",
+        "fold = true\nrename = { \".config/nvim/plugins.lua\" = \".config/nvim/lua/plugins.lua\" }",
+    );
+    std::fs::write(dotfiles.path().join("dotm.toml"), config).unwrap();
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    assert!(!target.path().join(".config").is_symlink());
+    assert!(!target.path().join(".config/nvim").is_symlink());
+    assert!(target.path().join(".config/nvim/lua/plugins.lua").exists());
+    assert!(!target.path().join(".config/nvim/plugins.lua").exists());
+}
+
+#[test]
+fn fold_all_folds_highest_missing_directory() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "true");
+    std::fs::create_dir_all(target.path().join(".config")).unwrap();
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    assert!(!target.path().join(".config").is_symlink(), "existing directories are not folded");
+    assert!(target.path().join(".config/nvim").is_symlink());
+
+    // Redeploying keeps the directory folded
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert!(target.path().join(".config/nvim").is_symlink());
+}
+
+#[test]
+fn fold_onto_existing_directory_is_a_conflict_left_unread() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "[\".config/nvim\"]");
+    let nvim = target.path().join(".config/nvim");
+    std::fs::create_dir_all(&nvim).unwrap();
+    // A socket can't be read, so journaling the directory would fail the deploy
+    let _socket = std::os::unix::net::UnixListener::bind(nvim.join("server.sock")).unwrap();
+
+    let report = deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    assert_eq!(report.conflicts.len(), 1, "{:?}", report.conflicts);
+    assert!(report.conflicts[0].1.contains("directory already exists"));
+    assert!(!nvim.is_symlink());
+    assert!(nvim.join("server.sock").exists());
+}
+
+#[test]
+fn folded_directory_drift_is_detected() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "[\".config/nvim\"]");
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    std::fs::write(target.path().join(".config/nvim/new.lua"), "-- added\n").unwrap();
+
+    let state = DeployState::load(state_dir.path()).unwrap();
+    let entry = state.entries().iter().find(|e| e.kind == EntryKind::Directory).unwrap();
+    assert!(state.check_entry_status(entry).is_modified());
+
+    let report = deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert!(
+        report.conflicts.iter().any(|(_, msg)| msg.contains("modified since last deploy")),
+        "modified folded directory should not be overwritten without --force"
+    );
+}
+
+#[test]
+fn folding_replaces_previously_unfolded_files() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "[]");
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert!(target.path().join(".config/nvim/init.lua").is_symlink());
+
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        config.replace("fold = []", "fold = [\".config/nvim\"]"),
+    )
+    .unwrap();
+
+    let report = deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert!(report.conflicts.is_empty(), "conflicts: {:?}", report.conflicts);
+    assert!(report.orphaned.is_empty(), "folded files are not orphans");
+    assert!(target.path().join(".config/nvim").is_symlink());
+    assert!(target.path().join(".config/nvim/init.lua").exists());
+
+    // And back again
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        config.replace("fold = [\".config/nvim\"]", "fold = []"),
+    )
+    .unwrap();
+
+    let report = deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert!(report.conflicts.is_empty(), "conflicts: {:?}", report.conflicts);
+    assert!(report.orphaned.is_empty());
+    assert!(!target.path().join(".config/nvim").is_symlink());
+    assert!(target.path().join(".config/nvim/init.lua").is_symlink());
+}
+
+#[test]
+fn auto_prune_removes_orphaned_folded_directory() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "[\".config/nvim\"]");
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        config.replace("[dotm]\ntarget = \"~\"", "[dotm]\ntarget = \"~\"\nauto_prune = true"),
+    )
+    .unwrap();
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    std::fs::write(dotfiles.path().join("roles/dev.toml"), "packages = []\n").unwrap();
+
+    let report = deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert_eq!(report.pruned, vec![target.path().join(".config/nvim")]);
+    assert!(!target.path().join(".config/nvim").exists());
+    assert!(!dotfiles.path().join(".staged/.config/nvim").exists());
+}
+
+#[test]
+fn fold_rejects_directory_with_templates() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "[\".config/nvim\"]");
+    std::fs::write(
+        dotfiles.path().join("packages/editor/.config/nvim/local.lua.tera"),
+        "-- {{ x }}\n",
+    )
+    .unwrap();
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    let err = orch.deploy("testhost", false, false).unwrap_err();
+    assert!(format!("{err:#}").contains("cannot fold"));
+}
+
+#[test]
+fn undeploy_removes_staged_directory() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "[\".config/nvim\"]");
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    let state = DeployState::load(state_dir.path()).unwrap();
+    state.undeploy().unwrap();
+
+    assert!(!target.path().join(".config/nvim").exists());
+    assert!(!target.path().join(".config/nvim").is_symlink());
+    assert!(!dotfiles.path().join(".staged/.config/nvim").exists());
+}
+
+#[test]
+fn fold_with_link_strategy_links_source_directory() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_fold_fixture(dotfiles.path(), "[\".config/nvim\"]\nstrategy = \"link\"");
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    let source = std::fs::canonicalize(dotfiles.path().join("packages/editor/.config/nvim")).unwrap();
+    assert_eq!(std::fs::read_link(target.path().join(".config/nvim")).unwrap(), source);
+    assert!(!dotfiles.path().join(".staged/.config/nvim").exists());
+}
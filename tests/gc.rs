@@ -0,0 +1,120 @@
+mod common;
+
+use common::{copy_dir_recursive, deploy};
+use dotm::gc;
+use dotm::history;
+use dotm::orchestrator::Orchestrator;
+use dotm::state::DeployState;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn blob_count(dir: &Path) -> usize {
+    std::fs::read_dir(dir).map(|d| d.count()).unwrap_or(0)
+}
+
+/// Deploy three versions of .bashrc, leaving three generations.
+fn deploy_three_versions(dotfiles: &Path, target: &Path, state_dir: &Path) {
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles);
+    for version in 1..=3 {
+        std::fs::write(
+            dotfiles.join("packages/shell/.bashrc"),
+            format!("# version {version}\n"),
+        )
+        .unwrap();
+        deploy(dotfiles, target, state_dir);
+    }
+}
+
+#[test]
+fn gc_keeps_blobs_referenced_by_history() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    deploy_three_versions(dotfiles.path(), target.path(), state_dir.path());
+
+    // 3 versions of .bashrc + init.lua
+    let deployed = state_dir.path().join("deployed");
+    assert_eq!(blob_count(&deployed), 4);
+
+    let report = gc::collect(&DeployState::load_locked(state_dir.path()).unwrap(), None, false).unwrap();
+    assert!(report.removed.is_empty());
+    assert_eq!(blob_count(&deployed), 4);
+}
+
+#[test]
+fn gc_removes_unreferenced_blobs() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    deploy_three_versions(dotfiles.path(), target.path(), state_dir.path());
+    std::fs::write(state_dir.path().join("deployed/deadbeef"), "stray").unwrap();
+
+    let report = gc::collect(&DeployState::load_locked(state_dir.path()).unwrap(), Some(1), false).unwrap();
+    assert_eq!(report.expired_generations, vec![1, 2]);
+    assert_eq!(report.removed.len(), 3, "two old .bashrc versions and the stray blob");
+    assert_eq!(report.reclaimed_bytes, 2 * "# version 1\n".len() as u64 + 5);
+    assert_eq!(blob_count(&state_dir.path().join("deployed")), 2);
+    assert_eq!(history::list(state_dir.path()).unwrap().len(), 1);
+}
+
+#[test]
+fn gc_dry_run_reports_without_deleting() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    deploy_three_versions(dotfiles.path(), target.path(), state_dir.path());
+
+    let report = gc::collect(&DeployState::load_locked(state_dir.path()).unwrap(), Some(1), true).unwrap();
+    assert_eq!(report.removed.len(), 2);
+    assert!(report.reclaimed_bytes > 0);
+    assert_eq!(blob_count(&state_dir.path().join("deployed")), 4);
+    assert_eq!(history::list(state_dir.path()).unwrap().len(), 3);
+}
+
+#[test]
+fn gc_keeps_referenced_originals() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    std::fs::write(target.path().join(".bashrc"), "# pre-existing\n").unwrap();
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    orch.deploy("testhost", false, true).unwrap();
+    std::fs::write(state_dir.path().join("originals/deadbeef"), "stray").unwrap();
+
+    let report = gc::collect(&DeployState::load_locked(state_dir.path()).unwrap(), None, false).unwrap();
+    assert_eq!(report.removed, vec![state_dir.path().join("originals/deadbeef")]);
+    assert_eq!(blob_count(&state_dir.path().join("originals")), 1);
+}
+
+#[test]
+fn auto_gc_runs_after_deploy() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        config.replace(
+            "[dotm]\ntarget = \"~\"",
+            "[dotm]\ntarget = \"~\"\nauto_gc = true\nkeep_generations = 1",
+        ),
+    )
+    .unwrap();
+
+    for version in 1..=3 {
+        std::fs::write(
+            dotfiles.path().join("packages/shell/.bashrc"),
+            format!("# version {version}\n"),
+        )
+        .unwrap();
+        deploy(dotfiles.path(), target.path(), state_dir.path());
+    }
+
+    assert_eq!(blob_count(&state_dir.path().join("deployed")), 2);
+    assert_eq!(history::list(state_dir.path()).unwrap().len(), 1);
+}
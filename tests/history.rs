@@ -0,0 +1,154 @@
+mod common;
+
+use common::{copy_dir_recursive, deploy};
+use dotm::history;
+use dotm::orchestrator::Orchestrator;
+use dotm::state::DeployState;
+use std::path::Path;
+use tempfile::TempDir;
+
+#[test]
+fn deploy_records_generations() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    let generations = history::list(state_dir.path()).unwrap();
+    assert_eq!(generations.iter().map(|g| g.number).collect::<Vec<_>>(), vec![1, 2]);
+    assert_eq!(generations[0].host, "testhost");
+    assert_eq!(generations[0].entries.len(), 2);
+    assert!(generations[0].rollback_of.is_none());
+}
+
+#[test]
+fn dry_run_does_not_record_generation() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    orch.deploy("testhost", true, false).unwrap();
+
+    assert!(history::list(state_dir.path()).unwrap().is_empty());
+}
+
+#[test]
+fn rollback_restores_previous_content() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    let original = std::fs::read_to_string(target.path().join(".bashrc")).unwrap();
+
+    std::fs::write(dotfiles.path().join("packages/shell/.bashrc"), "# new version\n").unwrap();
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert_eq!(
+        std::fs::read_to_string(target.path().join(".bashrc")).unwrap(),
+        "# new version\n"
+    );
+
+    let report = history::rollback(state_dir.path(), 1, None).unwrap();
+    assert!(report.skipped.is_empty(), "skipped: {:?}", report.skipped);
+    assert_eq!(std::fs::read_to_string(target.path().join(".bashrc")).unwrap(), original);
+    assert!(target.path().join(".bashrc").is_symlink());
+
+    // The rollback becomes the current state and a new generation
+    let state = DeployState::load(state_dir.path()).unwrap();
+    for entry in state.entries() {
+        assert!(state.check_entry_status(entry).is_ok());
+    }
+    let latest = history::list(state_dir.path()).unwrap().pop().unwrap();
+    assert_eq!(latest.number, 3);
+    assert_eq!(latest.rollback_of, Some(1));
+}
+
+#[test]
+fn rollback_removes_files_added_later() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    std::fs::write(dotfiles.path().join("packages/shell/.inputrc"), "set bell-style none\n").unwrap();
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert!(target.path().join(".inputrc").exists());
+
+    let report = history::rollback(state_dir.path(), 1, None).unwrap();
+    assert_eq!(report.removed, vec![target.path().join(".inputrc")]);
+    assert!(!target.path().join(".inputrc").is_symlink());
+    assert!(!dotfiles.path().join(".staged/.inputrc").exists());
+    assert_eq!(DeployState::load(state_dir.path()).unwrap().entries().len(), 2);
+}
+
+#[test]
+fn rollback_leaves_modified_files_as_conflicts() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    std::fs::write(dotfiles.path().join("packages/shell/.bashrc"), "# new version\n").unwrap();
+    std::fs::write(dotfiles.path().join("packages/shell/.inputrc"), "set bell-style none\n").unwrap();
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    // Edit both files by hand (through the symlinks, into .staged/)
+    std::fs::write(target.path().join(".bashrc"), "# my edit\n").unwrap();
+    std::fs::write(target.path().join(".inputrc"), "set editing-mode vi\n").unwrap();
+
+    let report = history::rollback(state_dir.path(), 1, None).unwrap();
+    let mut conflicts: Vec<_> = report.conflicts.iter().map(|(p, _)| p.clone()).collect();
+    conflicts.sort();
+    assert_eq!(conflicts, vec![target.path().join(".bashrc"), target.path().join(".inputrc")]);
+    assert!(report.removed.is_empty());
+    assert_eq!(std::fs::read_to_string(target.path().join(".bashrc")).unwrap(), "# my edit\n");
+    assert_eq!(
+        std::fs::read_to_string(target.path().join(".inputrc")).unwrap(),
+        "set editing-mode vi\n"
+    );
+
+    // The modified files stay managed
+    let state = DeployState::load(state_dir.path()).unwrap();
+    assert_eq!(state.entries().len(), 3);
+}
+
+#[test]
+fn rollback_restores_original_of_removed_file() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    // A later deploy takes over a file the user already had
+    std::fs::write(target.path().join(".inputrc"), "set bell-style visible\n").unwrap();
+    std::fs::write(dotfiles.path().join("packages/shell/.inputrc"), "set bell-style none\n").unwrap();
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    orch.deploy("testhost", false, true).unwrap();
+    assert!(target.path().join(".inputrc").is_symlink());
+
+    let report = history::rollback(state_dir.path(), 1, None).unwrap();
+    assert_eq!(report.removed, vec![target.path().join(".inputrc")]);
+    let inputrc = target.path().join(".inputrc");
+    assert!(!inputrc.is_symlink());
+    assert_eq!(std::fs::read_to_string(&inputrc).unwrap(), "set bell-style visible\n");
+}
+
+#[test]
+fn rollback_to_unknown_generation_fails() {
+    let state_dir = TempDir::new().unwrap();
+    assert!(history::rollback(state_dir.path(), 7, None).is_err());
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::{copy_dir_recursive, deploy};
+use dotm::state::DeployState;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Copy the basic fixture with the shell package set to the given strategy.
+fn setup_link_fixture(dotfiles: &Path, strategy: &str) {
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles);
+    let config = std::fs::read_to_string(dotfiles.join("dotm.toml")).unwrap();
+    let config = config.replace(
+        "description = \"Shell configuration\"",
+        &format!("description = \"Shell configuration\"\nstrategy = \"{strategy}\""),
+    );
+    std::fs::write(dotfiles.join("dotm.toml"), config).unwrap();
+}
+
+#[test]
+fn link_strategy_symlinks_to_package_source() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_link_fixture(dotfiles.path(), "link");
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    let bashrc = target.path().join(".bashrc");
+    let source = std::fs::canonicalize(dotfiles.path().join("packages/shell/.bashrc")).unwrap();
+    assert_eq!(std::fs::read_link(&bashrc).unwrap(), source);
+    assert!(!dotfiles.path().join(".staged/.bashrc").exists(), "linked files are not staged");
+
+    // Editing the target edits the repo file
+    std::fs::write(&bashrc, "# edited\n").unwrap();
+    assert_eq!(std::fs::read_to_string(&source).unwrap(), "# edited\n");
+}
+
+#[test]
+fn linked_file_edits_are_not_drift() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_link_fixture(dotfiles.path(), "link");
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    std::fs::write(target.path().join(".bashrc"), "# edited\n").unwrap();
+
+    let state = DeployState::load(state_dir.path()).unwrap();
+    let entry = state.entries().iter().find(|e| e.package == "shell").unwrap();
+    assert!(entry.linked);
+    assert!(state.check_entry_status(entry).is_ok());
+
+    let report = deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert!(report.conflicts.is_empty(), "conflicts: {:?}", report.conflicts);
+}
+
+#[test]
+fn replaced_link_is_reported_as_modified() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_link_fixture(dotfiles.path(), "link");
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    let bashrc = target.path().join(".bashrc");
+    std::fs::remove_file(&bashrc).unwrap();
+    std::fs::write(&bashrc, "# replaced\n").unwrap();
+
+    let state = DeployState::load(state_dir.path()).unwrap();
+    let entry = state.entries().iter().find(|e| e.package == "shell").unwrap();
+    assert!(state.check_entry_status(entry).is_modified());
+}
+
+#[test]
+fn link_strategy_still_stages_templates() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_link_fixture(dotfiles.path(), "link");
+    std::fs::write(
+        dotfiles.path().join("packages/shell/.profile.tera"),
+        "export EDITOR=vim\n",
+    )
+    .unwrap();
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    let staged = std::fs::canonicalize(dotfiles.path().join(".staged/.profile")).unwrap();
+    assert_eq!(std::fs::read_link(target.path().join(".profile")).unwrap(), staged);
+}
+
+#[test]
+fn link_strategy_stages_files_with_permission_overrides() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_link_fixture(dotfiles.path(), "link");
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        config.replace(
+            "strategy = \"link\"",
+            "strategy = \"link\"\npermissions = { \".bashrc\" = \"600\" }",
+        ),
+    )
+    .unwrap();
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+
+    assert!(dotfiles.path().join(".staged/.bashrc").exists());
+}
+
+#[test]
+fn undeploy_linked_package_keeps_source() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_link_fixture(dotfiles.path(), "link");
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    let mut state = DeployState::load(state_dir.path()).unwrap();
+    state.undeploy_package("shell").unwrap();
+
+    assert!(!target.path().join(".bashrc").is_symlink());
+    assert!(dotfiles.path().join("packages/shell/.bashrc").exists());
+}
+
+#[test]
+fn switching_from_stage_to_link_removes_staged_copy() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_link_fixture(dotfiles.path(), "stage");
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert!(dotfiles.path().join(".staged/.bashrc").exists());
+
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        config.replace("strategy = \"stage\"", "strategy = \"link\""),
+    )
+    .unwrap();
+
+    deploy(dotfiles.path(), target.path(), state_dir.path());
+    assert!(!dotfiles.path().join(".staged/.bashrc").exists());
+    assert!(dotfiles.path().join("packages/shell/.bashrc").exists());
+}
@@ -1,5 +1,6 @@
-use dotm::loader::ConfigLoader;
+use dotm::loader::{ConfigLoader, HostMatch};
 use std::path::Path;
+use tempfile::TempDir;
 
 #[test]
 fn load_root_config() {
@@ -27,7 +28,9 @@ fn load_host_not_found() {
 fn load_role_config() {
     let loader = ConfigLoader::new(Path::new("tests/fixtures/basic")).unwrap();
     let role = loader.load_role("desktop").unwrap();
-    assert_eq!(role.packages, vec!["shell"]);
+    let names: Vec<&str> = role.packages.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, ["shell"]);
+    assert!(role.packages.iter().all(|p| p.when.is_none()));
 }
 
 #[test]
@@ -36,3 +39,183 @@ fn load_role_not_found() {
     let result = loader.load_role("nonexistent");
     assert!(result.is_err());
 }
+
+#[test]
+fn load_packages_from_includes_and_package_files() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(
+        dir.path().join("dotm.toml"),
+        "[dotm]\ntarget = \"~\"\n\n[packages.shell]\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(dir.path().join("dotm.d")).unwrap();
+    std::fs::write(
+        dir.path().join("dotm.d/dev.toml"),
+        "[packages.git]\ndescription = \"Git\"\ndepends = [\"shell\"]\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(dir.path().join("packages/editor")).unwrap();
+    std::fs::write(
+        dir.path().join("packages/editor/package.toml"),
+        "description = \"Editor\"\nstrategy = \"copy\"\n",
+    )
+    .unwrap();
+
+    let loader = ConfigLoader::new(dir.path()).unwrap();
+    let packages = &loader.root().packages;
+    assert_eq!(packages.len(), 3);
+    assert_eq!(packages["git"].depends, vec!["shell"]);
+    assert_eq!(packages["editor"].description.as_deref(), Some("Editor"));
+
+    let source = loader.package_source("git").unwrap();
+    assert_eq!(source.file, dir.path().join("dotm.d/dev.toml"));
+    assert_eq!(source.line, Some(1));
+    assert_eq!(loader.package_source("shell").unwrap().line, Some(4));
+    assert_eq!(
+        loader.package_source("editor").unwrap().to_string(),
+        dir.path().join("packages/editor/package.toml").display().to_string()
+    );
+}
+
+#[test]
+fn duplicate_packages_are_reported_and_the_first_is_used() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(
+        dir.path().join("dotm.toml"),
+        "[dotm]\ntarget = \"~\"\n\n[packages.shell]\ndescription = \"root\"\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(dir.path().join("packages/shell")).unwrap();
+    std::fs::write(
+        dir.path().join("packages/shell/package.toml"),
+        "description = \"package file\"\n",
+    )
+    .unwrap();
+
+    let loader = ConfigLoader::new(dir.path()).unwrap();
+    assert_eq!(loader.root().packages["shell"].description.as_deref(), Some("root"));
+    assert_eq!(
+        loader.duplicate_packages(),
+        vec![format!(
+            "package 'shell' is defined more than once: {}:4 and {}",
+            dir.path().join("dotm.toml").display(),
+            dir.path().join("packages/shell/package.toml").display()
+        )]
+    );
+}
+
+#[test]
+fn include_rejects_settings() {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("dotm.toml"), "[dotm]\ntarget = \"~\"\n").unwrap();
+    std::fs::create_dir_all(dir.path().join("dotm.d")).unwrap();
+    std::fs::write(dir.path().join("dotm.d/bad.toml"), "[dotm]\ntarget = \"/\"\n").unwrap();
+
+    let err = ConfigLoader::new(dir.path()).err().unwrap();
+    assert!(format!("{err:#}").contains("bad.toml"));
+}
+
+fn hosts_repo(hosts: &[(&str, &str)]) -> TempDir {
+    let dir = TempDir::new().unwrap();
+    std::fs::write(dir.path().join("dotm.toml"), "[dotm]\ntarget = \"~\"\n").unwrap();
+    std::fs::create_dir_all(dir.path().join("hosts")).unwrap();
+    for (name, content) in hosts {
+        std::fs::write(dir.path().join("hosts").join(format!("{name}.toml")), content).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn resolve_host_prefers_exact_then_pattern_then_default() {
+    let dir = hosts_repo(&[
+        ("ci-7", "roles = [\"exact\"]\n"),
+        ("ci-*", "roles = [\"glob\"]\n"),
+        ("numbered", "match = \"/^ci-[0-9]+$/\"\npriority = 10\nroles = [\"regex\"]\n"),
+        ("default", "roles = [\"fallback\"]\n"),
+    ]);
+    let loader = ConfigLoader::new(dir.path()).unwrap();
+
+    let resolved = loader.resolve_host("ci-7").unwrap();
+    assert_eq!((resolved.name.as_str(), resolved.matched_by), ("ci-7", HostMatch::Exact));
+
+    // Both patterns match; the higher priority wins
+    let resolved = loader.resolve_host("ci-42").unwrap();
+    assert_eq!(resolved.name, "numbered");
+    assert_eq!(resolved.matched_by, HostMatch::Pattern("/^ci-[0-9]+$/".into()));
+    assert_eq!(loader.load_host("ci-42").unwrap().roles, vec!["regex"]);
+
+    let resolved = loader.resolve_host("ci-x").unwrap();
+    assert_eq!(resolved.matched_by, HostMatch::Pattern("ci-*".into()));
+    assert_eq!(resolved.to_string(), "hosts/ci-*.toml (matches 'ci-*')");
+
+    let resolved = loader.resolve_host("laptop").unwrap();
+    assert_eq!((resolved.name.as_str(), resolved.matched_by), ("default", HostMatch::Default));
+}
+
+#[test]
+fn resolve_host_ties_go_to_first_file_by_name() {
+    let dir = hosts_repo(&[
+        ("b-workers", "match = \"worker-*\"\nroles = [\"b\"]\n"),
+        ("a-workers", "match = \"worker-?\"\nroles = [\"a\"]\n"),
+    ]);
+    let loader = ConfigLoader::new(dir.path()).unwrap();
+    assert_eq!(loader.resolve_host("worker-1").unwrap().name, "a-workers");
+    assert_eq!(loader.resolve_host("worker-12").unwrap().name, "b-workers");
+
+    let err = loader.resolve_host("laptop").unwrap_err();
+    assert!(err.to_string().contains("host config not found for 'laptop'"));
+}
+
+#[test]
+fn resolve_host_passes_over_broken_files_that_do_not_match() {
+    let dir = hosts_repo(&[
+        ("ci-*", "roles = [\"glob\"]\n"),
+        ("build-*", "roles = [\n"),
+        ("laptop-old", "roles = \"not a list\"\n"),
+        ("broken", "match = \n"),
+    ]);
+    let loader = ConfigLoader::new(dir.path()).unwrap();
+    assert_eq!(loader.load_host("ci-1").unwrap().roles, vec!["glob"]);
+
+    // The file that matches is still parsed, and its errors reported
+    assert_eq!(loader.resolve_host("build-1").unwrap().name, "build-*");
+    let err = loader.load_host("build-1").unwrap_err();
+    assert!(format!("{err:#}").contains("failed to parse"));
+}
+
+#[test]
+fn resolve_host_for_prefers_user_host_configs() {
+    let dir = hosts_repo(&[
+        ("box", "roles = [\"shared\"]\n"),
+        ("ci@box", "roles = [\"ci\"]\n"),
+        ("admin@*", "roles = [\"admin\"]\n"),
+        ("*", "roles = [\"any\"]\n"),
+    ]);
+    let loader = ConfigLoader::new(dir.path()).unwrap();
+
+    assert_eq!(loader.resolve_host_for(Some("ci"), "box").unwrap().name, "ci@box");
+    assert_eq!(loader.load_host_for(Some("admin"), "box").unwrap().roles, vec!["admin"]);
+    // No config for this user: fall back to the hostname's own
+    assert_eq!(loader.resolve_host_for(Some("alice"), "box").unwrap().name, "box");
+    assert_eq!(loader.resolve_host_for(None, "box").unwrap().name, "box");
+
+    // Patterns without an `@` never match a user@host key, and vice versa
+    let resolved = loader.resolve_host_for(Some("alice"), "other").unwrap();
+    assert_eq!((resolved.name.as_str(), resolved.hostname.as_str()), ("*", "other"));
+}
+
+#[test]
+fn concrete_hosts_leave_out_patterns() {
+    let dir = hosts_repo(&[
+        ("box", "roles = [\"shared\"]\n"),
+        ("ci@box", "roles = [\"ci\"]\n"),
+        ("admin@*", "roles = [\"admin\"]\n"),
+        ("ci-*", "roles = [\"glob\"]\n"),
+        ("numbered", "match = \"/^ci-[0-9]+$/\"\nroles = [\"regex\"]\n"),
+    ]);
+    let loader = ConfigLoader::new(dir.path()).unwrap();
+    assert_eq!(
+        loader.concrete_hosts().unwrap(),
+        vec![(None, "box".to_string()), (Some("ci".to_string()), "box".to_string())]
+    );
+}
@@ -41,3 +41,260 @@ fn full_deploy_dry_run_creates_nothing() {
     // Nothing should actually exist
     assert!(!target_dir.path().join(".bashrc").exists());
 }
+
+#[test]
+fn deploy_renders_dotm_facts() {
+    let dotfiles = TempDir::new().unwrap();
+    let root = dotfiles.path();
+    std::fs::write(root.join("dotm.toml"), "[dotm]\ntarget = \"~\"\n\n[packages.shell]\n").unwrap();
+    std::fs::create_dir_all(root.join("packages/shell")).unwrap();
+    std::fs::write(
+        root.join("packages/shell/.profile.tera"),
+        "{{ dotm.distro }} {{ dotm.host }} {{ dotm.roles | join(sep=\",\") }} {{ dotm.package }} {{ dotm.target }}\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("roles")).unwrap();
+    std::fs::write(root.join("roles/base.toml"), "packages = [\"shell\"]\n").unwrap();
+    std::fs::create_dir_all(root.join("hosts")).unwrap();
+    std::fs::write(root.join("hosts/box.toml"), "hostname = \"box\"\nroles = [\"base\"]\n").unwrap();
+
+    let target_dir = TempDir::new().unwrap();
+    let facts = dotm::facts::Facts {
+        distro: Some("debian".to_string()),
+        ..Default::default()
+    };
+    let mut orch = Orchestrator::new(root, target_dir.path()).unwrap().with_facts(facts);
+    orch.deploy("box", false, false).unwrap();
+
+    let target = target_dir.path().join(".profile");
+    assert_eq!(
+        std::fs::read_to_string(&target).unwrap(),
+        format!("debian box base shell {}\n", target.display())
+    );
+}
+
+#[test]
+fn deploy_renders_templates_with_shared_partials() {
+    let dotfiles = TempDir::new().unwrap();
+    let root = dotfiles.path();
+    std::fs::write(root.join("dotm.toml"), "[dotm]\ntarget = \"~\"\n\n[packages.shell]\n").unwrap();
+    std::fs::create_dir_all(root.join("templates")).unwrap();
+    std::fs::write(root.join("templates/aliases.tera"), "alias g=git\n").unwrap();
+    std::fs::create_dir_all(root.join("packages/shell")).unwrap();
+    std::fs::write(
+        root.join("packages/shell/.bashrc.tera"),
+        "# bashrc\n{% include \"aliases.tera\" %}",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("roles")).unwrap();
+    std::fs::write(root.join("roles/base.toml"), "packages = [\"shell\"]\n").unwrap();
+    std::fs::create_dir_all(root.join("hosts")).unwrap();
+    std::fs::write(root.join("hosts/box.toml"), "hostname = \"box\"\nroles = [\"base\"]\n").unwrap();
+
+    let target_dir = TempDir::new().unwrap();
+    let mut orch = Orchestrator::new(root, target_dir.path()).unwrap();
+    orch.deploy("box", false, false).unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(target_dir.path().join(".bashrc")).unwrap(),
+        "# bashrc\nalias g=git\n"
+    );
+}
+
+#[test]
+fn deploy_ignores_broken_templates_it_does_not_deploy() {
+    let dotfiles = TempDir::new().unwrap();
+    let root = dotfiles.path();
+    std::fs::write(
+        root.join("dotm.toml"),
+        "[dotm]\ntarget = \"~\"\n\n[packages.shell]\nignore = [\"drafts/\"]\n\n[packages.unused]\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("packages/shell/drafts")).unwrap();
+    std::fs::write(root.join("packages/shell/.bashrc.tera"), "# {{ 1 + 1 }}\n").unwrap();
+    std::fs::write(root.join("packages/shell/drafts/wip.tera"), "{% if %}").unwrap();
+    std::fs::create_dir_all(root.join("packages/unused")).unwrap();
+    std::fs::write(root.join("packages/unused/broken.tera"), "{{ unclosed").unwrap();
+    std::fs::create_dir_all(root.join("roles")).unwrap();
+    std::fs::write(root.join("roles/base.toml"), "packages = [\"shell\"]\n").unwrap();
+    std::fs::create_dir_all(root.join("hosts")).unwrap();
+    std::fs::write(root.join("hosts/box.toml"), "hostname = \"box\"\nroles = [\"base\"]\n").unwrap();
+
+    let target_dir = TempDir::new().unwrap();
+    let mut orch = Orchestrator::new(root, target_dir.path()).unwrap();
+    orch.deploy("box", false, false).unwrap();
+    assert_eq!(std::fs::read_to_string(target_dir.path().join(".bashrc")).unwrap(), "# 2\n");
+
+    // check still parses every declared package's templates
+    let err = orch.check_template_syntax().unwrap_err();
+    let errors = err.downcast::<dotm::template::TemplateErrors>().unwrap().0;
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].file, Path::new("packages/unused/broken.tera"));
+}
+
+#[test]
+fn deploy_layers_global_and_package_default_vars() {
+    let dotfiles = TempDir::new().unwrap();
+    let root = dotfiles.path();
+    std::fs::write(
+        root.join("dotm.toml"),
+        r#"[dotm]
+target = "~"
+
+[dotm.vars]
+font = "mono"
+theme = "light"
+
+[packages.shell]
+vars = { prompt = "$", theme = "solarized" }
+
+[packages.editor]
+"#,
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("packages/shell")).unwrap();
+    std::fs::write(
+        root.join("packages/shell/.bashrc.tera"),
+        "{{ prompt }} {{ font }} {{ theme }}\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("packages/editor")).unwrap();
+    std::fs::write(
+        root.join("packages/editor/.editorrc.tera"),
+        "{{ font }} {{ theme }} {{ prompt | default(value=\"none\") }}\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("roles")).unwrap();
+    std::fs::write(
+        root.join("roles/base.toml"),
+        "packages = [\"shell\", \"editor\"]\n\n[vars]\nprompt = \"%\"\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("hosts")).unwrap();
+    std::fs::write(
+        root.join("hosts/box.toml"),
+        "hostname = \"box\"\nroles = [\"base\"]\n\n[vars]\nfont = \"serif\"\n",
+    )
+    .unwrap();
+
+    let target_dir = TempDir::new().unwrap();
+    let mut orch = Orchestrator::new(root, target_dir.path()).unwrap();
+    orch.deploy("box", false, false).unwrap();
+
+    // Package defaults override global ones but not role or host vars
+    assert_eq!(
+        std::fs::read_to_string(target_dir.path().join(".bashrc")).unwrap(),
+        "% serif solarized\n"
+    );
+    // Another package's defaults aren't visible; the role's prompt is
+    assert_eq!(
+        std::fs::read_to_string(target_dir.path().join(".editorrc")).unwrap(),
+        "serif light %\n"
+    );
+
+    let host_vars = orch.host_vars("box").unwrap();
+    let shell_vars = orch.package_vars(&host_vars, "shell");
+    let sources: Vec<&str> = shell_vars.provenance["theme"].iter().map(|s| s.layer.as_str()).collect();
+    assert_eq!(sources, ["[dotm.vars]", "package 'shell'"]);
+    assert_eq!(host_vars.provenance["theme"].last().unwrap().layer, "[dotm.vars]");
+}
+
+#[test]
+fn deploy_applies_var_overrides_on_top_of_host_vars() {
+    let dotfiles = TempDir::new().unwrap();
+    let root = dotfiles.path();
+    std::fs::write(root.join("dotm.toml"), "[dotm]\ntarget = \"~\"\n\n[packages.shell]\n").unwrap();
+    std::fs::create_dir_all(root.join("packages/shell")).unwrap();
+    std::fs::write(root.join("packages/shell/.bashrc.tera"), "{{ prompt }} {{ color }}\n").unwrap();
+    std::fs::create_dir_all(root.join("roles")).unwrap();
+    std::fs::write(root.join("roles/base.toml"), "packages = [\"shell\"]\n\n[vars]\ncolor = \"red\"\n").unwrap();
+    std::fs::create_dir_all(root.join("hosts")).unwrap();
+    std::fs::write(
+        root.join("hosts/box.toml"),
+        "hostname = \"box\"\nroles = [\"base\"]\n\n[vars]\nprompt = \"$\"\n",
+    )
+    .unwrap();
+
+    let target_dir = TempDir::new().unwrap();
+    let mut orch = Orchestrator::new(root, target_dir.path())
+        .unwrap()
+        .with_var_overrides(vec![
+            ("--var-file ci.toml".to_string(), toml::from_str("prompt = \"ci>\"\ncolor = \"blue\"").unwrap()),
+            ("--var".to_string(), toml::from_str("prompt = \"#\"").unwrap()),
+        ]);
+    let report = orch.deploy("box", false, false).unwrap();
+
+    assert_eq!(
+        std::fs::read_to_string(target_dir.path().join(".bashrc")).unwrap(),
+        "# blue\n"
+    );
+    let overrides: Vec<(&str, &str)> = report
+        .var_overrides
+        .iter()
+        .map(|e| (e.path.as_str(), e.source.as_str()))
+        .collect();
+    assert_eq!(overrides, [("color", "--var-file ci.toml"), ("prompt", "--var")]);
+}
+
+#[test]
+fn deploy_renders_template_overrides() {
+    let dotfiles = TempDir::new().unwrap();
+    let root = dotfiles.path();
+    std::fs::write(root.join("dotm.toml"), "[dotm]\ntarget = \"~\"\n\n[packages.shell]\n").unwrap();
+    std::fs::create_dir_all(root.join("packages/shell")).unwrap();
+    std::fs::write(root.join("packages/shell/.bashrc.tera"), "PS1='{{ prompt }}'\n").unwrap();
+    std::fs::write(
+        root.join("packages/shell/.bashrc.tera##host.laptop"),
+        "PS1='{{ prompt }} on {{ dotm.hostname }}'\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("roles")).unwrap();
+    std::fs::write(root.join("roles/base.toml"), "packages = [\"shell\"]\n").unwrap();
+    std::fs::create_dir_all(root.join("hosts")).unwrap();
+    std::fs::write(root.join("hosts/default.toml"), "roles = [\"base\"]\n\n[vars]\nprompt = \"$\"\n").unwrap();
+
+    let facts = dotm::facts::Facts { hostname: "laptop".into(), ..Default::default() };
+    for (host, expected) in [("laptop", "PS1='$ on laptop'\n"), ("desktop", "PS1='$'\n")] {
+        let target_dir = TempDir::new().unwrap();
+        let mut orch = Orchestrator::new(root, target_dir.path())
+            .unwrap()
+            .with_facts(facts.clone());
+        let report = orch.deploy(host, false, false).unwrap();
+        assert!(report.conflicts.is_empty(), "{:?}", report.conflicts);
+        assert_eq!(std::fs::read_to_string(target_dir.path().join(".bashrc")).unwrap(), expected);
+    }
+}
+
+#[test]
+fn deploy_translates_dot_prefix_and_renames_files() {
+    let dotfiles = TempDir::new().unwrap();
+    let root = dotfiles.path();
+    std::fs::write(
+        root.join("dotm.toml"),
+        "[dotm]\ntarget = \"~\"\ndot_prefix = true\n\n[packages.code]\n\n[packages.code.rename]\n\"settings.json\" = \".config/Code/User/settings.json\"\n\n[packages.raw]\ndot_prefix = false\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("packages/code/dot-config/Code")).unwrap();
+    std::fs::write(root.join("packages/code/dot-config/Code/keybindings.json"), "[]\n").unwrap();
+    std::fs::write(root.join("packages/code/settings.json.tera"), "{\"font\": \"{{ font }}\"}\n").unwrap();
+    std::fs::create_dir_all(root.join("packages/raw")).unwrap();
+    std::fs::write(root.join("packages/raw/dot-emacs"), ";;\n").unwrap();
+    std::fs::create_dir_all(root.join("roles")).unwrap();
+    std::fs::write(root.join("roles/base.toml"), "packages = [\"code\", \"raw\"]\n").unwrap();
+    std::fs::create_dir_all(root.join("hosts")).unwrap();
+    std::fs::write(root.join("hosts/box.toml"), "roles = [\"base\"]\n\n[vars]\nfont = \"mono\"\n").unwrap();
+
+    let target_dir = TempDir::new().unwrap();
+    let mut orch = Orchestrator::new(root, target_dir.path()).unwrap();
+    orch.deploy("box", false, false).unwrap();
+
+    let target = target_dir.path();
+    assert!(target.join(".config/Code/keybindings.json").is_symlink());
+    assert_eq!(
+        std::fs::read_to_string(target.join(".config/Code/User/settings.json")).unwrap(),
+        "{\"font\": \"mono\"}\n"
+    );
+    // dot_prefix is opt-out per package
+    assert!(target.join("dot-emacs").exists());
+    assert!(!target.join(".emacs").exists());
+}
@@ -93,3 +93,77 @@ fn auto_prune_removes_orphaned_files() {
     // The orphaned symlink should be gone
     assert!(!target.path().join(".config/nvim/init.lua").exists());
 }
+
+#[test]
+fn profiles_keep_separate_state_and_do_not_orphan_each_other() {
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+    std::fs::write(dotfiles.path().join("hosts/alice@testhost.toml"), "roles = [\"desktop\"]\n").unwrap();
+    std::fs::write(dotfiles.path().join("hosts/bob@testhost.toml"), "roles = [\"dev\"]\n").unwrap();
+
+    let deploy = |profile: &str, target: &Path| {
+        let profile_dir = dotm::state::profile_state_dir(state_dir.path(), profile).unwrap();
+        Orchestrator::new(dotfiles.path(), target)
+            .unwrap()
+            .with_profile(Some(profile.to_string()))
+            .unwrap()
+            .with_state_dir(&profile_dir)
+            .deploy("testhost", false, false)
+            .unwrap()
+    };
+
+    let alice = TempDir::new().unwrap();
+    let bob = TempDir::new().unwrap();
+    deploy("alice", alice.path());
+    deploy("bob", bob.path());
+
+    // alice only has the desktop role; bob's editor config is his alone
+    assert!(alice.path().join(".bashrc").exists());
+    assert!(!alice.path().join(".config/nvim/init.lua").exists());
+    assert!(bob.path().join(".config/nvim/init.lua").exists());
+    let link = std::fs::read_link(alice.path().join(".bashrc")).unwrap();
+    assert!(link.starts_with(dotfiles.path().join(".staged-alice")), "got: {}", link.display());
+    assert!(!dotfiles.path().join(".staged").exists(), "profiles stage beside .staged/, not in it");
+
+    let report = deploy("alice", alice.path());
+    assert!(report.orphaned.is_empty(), "unexpected orphans: {:?}", report.orphaned);
+    assert!(bob.path().join(".config/nvim/init.lua").exists());
+}
+
+#[test]
+fn newly_ignored_files_become_orphans() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    let deploy = || {
+        Orchestrator::new(dotfiles.path(), target.path())
+            .unwrap()
+            .with_state_dir(state_dir.path())
+            .deploy("testhost", false, false)
+            .unwrap()
+    };
+    deploy();
+    assert!(target.path().join(".config/nvim/init.lua").exists());
+
+    std::fs::write(dotfiles.path().join(".dotmignore"), "init.lua\n").unwrap();
+    let report = deploy();
+    assert_eq!(report.orphaned, [target.path().join(".config/nvim/init.lua")]);
+}
+
+#[test]
+fn with_profile_rejects_names_that_are_not_plain() {
+    let dotfiles = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    for profile in ["../alice", "a/b", ".hidden", ""] {
+        let err = Orchestrator::new(dotfiles.path(), dotfiles.path())
+            .unwrap()
+            .with_profile(Some(profile.to_string()))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("invalid profile name"), "{profile}: {err}");
+    }
+}
@@ -19,6 +19,12 @@ fn make_root(packages: Vec<(&str, Vec<&str>, Vec<&str>)>) -> RootConfig {
             target: "~".to_string(),
             packages_dir: "packages".to_string(),
             auto_prune: false,
+            auto_gc: false,
+            keep_generations: None,
+            secrets: None,
+            encryption: Default::default(),
+            vars: Default::default(),
+            dot_prefix: false,
         },
         packages: pkg_map,
     }
@@ -0,0 +1,109 @@
+use dotm::loader::ConfigLoader;
+use dotm::orchestrator::Orchestrator;
+use dotm::resolver::resolve_roles;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn write(path: &Path, content: &str) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+}
+
+/// Roles: `laptop` and `workstation` both include `base`; `laptop` also includes `dev`.
+fn setup_dotfiles() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    write(
+        &root.join("dotm.toml"),
+        "[dotm]\ntarget = \"~\"\n\n[packages.shell]\n\n[packages.editor]\n\n[packages.laptop]\n",
+    );
+    write(&root.join("packages/shell/.bashrc"), "# bashrc\n");
+    write(&root.join("packages/shell/.profile##role.base"), "# base profile\n");
+    write(
+        &root.join("packages/editor/.config/app.conf.tera"),
+        "theme={{ theme }} font={{ font }} power={{ power }}\n",
+    );
+    write(&root.join("packages/laptop/.xinitrc"), "# xinitrc\n");
+    write(
+        &root.join("roles/base.toml"),
+        "packages = [\"shell\"]\n\n[vars]\ntheme = \"base\"\nfont = \"base\"\npower = \"base\"\n",
+    );
+    write(
+        &root.join("roles/dev.toml"),
+        "includes = [\"base\"]\npackages = [\"editor\"]\n\n[vars]\nfont = \"dev\"\n",
+    );
+    write(
+        &root.join("roles/laptop.toml"),
+        "includes = [\"base\", \"dev\"]\npackages = [\"laptop\"]\n\n[vars]\npower = \"battery\"\n",
+    );
+    write(
+        &root.join("hosts/lap.toml"),
+        "hostname = \"lap\"\nroles = [\"laptop\"]\n\n[vars]\ntheme = \"host\"\n",
+    );
+    dir
+}
+
+#[test]
+fn resolve_roles_expands_includes_first_and_once() {
+    let dotfiles = setup_dotfiles();
+    let loader = ConfigLoader::new(dotfiles.path()).unwrap();
+
+    let roles = resolve_roles(&loader, &["laptop".to_string()]).unwrap();
+    let names: Vec<&str> = roles.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["base", "dev", "laptop"]);
+}
+
+#[test]
+fn resolve_roles_circular_include_errors() {
+    let dotfiles = setup_dotfiles();
+    write(&dotfiles.path().join("roles/base.toml"), "includes = [\"laptop\"]\n");
+    let loader = ConfigLoader::new(dotfiles.path()).unwrap();
+
+    let err = resolve_roles(&loader, &["laptop".to_string()]).unwrap_err();
+    assert!(
+        err.to_string().contains("laptop -> base -> laptop"),
+        "unexpected error: {err}"
+    );
+}
+
+#[test]
+fn resolve_roles_unknown_include_errors() {
+    let dotfiles = setup_dotfiles();
+    write(&dotfiles.path().join("roles/dev.toml"), "includes = [\"missing\"]\n");
+    let loader = ConfigLoader::new(dotfiles.path()).unwrap();
+
+    assert!(resolve_roles(&loader, &["dev".to_string()]).is_err());
+}
+
+#[test]
+fn deploy_uses_packages_vars_and_overrides_of_included_roles() {
+    let dotfiles = setup_dotfiles();
+    let target = TempDir::new().unwrap();
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path()).unwrap();
+    orch.deploy("lap", false, false).unwrap();
+
+    assert!(target.path().join(".bashrc").exists());
+    assert!(target.path().join(".xinitrc").exists());
+    assert_eq!(
+        std::fs::read_to_string(target.path().join(".profile")).unwrap(),
+        "# base profile\n"
+    );
+    // base < dev < laptop < host
+    assert_eq!(
+        std::fs::read_to_string(target.path().join(".config/app.conf")).unwrap(),
+        "theme=host font=dev power=battery\n"
+    );
+}
+
+#[test]
+fn tree_shows_included_roles() {
+    let dotfiles = setup_dotfiles();
+    let loader = ConfigLoader::new(dotfiles.path()).unwrap();
+
+    let output = dotm::list::render_tree(&loader).unwrap();
+    assert_eq!(
+        output,
+        "└── lap\n    └── laptop\n        ├── [base]\n        │   └── shell\n        ├── [dev]\n        │   ├── [base]\n        │   │   └── shell\n        │   └── editor\n        └── laptop\n"
+    );
+}
@@ -1,4 +1,4 @@
-use dotm::scanner::scan_package;
+use dotm::scanner::{IgnoreRules, Machine, ScanOptions, Selector, override_ties, parse_selectors, scan_package, scan_package_checked, scan_package_with};
 use std::path::Path;
 
 #[test]
@@ -100,3 +100,242 @@ fn scan_theme_conf_has_no_override() {
         .unwrap();
     assert_eq!(theme.kind, dotm::scanner::EntryKind::Base);
 }
+
+#[test]
+fn scan_age_file_is_encrypted() {
+    let dir = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(dir.path().join(".ssh")).unwrap();
+    std::fs::write(dir.path().join(".ssh/id_ed25519.age"), "ciphertext").unwrap();
+    std::fs::write(dir.path().join(".ssh/config.age##host.laptop"), "ciphertext").unwrap();
+    std::fs::write(dir.path().join(".ssh/config"), "plain").unwrap();
+
+    let actions = scan_package(dir.path(), "laptop", &[]).unwrap();
+
+    let key = actions
+        .iter()
+        .find(|a| a.target_rel_path.to_str() == Some(".ssh/id_ed25519"))
+        .unwrap();
+    assert_eq!(key.kind, dotm::scanner::EntryKind::Encrypted);
+
+    let config = actions
+        .iter()
+        .find(|a| a.target_rel_path.to_str() == Some(".ssh/config"))
+        .unwrap();
+    assert_eq!(config.kind, dotm::scanner::EntryKind::Encrypted, "encrypted host override stays encrypted");
+}
+
+#[test]
+fn scan_folds_listed_directory() {
+    let pkg_dir = Path::new("tests/fixtures/basic/packages/editor");
+    let options = dotm::scanner::ScanOptions {
+        fold: vec![".config/nvim".into()],
+        ..Default::default()
+    };
+    let actions = dotm::scanner::scan_package_with(pkg_dir, "testhost", &[], &options).unwrap();
+
+    assert_eq!(actions.len(), 1);
+    assert_eq!(actions[0].target_rel_path.to_str(), Some(".config/nvim"));
+    assert_eq!(actions[0].kind, dotm::scanner::EntryKind::Directory);
+}
+
+#[test]
+fn scan_skips_package_config_file() {
+    let pkg_dir = tempfile::TempDir::new().unwrap();
+    std::fs::write(pkg_dir.path().join("package.toml"), "description = \"x\"\n").unwrap();
+    std::fs::write(pkg_dir.path().join(".bashrc"), "# bashrc\n").unwrap();
+    std::fs::create_dir_all(pkg_dir.path().join(".config/app")).unwrap();
+    std::fs::write(pkg_dir.path().join(".config/app/package.toml"), "").unwrap();
+
+    let actions = scan_package(pkg_dir.path(), "testhost", &[]).unwrap();
+    let targets: Vec<_> = actions.iter().map(|a| a.target_rel_path.to_str().unwrap()).collect();
+    assert_eq!(targets, vec![".bashrc", ".config/app/package.toml"]);
+}
+
+fn variants_dir(names: &[&str]) -> tempfile::TempDir {
+    let dir = tempfile::TempDir::new().unwrap();
+    for name in names {
+        std::fs::write(dir.path().join(name), name).unwrap();
+    }
+    dir
+}
+
+fn picked(dir: &Path, hostname: &str, roles: &[&str], machine: &Machine) -> String {
+    let options = ScanOptions { machine: machine.clone(), ..Default::default() };
+    let actions = scan_package_with(dir, hostname, roles, &options).unwrap();
+    assert_eq!(actions.len(), 1);
+    actions[0].source.file_name().unwrap().to_str().unwrap().to_string()
+}
+
+#[test]
+fn scan_ranks_override_selectors_by_specificity() {
+    let dir = variants_dir(&[
+        "app.conf",
+        "app.conf##arch.aarch64",
+        "app.conf##os.linux",
+        "app.conf##distro.arch",
+        "app.conf##role.laptop,distro.arch",
+        "app.conf##user.alice",
+        "app.conf##host.box",
+    ]);
+    let arch_linux = Machine {
+        os: Some("linux".into()),
+        distro: Some("arch".into()),
+        arch: Some("aarch64".into()),
+        user: Some("bob".into()),
+    };
+    let fedora = Machine { distro: Some("fedora".into()), ..arch_linux.clone() };
+
+    assert_eq!(picked(dir.path(), "box", &[], &arch_linux), "app.conf##host.box");
+    assert_eq!(
+        picked(dir.path(), "other", &[], &Machine { user: Some("alice".into()), ..arch_linux.clone() }),
+        "app.conf##user.alice"
+    );
+    assert_eq!(picked(dir.path(), "other", &["laptop"], &arch_linux), "app.conf##role.laptop,distro.arch");
+    // Every selector of a combined override has to match
+    assert_eq!(picked(dir.path(), "other", &["laptop"], &fedora), "app.conf##os.linux");
+    assert_eq!(picked(dir.path(), "other", &[], &arch_linux), "app.conf##distro.arch");
+    assert_eq!(picked(dir.path(), "other", &[], &Machine { arch: Some("aarch64".into()), ..Default::default() }), "app.conf##arch.aarch64");
+    assert_eq!(picked(dir.path(), "other", &[], &Machine::default()), "app.conf");
+}
+
+#[test]
+fn scan_reports_equally_specific_overrides() {
+    let dir = variants_dir(&[
+        "app.conf##os.linux,arch.x86_64",
+        "app.conf##arch.x86_64,os.linux",
+        "theme.conf##role.desktop",
+        "theme.conf##role.laptop",
+    ]);
+    let machine = Machine { os: Some("linux".into()), arch: Some("x86_64".into()), ..Default::default() };
+    let options = ScanOptions { machine, ..Default::default() };
+
+    // Roles that both match are ranked by role order, so they never tie
+    let actions = scan_package_with(dir.path(), "box", &["laptop", "desktop"], &options).unwrap();
+    assert!(actions[1].source.ends_with("theme.conf##role.desktop"));
+
+    let ties = override_ties(dir.path(), "box", &["laptop", "desktop"], &options).unwrap();
+    assert_eq!(ties.len(), 1);
+    assert_eq!(ties[0].target_rel_path, Path::new("app.conf"));
+    assert!(ties[0].variants[0].ends_with("app.conf##arch.x86_64,os.linux"));
+    assert!(actions[0].source.ends_with("app.conf##arch.x86_64,os.linux"));
+}
+
+#[test]
+fn parse_selectors_rejects_unknown_kinds() {
+    assert_eq!(parse_selectors("app.conf").unwrap(), None);
+    assert_eq!(
+        parse_selectors("app.conf##role.laptop,distro.arch").unwrap(),
+        Some(vec![Selector::Role("laptop".into()), Selector::Distro("arch".into())])
+    );
+    let err = parse_selectors("app.conf##hots.laptop").unwrap_err();
+    assert!(err.to_string().contains("unknown override selector 'hots'"), "{err}");
+    assert!(parse_selectors("app.conf##host").is_err());
+
+}
+
+#[test]
+fn scan_skips_overrides_with_unknown_selectors() {
+    let dir = variants_dir(&["app.conf", "app.conf##hots.laptop", "other.conf##hots.laptop"]);
+    let actions = scan_package(dir.path(), "laptop", &[]).unwrap();
+    assert_eq!(actions.len(), 1);
+    assert!(actions[0].source.ends_with("app.conf"));
+    assert_eq!(actions[0].kind, dotm::scanner::EntryKind::Base);
+
+    let (actions, skipped) =
+        scan_package_checked(dir.path(), "laptop", &[], &ScanOptions::default()).unwrap();
+    assert_eq!(actions.len(), 1);
+    let skipped: Vec<String> = skipped.iter().map(|e| e.to_string()).collect();
+    assert_eq!(skipped, ["invalid override app.conf##hots.laptop", "invalid override other.conf##hots.laptop"]);
+
+    let invalid = dotm::scanner::invalid_overrides(dir.path()).unwrap();
+    assert_eq!(invalid.len(), 2);
+}
+
+#[test]
+fn scan_template_override_is_a_template() {
+    let dir = variants_dir(&["config.tera", "config.tera##host.laptop", "plain.conf##host.laptop"]);
+
+    let actions = scan_package(dir.path(), "laptop", &[]).unwrap();
+    let config = actions.iter().find(|a| a.target_rel_path == Path::new("config")).unwrap();
+    assert!(config.source.ends_with("config.tera##host.laptop"));
+    assert_eq!(config.kind, dotm::scanner::EntryKind::Template);
+    let plain = actions.iter().find(|a| a.target_rel_path == Path::new("plain.conf")).unwrap();
+    assert_eq!(plain.kind, dotm::scanner::EntryKind::Override);
+
+    let actions = scan_package(dir.path(), "desktop", &[]).unwrap();
+    let config = actions.iter().find(|a| a.target_rel_path == Path::new("config")).unwrap();
+    assert!(config.source.ends_with("config.tera"));
+}
+
+#[test]
+fn scan_skips_ignored_files() {
+    let repo = tempfile::TempDir::new().unwrap();
+    let pkg_dir = repo.path().join("packages/nvim");
+    std::fs::create_dir_all(pkg_dir.join(".config/nvim/pack/plugin/.git")).unwrap();
+    std::fs::write(repo.path().join(".dotmignore"), "*.swp\n*.md\n").unwrap();
+    std::fs::write(pkg_dir.join(".dotmignore"), ".git/\n!CHANGES.md\n").unwrap();
+    for file in [
+        "README.md",
+        "CHANGES.md",
+        "notes.txt",
+        ".config/nvim/init.lua",
+        ".config/nvim/.init.lua.swp",
+        ".config/nvim/pack/plugin/plugin.lua",
+        ".config/nvim/pack/plugin/.git/HEAD",
+    ] {
+        std::fs::write(pkg_dir.join(file), "x").unwrap();
+    }
+
+    let ignore = IgnoreRules::load(repo.path(), &pkg_dir, &["notes.*".to_string()]).unwrap();
+    let options = ScanOptions { ignore, ..Default::default() };
+    let actions = scan_package_with(&pkg_dir, "box", &[], &options).unwrap();
+    let targets: Vec<&str> = actions.iter().map(|a| a.target_rel_path.to_str().unwrap()).collect();
+    assert_eq!(targets, [".config/nvim/init.lua", ".config/nvim/pack/plugin/plugin.lua", "CHANGES.md"]);
+
+    // A directory with ignored files inside can't be folded as a whole
+    let options = ScanOptions { fold: vec![".config/nvim/pack".into()], ..options };
+    let err = scan_package_with(&pkg_dir, "box", &[], &options).unwrap_err();
+    assert!(format!("{err:#}").contains("cannot fold .config/nvim/pack"), "{err:#}");
+}
+
+#[test]
+fn scan_translates_dot_prefix_and_renames() {
+    let dir = tempfile::TempDir::new().unwrap();
+    std::fs::create_dir_all(dir.path().join("dot-config/nvim")).unwrap();
+    for file in [
+        "dot-bashrc",
+        "dot-config/nvim/init.lua",
+        "dot-config/app.conf##host.box",
+        "notdot-file",
+        "vscode-settings.json.tera",
+    ] {
+        std::fs::write(dir.path().join(file), "x").unwrap();
+    }
+    let targets = |options: &ScanOptions| -> Vec<String> {
+        scan_package_with(dir.path(), "box", &[], options)
+            .unwrap()
+            .iter()
+            .map(|a| a.target_rel_path.to_str().unwrap().to_string())
+            .collect()
+    };
+
+    assert_eq!(
+        targets(&ScanOptions::default()),
+        ["dot-bashrc", "dot-config/app.conf", "dot-config/nvim/init.lua", "notdot-file", "vscode-settings.json"]
+    );
+
+    let options = ScanOptions {
+        dot_prefix: true,
+        rename: [("vscode-settings.json".into(), ".config/Code/User/settings.json".into())].into(),
+        ..Default::default()
+    };
+    assert_eq!(
+        targets(&options),
+        [".bashrc", ".config/Code/User/settings.json", ".config/app.conf", ".config/nvim/init.lua", "notdot-file"]
+    );
+
+    // Two package files can't end up at the same target
+    std::fs::write(dir.path().join(".bashrc"), "x").unwrap();
+    let err = scan_package_with(dir.path(), "box", &[], &options).unwrap_err();
+    assert!(err.to_string().contains("both deploy to .bashrc"), "{err}");
+}
@@ -0,0 +1,272 @@
+use dotm::orchestrator::Orchestrator;
+use dotm::secrets::{FileProvider, SecretProvider};
+use dotm::state::DeployState;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tempfile::TempDir;
+
+fn setup_secret_fixture(dir: &Path, secrets_table: &str) {
+    std::fs::write(
+        dir.join("dotm.toml"),
+        format!(
+            r#"
+[dotm]
+target = "~"
+
+{secrets_table}
+
+[packages.net]
+description = "Network credentials"
+"#
+        ),
+    )
+    .unwrap();
+
+    let pkg_dir = dir.join("packages/net");
+    std::fs::create_dir_all(&pkg_dir).unwrap();
+    std::fs::write(
+        pkg_dir.join(".netrc.tera"),
+        "machine example.com password {{ secret(path=\"example.password\") }}\n",
+    )
+    .unwrap();
+    std::fs::write(pkg_dir.join(".plainrc"), "not secret\n").unwrap();
+
+    std::fs::create_dir_all(dir.join("hosts")).unwrap();
+    std::fs::write(
+        dir.join("hosts/testhost.toml"),
+        "hostname = \"testhost\"\nroles = [\"all\"]\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(dir.join("roles")).unwrap();
+    std::fs::write(dir.join("roles/all.toml"), "packages = [\"net\"]\n").unwrap();
+}
+
+/// Every file under `dir`, following no symlinks.
+fn walk(dir: &Path) -> Vec<std::path::PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() && !path.is_symlink() {
+            files.extend(walk(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
+
+#[test]
+fn file_provider_reads_dotted_paths() {
+    let dir = TempDir::new().unwrap();
+    let file = dir.path().join("secrets.toml.enc");
+    std::fs::write(&file, "[example]\npassword = \"hunter2\"\nport = 22\n").unwrap();
+
+    let provider = FileProvider::new(&file, None, Some("cat")).unwrap();
+    assert_eq!(provider.get("example.password").unwrap(), "hunter2");
+    assert_eq!(provider.get("example.port").unwrap(), "22");
+    assert!(provider.get("example.missing").is_err());
+    assert!(provider.get("example").is_err());
+}
+
+#[test]
+fn deploy_renders_secrets_without_storing_plaintext() {
+    let dotfiles = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+
+    setup_secret_fixture(
+        dotfiles.path(),
+        "[dotm.secrets]\nbackend = \"file\"\nfile = \"secrets.toml.enc\"\ndecrypt_command = \"cat\"",
+    );
+    std::fs::write(
+        dotfiles.path().join("secrets.toml.enc"),
+        "[example]\npassword = \"hunter2\"\n",
+    )
+    .unwrap();
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    let report = orch.deploy("testhost", false, false).unwrap();
+    assert!(report.conflicts.is_empty());
+
+    let netrc = target.path().join(".netrc");
+    assert!(std::fs::read_to_string(&netrc).unwrap().contains("hunter2"));
+    assert!(!netrc.is_symlink(), "templates using secrets are copied, not staged");
+    assert_eq!(netrc.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+    assert!(!dotfiles.path().join(".staged/.netrc").exists());
+    assert!(dotfiles.path().join(".staged/.plainrc").exists());
+    for entry in walk(&dotfiles.path().join(".staged")) {
+        let content = std::fs::read(&entry).unwrap();
+        assert!(!String::from_utf8_lossy(&content).contains("hunter2"), "{} leaks the secret", entry.display());
+    }
+
+    let state = DeployState::load(state_dir.path()).unwrap();
+    let entry = state
+        .entries()
+        .iter()
+        .find(|e| e.target.ends_with(".netrc"))
+        .unwrap();
+    assert!(entry.sensitive);
+    assert!(state.load_deployed(&entry.content_hash).is_err());
+
+    let plain = state
+        .entries()
+        .iter()
+        .find(|e| e.target.ends_with(".plainrc"))
+        .unwrap();
+    assert!(!plain.sensitive);
+    assert!(state.load_deployed(&plain.content_hash).is_ok());
+
+    let raw_state = std::fs::read_to_string(state_dir.path().join("dotm-state.json")).unwrap();
+    assert!(!raw_state.contains("hunter2"));
+}
+
+#[test]
+fn dry_run_does_not_call_the_secrets_backend() {
+    let dotfiles = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    let marker = dotfiles.path().join("backend-called");
+
+    setup_secret_fixture(
+        dotfiles.path(),
+        &format!(
+            "[dotm.secrets]\nbackend = \"command\"\ncommand = \"touch {} && printf pw\"",
+            marker.display()
+        ),
+    );
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path()).unwrap();
+    let report = orch.deploy("testhost", true, false).unwrap();
+    assert!(report.dry_run_actions.iter().any(|p| p.ends_with(".netrc")));
+    assert!(!marker.exists(), "dry run ran the secrets command");
+    assert!(!target.path().join(".netrc").exists());
+}
+
+#[test]
+fn deploy_with_command_backend() {
+    let dotfiles = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    setup_secret_fixture(
+        dotfiles.path(),
+        "[dotm.secrets]\nbackend = \"command\"\ncommand = \"printf 'pw-%s' {path}\"",
+    );
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path()).unwrap();
+    orch.deploy("testhost", false, false).unwrap();
+
+    let content = std::fs::read_to_string(target.path().join(".netrc")).unwrap();
+    assert!(content.contains("password pw-example.password"));
+}
+
+#[test]
+fn deploy_fails_when_secret_is_missing() {
+    let dotfiles = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    setup_secret_fixture(dotfiles.path(), "[dotm.secrets]\nbackend = \"command\"\ncommand = \"false\"");
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path()).unwrap();
+    let result = orch.deploy("testhost", false, false);
+    assert!(result.is_err());
+    assert!(!target.path().join(".netrc").exists());
+}
+
+fn setup_encrypted_fixture(dir: &Path) {
+    std::fs::write(
+        dir.join("dotm.toml"),
+        r#"
+[dotm]
+target = "~"
+
+[dotm.encryption]
+decrypt_command = "base64 -d"
+
+[packages.ssh]
+description = "SSH keys"
+"#,
+    )
+    .unwrap();
+
+    let pkg_dir = dir.join("packages/ssh/.ssh");
+    std::fs::create_dir_all(&pkg_dir).unwrap();
+    // "PRIVATE KEY\n" base64-encoded
+    std::fs::write(pkg_dir.join("id_ed25519.age"), "UFJJVkFURSBLRVkK\n").unwrap();
+
+    std::fs::create_dir_all(dir.join("hosts")).unwrap();
+    std::fs::write(
+        dir.join("hosts/testhost.toml"),
+        "hostname = \"testhost\"\nroles = [\"all\"]\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(dir.join("roles")).unwrap();
+    std::fs::write(dir.join("roles/all.toml"), "packages = [\"ssh\"]\n").unwrap();
+}
+
+#[test]
+fn deploy_decrypts_encrypted_files_as_private_copies() {
+    let dotfiles = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_encrypted_fixture(dotfiles.path());
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    let report = orch.deploy("testhost", false, false).unwrap();
+    assert!(report.conflicts.is_empty());
+
+    let key = target.path().join(".ssh/id_ed25519");
+    assert!(!key.is_symlink(), "encrypted files must be copied, not symlinked");
+    assert_eq!(std::fs::read_to_string(&key).unwrap(), "PRIVATE KEY\n");
+    assert_eq!(key.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+    assert!(!dotfiles.path().join(".staged/.ssh/id_ed25519").exists());
+
+    let state = DeployState::load(state_dir.path()).unwrap();
+    let entry = &state.entries()[0];
+    assert_eq!(entry.kind, dotm::scanner::EntryKind::Encrypted);
+    assert!(entry.sensitive);
+    assert!(state.load_deployed(&entry.content_hash).is_err());
+    let stored = state.load_deployed(entry.encrypted_hash.as_ref().unwrap()).unwrap();
+    assert_eq!(stored, b"UFJJVkFURSBLRVkK\n");
+    assert!(state.check_entry_status(entry).is_ok());
+}
+
+#[test]
+fn deploy_errors_on_undecryptable_file() {
+    let dotfiles = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    setup_encrypted_fixture(dotfiles.path());
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        "[dotm]\ntarget = \"~\"\n\n[packages.ssh]\ndescription = \"SSH keys\"\n",
+    )
+    .unwrap();
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path()).unwrap();
+    let result = orch.deploy("testhost", false, false);
+    assert!(result.is_err());
+    assert!(format!("{:#}", result.unwrap_err()).contains("identity"));
+}
+
+#[test]
+fn deploy_ignores_permission_override_on_encrypted_files() {
+    let dotfiles = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_encrypted_fixture(dotfiles.path());
+    let mut config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    config.push_str("\n[packages.ssh.permissions]\n\".ssh/id_ed25519\" = \"644\"\n");
+    std::fs::write(dotfiles.path().join("dotm.toml"), config).unwrap();
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    orch.deploy("testhost", false, false).unwrap();
+
+    let key = target.path().join(".ssh/id_ed25519");
+    assert_eq!(key.metadata().unwrap().permissions().mode() & 0o777, 0o600);
+    let state = DeployState::load(state_dir.path()).unwrap();
+    assert_eq!(state.entries()[0].mode, None);
+}
@@ -1,6 +1,6 @@
 use dotm::scanner::EntryKind;
 use dotm::state::{DeployEntry, DeployState};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tempfile::TempDir;
 
 #[test]
@@ -21,6 +21,9 @@ fn state_save_includes_version() {
         original_owner: None,
         original_group: None,
         original_mode: None,
+        sensitive: false,
+        encrypted_hash: None,
+        linked: false,
     });
     state.save().unwrap();
 
@@ -74,6 +77,9 @@ fn update_entry_hash_changes_hash() {
         original_owner: None,
         original_group: None,
         original_mode: None,
+        sensitive: false,
+        encrypted_hash: None,
+        linked: false,
     });
     state.update_entry_hash(0, "new_hash".to_string());
     assert_eq!(state.entries()[0].content_hash, "new_hash");
@@ -97,6 +103,9 @@ fn save_and_load_new_state() {
         original_owner: None,
         original_group: None,
         original_mode: None,
+        sensitive: false,
+        encrypted_hash: None,
+        linked: false,
     });
     state.record(DeployEntry {
         target: PathBuf::from("/home/user/.config/app.conf"),
@@ -112,6 +121,9 @@ fn save_and_load_new_state() {
         original_owner: None,
         original_group: None,
         original_mode: None,
+        sensitive: false,
+        encrypted_hash: None,
+        linked: false,
     });
     state.save().unwrap();
 
@@ -159,6 +171,9 @@ fn undeploy_removes_target_and_staged() {
         original_owner: None,
         original_group: None,
         original_mode: None,
+        sensitive: false,
+        encrypted_hash: None,
+        linked: false,
     });
     state.save().unwrap();
 
@@ -197,6 +212,9 @@ fn check_entry_status_detects_modified() {
         original_owner: None,
         original_group: None,
         original_mode: None,
+        sensitive: false,
+        encrypted_hash: None,
+        linked: false,
     };
 
     assert!(state.check_entry_status(&entry).is_ok());
@@ -225,6 +243,9 @@ fn check_entry_status_detects_missing() {
         original_owner: None,
         original_group: None,
         original_mode: None,
+        sensitive: false,
+        encrypted_hash: None,
+        linked: false,
     };
 
     assert!(state.check_entry_status(&entry).is_missing());
@@ -261,6 +282,9 @@ fn undeploy_cleans_empty_staged_directories() {
         original_owner: None,
         original_group: None,
         original_mode: None,
+        sensitive: false,
+        encrypted_hash: None,
+        linked: false,
     });
     state.save().unwrap();
 
@@ -271,3 +295,13 @@ fn undeploy_cleans_empty_staged_directories() {
         "empty staged parent should be cleaned up"
     );
 }
+
+#[test]
+fn profile_state_dir_nests_plain_names_only() {
+    let dir = dotm::state::profile_state_dir(Path::new("/state/dotm"), "work").unwrap();
+    assert_eq!(dir, PathBuf::from("/state/dotm/profiles/work"));
+
+    for name in ["", "..", "../etc", "a/b", ".hidden"] {
+        assert!(dotm::state::profile_state_dir(Path::new("/state/dotm"), name).is_err(), "{name:?}");
+    }
+}
@@ -1,4 +1,6 @@
-use dotm::template::render_template;
+use dotm::secrets::{CommandProvider, SecretStore};
+use dotm::template::{render_template, render_template_with_secrets};
+use std::sync::Arc;
 use toml::map::Map;
 use toml::Value;
 
@@ -48,3 +50,306 @@ fn render_missing_variable_errors() {
     let result = render_template(template, &vars);
     assert!(result.is_err());
 }
+
+#[test]
+fn render_secret_from_command_backend() {
+    let store = SecretStore::new(Box::new(CommandProvider::new("printf 'secret-for-%s\\n'")));
+    let result = render_template_with_secrets(
+        r#"token={{ secret(path="github/token") }}"#,
+        &Map::new(),
+        Some(Arc::new(store)),
+    )
+    .unwrap();
+    assert_eq!(result.content, "token=secret-for-github/token");
+    assert!(result.uses_secrets);
+}
+
+#[test]
+fn render_without_secret_calls_is_not_sensitive() {
+    let store = SecretStore::new(Box::new(CommandProvider::new("false")));
+    let result = render_template_with_secrets("plain", &Map::new(), Some(Arc::new(store))).unwrap();
+    assert_eq!(result.content, "plain");
+    assert!(!result.uses_secrets);
+}
+
+#[test]
+fn render_secret_without_backend_errors() {
+    let result = render_template(r#"{{ secret(path="x") }}"#, &Map::new());
+    assert!(result.is_err());
+}
+
+#[test]
+fn render_secret_command_failure_errors() {
+    let store = SecretStore::new(Box::new(CommandProvider::new("false")));
+    let result = render_template_with_secrets(
+        r#"{{ secret(path="x") }}"#,
+        &Map::new(),
+        Some(Arc::new(store)),
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn render_dotm_namespace() {
+    let facts = dotm::facts::Facts {
+        os: "linux".to_string(),
+        arch: "aarch64".to_string(),
+        distro: Some("fedora".to_string()),
+        username: "alice".to_string(),
+        cpus: 8,
+        memory: Some(16 * 1024 * 1024 * 1024),
+        ..Default::default()
+    };
+    let context = dotm::template::DotmContext {
+        facts: &facts,
+        host: "laptop",
+        roles: &["base", "desktop"],
+        package: "shell",
+        target: std::path::Path::new("/home/alice/.bashrc"),
+        target_dir: std::path::Path::new("/home/alice"),
+    };
+    let template = "{{ dotm.os }}/{{ dotm.arch }} {{ dotm.distro }} {{ dotm.username }}@{{ dotm.host }} \
+                    cpus={{ dotm.cpus }} mem={{ dotm.memory / 1073741824 }}G \
+                    roles={{ dotm.roles | join(sep=\",\") }} {{ dotm.package }} -> {{ dotm.target }}";
+    // A user var named `dotm` is shadowed by the namespace
+    let vars = vars_from_str(r#"dotm = "user""#);
+
+    let result = dotm::template::render_template_with_context(template, &vars, None, Some(&context)).unwrap();
+    assert_eq!(
+        result.content,
+        "linux/aarch64 fedora alice@laptop cpus=8 mem=16G roles=base,desktop shell -> /home/alice/.bashrc"
+    );
+}
+
+fn write(path: &std::path::Path, content: &str) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+}
+
+/// Load an engine with the templates of every package under `root/packages`.
+fn load_engine(root: &std::path::Path) -> anyhow::Result<dotm::template::TemplateEngine> {
+    let packages_dir = root.join("packages");
+    let mut templates = Vec::new();
+    if packages_dir.is_dir() {
+        for entry in std::fs::read_dir(&packages_dir).unwrap() {
+            let ignore = dotm::scanner::IgnoreRules::default();
+            templates.extend(dotm::scanner::template_files(&entry.unwrap().path(), &ignore).unwrap());
+        }
+    }
+    dotm::template::TemplateEngine::load(root, &packages_dir, &templates, None)
+}
+
+#[test]
+fn engine_resolves_partials_macros_and_inheritance() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let root = dir.path();
+    write(&root.join("templates/palette.tera"), "bg={{ colors.bg }}\n");
+    write(
+        &root.join("templates/macros/shell.tera"),
+        "{% macro alias(name, cmd) %}alias {{ name }}='{{ cmd }}'{% endmacro alias %}",
+    );
+    write(&root.join("templates/base.conf"), "# header\n{% block body %}{% endblock body %}\n");
+    write(
+        &root.join("packages/shell/.aliases.tera"),
+        "{% import \"macros/shell.tera\" as shell %}{{ shell::alias(name=\"ll\", cmd=\"ls -l\") }}\n",
+    );
+    write(&root.join("packages/shell/.colors.tera"), "fg={{ colors.fg }}\n");
+    write(
+        &root.join("packages/term/.termrc.tera"),
+        "{% extends \"base.conf\" %}{% block body %}{% include \"palette.tera\" %}{% include \"packages/shell/.colors.tera\" %}{% endblock body %}",
+    );
+
+    let mut engine = load_engine(root).unwrap();
+    let vars = vars_from_str("[colors]\nbg = \"#000000\"\nfg = \"#ffffff\"\n");
+    let rendered = engine
+        .render_file(&root.join("packages/term/.termrc.tera"), &vars, None)
+        .unwrap();
+    assert_eq!(rendered.content, "# header\nbg=#000000\nfg=#ffffff\n\n");
+
+    let rendered = engine
+        .render_file(&root.join("packages/shell/.aliases.tera"), &vars, None)
+        .unwrap();
+    assert_eq!(rendered.content, "alias ll='ls -l'\n");
+}
+
+#[test]
+fn engine_does_not_escape_html_partials() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let root = dir.path();
+    write(&root.join("templates/snippet.html"), "{{ value }}");
+    write(&root.join("packages/web/page.html.tera"), "{% include \"snippet.html\" %}");
+
+    let mut engine = load_engine(root).unwrap();
+    let vars = vars_from_str(r#"value = "<b>&</b>""#);
+    let rendered = engine
+        .render_file(&root.join("packages/web/page.html.tera"), &vars, None)
+        .unwrap();
+    assert_eq!(rendered.content, "<b>&</b>");
+}
+
+#[test]
+fn engine_load_reports_broken_template() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let root = dir.path();
+    write(&root.join("templates/broken.tera"), "{% if %}");
+
+    let err = load_engine(root).err().unwrap();
+    assert!(format!("{err:#}").contains("broken.tera"));
+}
+
+#[test]
+fn engine_load_reports_every_broken_template() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let root = dir.path();
+    write(&root.join("templates/a.tera"), "ok\n{% if %}");
+    write(&root.join("packages/shell/.bashrc.tera"), "{{ unclosed");
+
+    let err = load_engine(root).err().unwrap();
+    let errors = err.downcast::<dotm::template::TemplateErrors>().unwrap().0;
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].file, std::path::Path::new("packages/shell/.bashrc.tera"));
+    assert_eq!(errors[1].file, std::path::Path::new("templates/a.tera"));
+    assert_eq!((errors[1].line, errors[1].column), (Some(2), Some(7)));
+}
+
+#[test]
+fn engine_render_error_locates_missing_variable() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let root = dir.path();
+    write(&root.join("templates/palette.tera"), "bg={{ colors.bg }}\nfg={{ colors.fgg }}\n");
+    write(&root.join("packages/term/.termrc.tera"), "{% include \"palette.tera\" %}");
+
+    let mut engine = load_engine(root).unwrap();
+    let vars = vars_from_str("[colors]\nbg = \"#000000\"\nfg = \"#ffffff\"\n");
+    let err = engine
+        .render_file(&root.join("packages/term/.termrc.tera"), &vars, None)
+        .err()
+        .unwrap();
+    let mut err = err.downcast::<dotm::template::TemplateError>().unwrap();
+    // Reported where the variable is used, not in the template being rendered
+    assert_eq!(err.file, std::path::Path::new("templates/palette.tera"));
+    assert_eq!((err.line, err.column), (Some(2), Some(7)));
+    assert_eq!(err.missing_var.as_deref(), Some("colors.fgg"));
+
+    let mut provenance = dotm::vars::Provenance::new();
+    let role_vars = vars_from_str("[colors]\nbg = \"#000000\"\nfg = \"#eeeeee\"\n[shell]\nprompt = \"$\"\n");
+    let host_vars = vars_from_str("[colors]\nfg = \"#ffffff\"\n");
+    let merged = dotm::vars::merge_vars_tracked(&Map::new(), &role_vars, "role 'base'", &mut provenance);
+    dotm::vars::merge_vars_tracked(&merged, &host_vars, "host 'laptop'", &mut provenance);
+    err.add_candidates(&provenance);
+    assert_eq!(
+        err.candidates,
+        vec![
+            ("colors.fg".to_string(), "host 'laptop'".to_string()),
+            ("colors.bg".to_string(), "role 'base'".to_string()),
+        ]
+    );
+    assert!(err.to_string().contains("did you mean `colors.fg` (set by host 'laptop')?"));
+}
+
+#[test]
+fn engine_render_error_names_unknown_filter() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let root = dir.path();
+    write(&root.join("packages/shell/.bashrc.tera"), "{{ prompt | shout }}");
+
+    let mut engine = load_engine(root).unwrap();
+    let err = engine
+        .render_file(&root.join("packages/shell/.bashrc.tera"), &vars_from_str("prompt = \"$\""), None)
+        .err()
+        .unwrap();
+    let err = err.downcast::<dotm::template::TemplateError>().unwrap();
+    assert_eq!(err.file, std::path::Path::new("packages/shell/.bashrc.tera"));
+    assert_eq!(err.message, "unknown filter `shout`");
+}
+
+#[test]
+fn helper_command_exists() {
+    let template = r#"{{ command_exists(name="sh") }} {{ command_exists(name="dotm-no-such-command") }}"#;
+    let result = render_template(template, &Map::new()).unwrap();
+    assert_eq!(result, "true false");
+}
+
+#[test]
+fn helper_env_with_default() {
+    let template = r#"{{ env(name="DOTM_TEST_UNSET_VARIABLE", default="fallback") }}"#;
+    assert_eq!(render_template(template, &Map::new()).unwrap(), "fallback");
+
+    let template = r#"{{ env(name="PATH") }}"#;
+    assert_eq!(
+        render_template(template, &Map::new()).unwrap(),
+        std::env::var("PATH").unwrap()
+    );
+
+    let err = render_template(r#"{{ env(name="DOTM_TEST_UNSET_VARIABLE") }}"#, &Map::new()).unwrap_err();
+    assert!(format!("{err:#}").contains("DOTM_TEST_UNSET_VARIABLE"));
+}
+
+#[test]
+fn helper_path_join() {
+    let template = r#"{{ path_join(parts=[dir, "nvim", "init.lua"]) }}"#;
+    let vars = vars_from_str(r#"dir = "/home/me/.config""#);
+    assert_eq!(render_template(template, &vars).unwrap(), "/home/me/.config/nvim/init.lua");
+}
+
+#[test]
+fn helper_read_file_and_file_exists_are_package_relative() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let root = dir.path();
+    write(&root.join("packages/shell/snippets/prompt.sh"), "PS1='> '\n");
+    write(
+        &root.join("packages/shell/.bashrc.tera"),
+        "{{ read_file(path=\"snippets/prompt.sh\") }}{{ file_exists(path=\"snippets/prompt.sh\") }} {{ file_exists(path=\"missing\") }}",
+    );
+
+    let mut engine = load_engine(root).unwrap();
+    let rendered = engine
+        .render_file(&root.join("packages/shell/.bashrc.tera"), &Map::new(), None)
+        .unwrap();
+    assert_eq!(rendered.content, "PS1='> '\ntrue false");
+
+    // Outside a package, relative paths are an error
+    assert!(render_template(r#"{{ read_file(path="x") }}"#, &Map::new()).is_err());
+}
+
+#[test]
+fn helper_colour_filters() {
+    let template = r##"{{ bg | hex_to_rgb | join(sep=",") }} {{ "#abc" | hex_to_rgb | join(sep=",") }} {{ bg | lighten(amount=0.5) }} {{ bg | darken(amount=0.5) }}"##;
+    let vars = vars_from_str(r##"bg = "#ff8800""##);
+    assert_eq!(
+        render_template(template, &vars).unwrap(),
+        "255,136,0 170,187,204 #ffc480 #804400"
+    );
+
+    let err = render_template(r#"{{ "red" | hex_to_rgb }}"#, &Map::new()).unwrap_err();
+    assert!(format!("{err:#}").contains("expected a hex colour"));
+}
+
+#[test]
+fn helper_structured_output_filters() {
+    let vars = vars_from_str(
+        r#"
+[settings]
+name = "dotm"
+enabled = true
+tags = ["a", "yes"]
+
+[settings.window]
+width = 80
+"#,
+    );
+
+    assert_eq!(
+        render_template("{{ settings | to_json }}", &vars).unwrap(),
+        r#"{"enabled":true,"name":"dotm","tags":["a","yes"],"window":{"width":80}}"#
+    );
+    assert_eq!(
+        render_template("{{ settings | to_toml }}", &vars).unwrap(),
+        "enabled = true\nname = \"dotm\"\ntags = [\"a\", \"yes\"]\n\n[window]\nwidth = 80\n"
+    );
+    assert_eq!(
+        render_template("{{ settings | to_yaml }}", &vars).unwrap(),
+        "enabled: true\nname: dotm\ntags:\n  - a\n  - \"yes\"\nwindow:\n  width: 80\n"
+    );
+    assert_eq!(render_template("{{ settings.name | to_toml }}", &vars).unwrap(), "\"dotm\"");
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::copy_dir_recursive;
+use dotm::orchestrator::Orchestrator;
+use dotm::state::DeployState;
+use std::path::Path;
+use tempfile::TempDir;
+
+/// Basic fixture where the editor package (deployed after shell) fails mid-deploy
+/// because of an invalid permission override.
+fn setup_failing_fixture(dotfiles: &Path) {
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles);
+    let config = std::fs::read_to_string(dotfiles.join("dotm.toml")).unwrap();
+    let config = config.replace(
+        "suggests = [\"theme\"]",
+        "suggests = [\"theme\"]\npermissions = { \".config/nvim/init.lua\" = \"999\" }",
+    );
+    std::fs::write(dotfiles.join("dotm.toml"), config).unwrap();
+}
+
+#[test]
+fn failed_deploy_rolls_back_earlier_packages() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_failing_fixture(dotfiles.path());
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    let err = orch.deploy("testhost", false, false).unwrap_err();
+    assert!(format!("{err:#}").contains("rolled back"), "error: {err:#}");
+
+    assert!(!target.path().join(".bashrc").exists());
+    assert!(!target.path().join(".bashrc").is_symlink());
+    assert!(!target.path().join(".config").exists(), "created directories are removed");
+    assert!(!dotfiles.path().join(".staged/.bashrc").exists());
+    assert!(DeployState::load(state_dir.path()).unwrap().entries().is_empty());
+}
+
+#[test]
+fn failed_deploy_restores_overwritten_files() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    setup_failing_fixture(dotfiles.path());
+    std::fs::write(target.path().join(".bashrc"), "# my own bashrc\n").unwrap();
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    assert!(orch.deploy("testhost", false, true).is_err());
+
+    let bashrc = target.path().join(".bashrc");
+    assert!(!bashrc.is_symlink());
+    assert_eq!(std::fs::read_to_string(&bashrc).unwrap(), "# my own bashrc\n");
+}
+
+#[test]
+fn failed_redeploy_restores_previous_deployment() {
+    let target = TempDir::new().unwrap();
+    let dotfiles = TempDir::new().unwrap();
+    let state_dir = TempDir::new().unwrap();
+    copy_dir_recursive(Path::new("tests/fixtures/basic"), dotfiles.path());
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    orch.deploy("testhost", false, false).unwrap();
+    let staged_bashrc = dotfiles.path().join(".staged/.bashrc");
+    let before = std::fs::read_to_string(&staged_bashrc).unwrap();
+    let link_before = std::fs::read_link(target.path().join(".bashrc")).unwrap();
+
+    // Change the shell package, then make the editor package fail
+    std::fs::write(dotfiles.path().join("packages/shell/.bashrc"), "# changed\n").unwrap();
+    let config = std::fs::read_to_string(dotfiles.path().join("dotm.toml")).unwrap();
+    std::fs::write(
+        dotfiles.path().join("dotm.toml"),
+        config.replace(
+            "suggests = [\"theme\"]",
+            "suggests = [\"theme\"]\npermissions = { \".config/nvim/init.lua\" = \"999\" }",
+        ),
+    )
+    .unwrap();
+
+    let mut orch = Orchestrator::new(dotfiles.path(), target.path())
+        .unwrap()
+        .with_state_dir(state_dir.path());
+    assert!(orch.deploy("testhost", false, false).is_err());
+
+    assert_eq!(std::fs::read_to_string(&staged_bashrc).unwrap(), before);
+    assert_eq!(std::fs::read_link(target.path().join(".bashrc")).unwrap(), link_before);
+    assert!(target.path().join(".config/nvim/init.lua").is_symlink());
+    assert_eq!(DeployState::load(state_dir.path()).unwrap().entries().len(), 2);
+}
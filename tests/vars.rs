@@ -1,4 +1,9 @@
-use dotm::vars::merge_vars;
+use dotm::config::VarSchema;
+use dotm::vars::{
+    Provenance, entries, env_overrides, merge_vars, merge_vars_tracked, parse_override, render_entries,
+    schema_defaults, unknown_vars, validate_schema,
+};
+use std::collections::BTreeMap;
 use toml::map::Map;
 use toml::Value;
 
@@ -52,3 +57,154 @@ resolution = "3840x2160"
     assert_eq!(display.get("resolution").unwrap().as_str().unwrap(), "3840x2160");
     assert_eq!(display.get("refresh").unwrap().as_integer().unwrap(), 60);
 }
+
+#[test]
+fn merge_tracked_records_each_layer() {
+    let mut provenance = Provenance::new();
+    let role = map_from_str("[display]\nresolution = \"1920x1080\"\nrefresh = 60\n[theme]\nname = \"light\"\n");
+    let host = map_from_str("[display]\nresolution = \"3840x2160\"\n[theme]\nname = { dark = true }\n");
+    let merged = merge_vars_tracked(&Map::new(), &role, "role 'desktop'", &mut provenance);
+    let merged = merge_vars_tracked(&merged, &host, "host 'laptop'", &mut provenance);
+    assert_eq!(merged, merge_vars(&role, &host));
+
+    let layers = |path: &str| -> Vec<String> { provenance[path].iter().map(|s| s.layer.clone()).collect() };
+    assert_eq!(layers("display.resolution"), ["role 'desktop'", "host 'laptop'"]);
+    assert_eq!(layers("display.refresh"), ["role 'desktop'"]);
+    // A table replacing a scalar starts a new history
+    assert!(!provenance.contains_key("theme.name"));
+    assert_eq!(layers("theme.name.dark"), ["host 'laptop'"]);
+}
+
+#[test]
+fn entries_filter_by_key_and_render_overrides() {
+    let mut provenance = Provenance::new();
+    let role = map_from_str("[display]\nresolution = \"1920x1080\"\nrefresh = 60\n[displays]\ncount = 2\n");
+    let host = map_from_str("[display]\nresolution = \"3840x2160\"\n");
+    let merged = merge_vars_tracked(&Map::new(), &role, "role 'desktop'", &mut provenance);
+    merge_vars_tracked(&merged, &host, "host 'laptop'", &mut provenance);
+
+    let display = entries(&provenance, Some("display"));
+    let paths: Vec<&str> = display.iter().map(|e| e.path.as_str()).collect();
+    assert_eq!(paths, ["display.refresh", "display.resolution"]);
+    assert_eq!(entries(&provenance, Some("display.resolution"))[0].source, "host 'laptop'");
+    assert!(entries(&provenance, Some("disp")).is_empty());
+
+    assert_eq!(
+        render_entries(&display),
+        "display.refresh = 60              # role 'desktop'\n\
+         display.resolution = \"3840x2160\"  # host 'laptop', overrides role 'desktop' = \"1920x1080\"\n"
+    );
+}
+
+fn schema_from_str(s: &str) -> BTreeMap<String, VarSchema> {
+    toml::from_str(s).unwrap()
+}
+
+#[test]
+fn schema_defaults_nest_dotted_paths() {
+    let schema = schema_from_str(
+        r#"
+"editor.theme" = { type = "string", default = "gruvbox" }
+"editor.font_size" = { type = "integer", default = 11 }
+"editor.font" = { type = "string", required = true }
+"#,
+    );
+    assert_eq!(
+        schema_defaults(&schema),
+        map_from_str("[editor]\ntheme = \"gruvbox\"\nfont_size = 11\n")
+    );
+}
+
+#[test]
+fn validate_schema_reports_missing_mistyped_and_disallowed() {
+    let schema = schema_from_str(
+        r#"
+"editor.font" = { type = "string", required = true, description = "Font family" }
+"editor.font_size" = { type = "integer" }
+"editor.theme" = { allowed = ["light", "dark"] }
+"editor.opacity" = { type = "float" }
+"#,
+    );
+    let mut provenance = Provenance::new();
+    let role = map_from_str("[editor]\nfont_size = \"11\"\ntheme = \"solarized\"\nopacity = 1\n");
+    let vars = merge_vars_tracked(&Map::new(), &role, "role 'dev'", &mut provenance);
+
+    assert_eq!(
+        validate_schema("editor", &schema, &vars, &provenance),
+        vec![
+            "package 'editor' requires var 'editor.font' (Font family), which is not set",
+            "var 'editor.font_size' (set by role 'dev') must be an integer, not string \"11\", for package 'editor'",
+            "var 'editor.theme' (set by role 'dev') is \"solarized\", expected one of: \"light\", \"dark\", for package 'editor'",
+        ]
+    );
+}
+
+#[test]
+fn unknown_vars_flags_undeclared_siblings() {
+    let schema = schema_from_str(
+        r#"
+"editor.font_size" = { type = "integer" }
+"editor.colors" = { type = "table" }
+"#,
+    );
+    let mut provenance = Provenance::new();
+    let host = map_from_str(
+        "[editor]\nfont_sise = 12\ncolors = { bg = \"#000\" }\n[shell]\nprompt = \"$\"\n",
+    );
+    merge_vars_tracked(&Map::new(), &host, "host 'laptop'", &mut provenance);
+
+    assert_eq!(
+        unknown_vars(&[&schema], &provenance),
+        vec!["unknown var 'editor.font_sise' (set by host 'laptop'), did you mean 'editor.font_size'?"]
+    );
+}
+
+#[test]
+fn unknown_vars_flags_misspelled_top_level_keys() {
+    let schema = schema_from_str(
+        r#"
+"editor.font_size" = { type = "integer" }
+"theme" = { type = "string" }
+"#,
+    );
+    let mut provenance = Provenance::new();
+    let host = map_from_str(
+        "thme = \"dark\"\nprompt = \"$\"\n[edtor]\nfont_size = 12\n[shell]\nprompt = \"$\"\n",
+    );
+    merge_vars_tracked(&Map::new(), &host, "host 'laptop'", &mut provenance);
+
+    assert_eq!(
+        unknown_vars(&[&schema], &provenance),
+        vec![
+            "unknown var 'edtor.font_size' (set by host 'laptop'), did you mean 'editor.font_size'?",
+            "unknown var 'thme' (set by host 'laptop'), did you mean 'theme'?",
+        ]
+    );
+}
+
+#[test]
+fn parse_override_reads_toml_values_and_bare_words() {
+    assert_eq!(parse_override("editor.size=12").unwrap(), map_from_str("editor.size = 12"));
+    assert_eq!(parse_override("editor.theme=nord").unwrap(), map_from_str("editor.theme = \"nord\""));
+    assert_eq!(parse_override("fonts=[\"a\", \"b\"]").unwrap(), map_from_str("fonts = [\"a\", \"b\"]"));
+    assert_eq!(parse_override("empty=").unwrap(), map_from_str("empty = \"\""));
+    assert!(parse_override("editor.theme").is_err());
+    assert!(parse_override("editor..theme=x").is_err());
+}
+
+#[test]
+fn env_overrides_map_names_to_paths() {
+    let env = vec![
+        ("PATH".to_string(), "/bin".to_string()),
+        ("DOTM_VAR_EDITOR__FONT_SIZE".to_string(), "12".to_string()),
+        ("DOTM_VAR_DEBUG".to_string(), "true".to_string()),
+    ];
+    let overrides = env_overrides(env).unwrap();
+    assert_eq!(
+        overrides,
+        vec![
+            ("DOTM_VAR_DEBUG".to_string(), map_from_str("debug = true")),
+            ("DOTM_VAR_EDITOR__FONT_SIZE".to_string(), map_from_str("editor.font_size = 12")),
+        ]
+    );
+}